@@ -0,0 +1,103 @@
+use crate::metadata::PhotoMetadata;
+use chrono::{DateTime, Local, TimeZone};
+use std::fs::{self, FileTimes, Metadata};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Extended attribute holding the name a file had before it was first labeled
+#[cfg(unix)]
+const ORIGINAL_NAME_ATTRIBUTE: &str = "user.image-labeler.original-name";
+
+/// Gives `to` the access and modification times and the extended attributes (e.g. macOS Finder
+/// tags) of `from`, which a plain copy doesn't carry over everywhere.
+pub fn copy_attributes(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    set_times(to, &metadata)?;
+    copy_xattrs(from, to)
+}
+
+/// Puts back the access and modification times a file had before it was renamed, for network
+/// filesystems that reset them.
+pub fn restore_times(path: &Path, before: &Metadata) -> std::io::Result<()> {
+    if fs::metadata(path)?.modified()? == before.modified()? {
+        return Ok(());
+    }
+    set_times(path, before)
+}
+
+pub fn set_modified(path: &Path, modified: SystemTime) -> std::io::Result<()> {
+    fs::File::options().write(true).open(path)?.set_times(FileTimes::new().set_modified(modified))
+}
+
+/// Remembers `original` as the name `path` had before it was labeled, unless an earlier run did so
+/// already. Filesystems without extended attributes are left alone.
+pub fn record_original_name(path: &Path, original: &str) -> std::io::Result<()> {
+    if original_name(path).is_some() {
+        return Ok(());
+    }
+    set_original_name(path, original)
+}
+
+/// The name `path` had before it was first labeled, if that was recorded.
+#[cfg(unix)]
+pub fn original_name(path: &Path) -> Option<String> {
+    let value = xattr::get(path, ORIGINAL_NAME_ATTRIBUTE).ok()??;
+    String::from_utf8(value).ok()
+}
+
+#[cfg(not(unix))]
+pub fn original_name(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn set_original_name(path: &Path, original: &str) -> std::io::Result<()> {
+    match xattr::set(path, ORIGINAL_NAME_ATTRIBUTE, original.as_bytes()) {
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn set_original_name(_path: &Path, _original: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// When the photo was taken. Times from a camera that doesn't record its UTC offset are taken to
+/// be in this machine's timezone.
+pub fn capture_time(metadata: &PhotoMetadata) -> Option<SystemTime> {
+    let timestamp = metadata.timestamp?;
+    let seconds = if metadata.timestamp_is_utc {
+        timestamp
+    } else {
+        let wall_clock = DateTime::from_timestamp(timestamp as i64, 0)?.naive_utc();
+        Local.from_local_datetime(&wall_clock).earliest()?.timestamp() as f64 + timestamp.fract()
+    };
+    (seconds >= 0.0).then(|| UNIX_EPOCH + Duration::from_secs_f64(seconds))
+}
+
+fn set_times(path: &Path, metadata: &Metadata) -> std::io::Result<()> {
+    let times = FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?);
+    fs::File::options().write(true).open(path)?.set_times(times)
+}
+
+/// Copies every extended attribute of `from` onto `to`.
+#[cfg(unix)]
+pub fn copy_xattrs(from: &Path, to: &Path) -> std::io::Result<()> {
+    for name in xattr::list(from)? {
+        let Some(value) = xattr::get(from, &name)? else {
+            continue;
+        };
+        match xattr::set(to, &name, &value) {
+            // The destination's filesystem may not support them at all
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Ok(()),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn copy_xattrs(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use crate::geocoder::GeocodeResponse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// The settings that shape a geocoder's answers. Responses looked up with different settings are
+/// cached in files of their own, so a city-level or French name isn't handed out to a run that asked
+/// for streets in English.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheScope {
+    /// The provider, as named on the command line
+    pub provider: String,
+    /// Address of a self-hosted instance
+    pub url: Option<String>,
+    pub language: String,
+    pub zoom: Option<u8>,
+}
+
+impl CacheScope {
+    // The settings of a run without any options, whose responses keep the original cache file
+    fn is_default(&self) -> bool {
+        self.provider == "maps-co" && self.url.is_none() && self.language == "en" && self.zoom.is_none()
+    }
+
+    fn file_name(&self) -> String {
+        if self.is_default() {
+            return CACHE_FILE.to_string();
+        }
+        let readable = |value: &str| value.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect::<String>();
+        let hash = blake3::hash(format!("{:?}", self).as_bytes()).to_hex();
+        format!("geocode-cache-{}-{}-{}.json", readable(&self.provider), readable(&self.language), &hash[..8])
+    }
+}
+
+const CACHE_FILE: &str = "geocode-cache.json";
+
+/// On-disk cache of geocoder responses keyed by coordinates rounded to a fixed number of decimals,
+/// so repeated runs and nearby photos don't hit the network again.
+#[derive(Debug)]
+pub struct GeocodeCache {
+    path: Option<PathBuf>,
+    precision: usize,
+    entries: HashMap<String, GeocodeResponse>,
+    dirty: bool,
+}
+
+impl GeocodeCache {
+    /// The cache of a run with the default provider and settings, which the `cache` commands
+    /// work on.
+    pub fn path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("image-labeler").join(CACHE_FILE))
+    }
+
+    pub fn path_for(scope: &CacheScope) -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("image-labeler").join(scope.file_name()))
+    }
+
+    /// Every cache file, the default one first.
+    pub fn paths() -> Vec<PathBuf> {
+        let Some(default) = GeocodeCache::path() else {
+            return Vec::new();
+        };
+        let mut scoped = default.parent()
+            .and_then(|dir| fs::read_dir(dir).ok())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("geocode-cache-") && name.ends_with(".json")))
+            .collect::<Vec<_>>();
+        scoped.sort();
+        std::iter::once(default).filter(|path| path.exists()).chain(scoped).collect()
+    }
+
+    /// The default cache.
+    pub fn load(precision: usize) -> GeocodeCache {
+        GeocodeCache::load_from(GeocodeCache::path(), precision)
+    }
+
+    /// The cache of responses looked up with the settings in `scope`.
+    pub fn load_scoped(scope: &CacheScope, precision: usize) -> GeocodeCache {
+        GeocodeCache::load_from(GeocodeCache::path_for(scope), precision)
+    }
+
+    fn load_from(path: Option<PathBuf>, precision: usize) -> GeocodeCache {
+        let entries = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(entries) => Some(entries),
+                Err(e) => {
+                    warn!("Warning: Ignoring unreadable geocode cache: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        GeocodeCache { path, precision, entries, dirty: false }
+    }
+
+    /// A cache that is never read from or written to disk.
+    pub fn disabled() -> GeocodeCache {
+        GeocodeCache { path: None, precision: 0, entries: HashMap::new(), dirty: false }
+    }
+
+    fn key(&self, lat: f64, lon: f64) -> String {
+        format!("{:.*},{:.*}", self.precision, lat, self.precision, lon)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, lat: f64, lon: f64) -> Option<&GeocodeResponse> {
+        self.entries.get(&self.key(lat, lon))
+    }
+
+    pub fn insert(&mut self, lat: f64, lon: f64, response: GeocodeResponse) {
+        if self.path.is_none() {
+            return;
+        }
+        let key = self.key(lat, lon);
+        self.entries.insert(key, response);
+        self.dirty = true;
+    }
+
+    /// Writes every location to `path` as JSON keyed by "lat,lon", the format of the cache itself,
+    /// so it can be imported on another machine or replayed with `--fixture`. Keys are sorted to
+    /// keep exports of the same cache identical. Returns how many locations were written.
+    pub fn export(&self, path: &Path) -> std::io::Result<usize> {
+        let entries = self.entries.iter().collect::<BTreeMap<_, _>>();
+        fs::write(path, serde_json::to_string_pretty(&entries)?)?;
+        Ok(entries.len())
+    }
+
+    /// Adds the locations of a file written by `export`, keeping the ones already cached unless
+    /// `overwrite` is set. Returns how many were added or replaced.
+    pub fn import(&mut self, path: &Path, overwrite: bool) -> std::io::Result<usize> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+        let entries: HashMap<String, GeocodeResponse> = serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?;
+        if let Some(key) = entries.keys().find(|key| !is_valid_key(key)) {
+            return Err(invalid(format!("\"{}\" isn't a \"lat,lon\" position", key)));
+        }
+
+        let mut imported = 0;
+        for (key, response) in entries {
+            if overwrite || !self.entries.contains_key(&key) {
+                self.entries.insert(key, response);
+                imported += 1;
+            }
+        }
+        self.dirty |= imported > 0;
+        Ok(imported)
+    }
+
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&self.entries)?)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let Some((lat, lon)) = key.split_once(',') else {
+        return false;
+    };
+    let (Ok(lat), Ok(lon)) = (lat.trim().parse::<f64>(), lon.trim().parse::<f64>()) else {
+        return false;
+    };
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+/// Deletes the cache files of every scope. Returns how many there were.
+pub fn clear() -> std::io::Result<usize> {
+    let mut cleared = 0;
+    for path in GeocodeCache::paths() {
+        match fs::remove_file(path) {
+            Ok(()) => cleared += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(cleared)
+}
@@ -0,0 +1,57 @@
+use crate::datetime::unix_time;
+
+// Names per language, capitalized so they read the same as English ones at the start of a folder name
+const MONTHS: &[(&str, [&str; 12])] = &[
+    ("en", ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"]),
+    ("nl", ["Januari", "Februari", "Maart", "April", "Mei", "Juni", "Juli", "Augustus", "September", "Oktober", "November", "December"]),
+    ("de", ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"]),
+    ("fr", ["Janvier", "Février", "Mars", "Avril", "Mai", "Juin", "Juillet", "Août", "Septembre", "Octobre", "Novembre", "Décembre"]),
+    ("es", ["Enero", "Febrero", "Marzo", "Abril", "Mayo", "Junio", "Julio", "Agosto", "Septiembre", "Octubre", "Noviembre", "Diciembre"]),
+    ("it", ["Gennaio", "Febbraio", "Marzo", "Aprile", "Maggio", "Giugno", "Luglio", "Agosto", "Settembre", "Ottobre", "Novembre", "Dicembre"]),
+    ("pt", ["Janeiro", "Fevereiro", "Março", "Abril", "Maio", "Junho", "Julho", "Agosto", "Setembro", "Outubro", "Novembro", "Dezembro"]),
+    ("sv", ["Januari", "Februari", "Mars", "April", "Maj", "Juni", "Juli", "Augusti", "September", "Oktober", "November", "December"]),
+    ("da", ["Januar", "Februar", "Marts", "April", "Maj", "Juni", "Juli", "August", "September", "Oktober", "November", "December"]),
+    ("nb", ["Januar", "Februar", "Mars", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Desember"]),
+    ("fi", ["Tammikuu", "Helmikuu", "Maaliskuu", "Huhtikuu", "Toukokuu", "Kesäkuu", "Heinäkuu", "Elokuu", "Syyskuu", "Lokakuu", "Marraskuu", "Joulukuu"]),
+    ("pl", ["Styczeń", "Luty", "Marzec", "Kwiecień", "Maj", "Czerwiec", "Lipiec", "Sierpień", "Wrzesień", "Październik", "Listopad", "Grudzień"]),
+];
+
+// Starting on Monday
+const WEEKDAYS: &[(&str, [&str; 7])] = &[
+    ("en", ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"]),
+    ("nl", ["Maandag", "Dinsdag", "Woensdag", "Donderdag", "Vrijdag", "Zaterdag", "Zondag"]),
+    ("de", ["Montag", "Dienstag", "Mittwoch", "Donnerstag", "Freitag", "Samstag", "Sonntag"]),
+    ("fr", ["Lundi", "Mardi", "Mercredi", "Jeudi", "Vendredi", "Samedi", "Dimanche"]),
+    ("es", ["Lunes", "Martes", "Miércoles", "Jueves", "Viernes", "Sábado", "Domingo"]),
+    ("it", ["Lunedì", "Martedì", "Mercoledì", "Giovedì", "Venerdì", "Sabato", "Domenica"]),
+    ("pt", ["Segunda-feira", "Terça-feira", "Quarta-feira", "Quinta-feira", "Sexta-feira", "Sábado", "Domingo"]),
+    ("sv", ["Måndag", "Tisdag", "Onsdag", "Torsdag", "Fredag", "Lördag", "Söndag"]),
+    ("da", ["Mandag", "Tirsdag", "Onsdag", "Torsdag", "Fredag", "Lørdag", "Søndag"]),
+    ("nb", ["Mandag", "Tirsdag", "Onsdag", "Torsdag", "Fredag", "Lørdag", "Søndag"]),
+    ("fi", ["Maanantai", "Tiistai", "Keskiviikko", "Torstai", "Perjantai", "Lauantai", "Sunnuntai"]),
+    ("pl", ["Poniedziałek", "Wtorek", "Środa", "Czwartek", "Piątek", "Sobota", "Niedziela"]),
+];
+
+// The table for a language code such as "de" or "pt-BR", English for languages without one
+fn names_for<const N: usize>(table: &'static [(&str, [&'static str; N])], language: &str) -> &'static [&'static str; N] {
+    let primary = language.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+    // Norwegian is often given without saying Bokmål
+    let primary = if primary == "no" || primary == "nn" { "nb".to_string() } else { primary };
+    table.iter()
+        .find(|(code, _)| *code == primary)
+        .map_or(&table[0].1, |(_, names)| names)
+}
+
+/// The name of a month from 1 to 12 in `language`, e.g. "Oktober" for 10 in "de".
+pub fn month_name(month: u32, language: &str) -> Option<&'static str> {
+    names_for(MONTHS, language).get(month.checked_sub(1)? as usize).copied()
+}
+
+/// The name of the day of the week a yyyyMMdd date fell on in `language`, e.g. "Dienstag" for
+/// "20231024" in "de".
+pub fn weekday_name(date: &str, language: &str) -> Option<&'static str> {
+    let days = unix_time(date, "000000")?.div_euclid(86_400);
+    // 1 January 1970 was a Thursday
+    let weekday = (days + 3).rem_euclid(7) as usize;
+    Some(names_for(WEEKDAYS, language)[weekday])
+}
@@ -0,0 +1,81 @@
+use crate::geocoder::GeocodeResponse;
+use crate::paths;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CHECKPOINT_FILE_NAME: &str = ".image-labeler-checkpoint.json";
+
+// Resolved locations are written to disk in batches rather than after every request
+const SAVE_INTERVAL: usize = 20;
+
+/// Locations resolved so far by a run, kept in the processed directory until the run finishes so
+/// an interrupted run can be resumed with `--resume` without geocoding the same photos again.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    entries: HashMap<PathBuf, GeocodeResponse>,
+    unsaved: usize,
+}
+
+impl Checkpoint {
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(CHECKPOINT_FILE_NAME).is_file()
+    }
+
+    /// Picks up the checkpoint left behind by an interrupted run, or starts a new one if there
+    /// is none.
+    pub fn load(dir: &Path) -> std::io::Result<Checkpoint> {
+        let mut checkpoint = Checkpoint::start(dir)?;
+        match fs::read_to_string(&checkpoint.path) {
+            Ok(contents) => checkpoint.entries = serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(checkpoint)
+    }
+
+    /// An empty checkpoint. Any existing checkpoint file is replaced once something is recorded.
+    pub fn start(dir: &Path) -> std::io::Result<Checkpoint> {
+        let path = paths::canonicalize(dir)?.join(CHECKPOINT_FILE_NAME);
+        Ok(Checkpoint { path, entries: HashMap::new(), unsaved: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&GeocodeResponse> {
+        self.entries.get(&paths::canonicalize(path).ok()?)
+    }
+
+    pub fn record(&mut self, path: &Path, response: GeocodeResponse) -> std::io::Result<()> {
+        self.entries.insert(paths::canonicalize(path)?, response);
+        self.unsaved += 1;
+        if self.unsaved >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> std::io::Result<()> {
+        if self.unsaved == 0 {
+            return Ok(());
+        }
+        fs::write(&self.path, serde_json::to_string(&self.entries)?)?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// Removes the checkpoint once the run it belongs to has completed.
+    pub fn finish(self) -> std::io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::journal::Journal;
+use crate::output::read_manifest;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+/// The SHA-256 digest of the file's contents as lowercase hex.
+pub fn sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Checks the files recorded with a checksum against their contents, as listed in a manifest
+/// written with `--checksums` or, when `path` is a directory, in its journal. Returns how many
+/// files are missing or changed.
+pub fn check(path: &Path) -> std::io::Result<usize> {
+    let expected: Vec<(PathBuf, String)> = if path.is_dir() {
+        Journal::load(path)?.checksums()
+    } else {
+        read_manifest(path)?.into_iter()
+            .filter_map(|record| Some((record.new_path.unwrap_or(record.path), record.sha256?)))
+            .collect()
+    };
+
+    let mut problems = 0;
+    for (file, checksum) in &expected {
+        match sha256(file) {
+            Ok(actual) if actual == *checksum => {}
+            Ok(_) => {
+                error!("  Error: {:?} has changed since it was recorded.", file);
+                problems += 1;
+            }
+            Err(e) => {
+                error!("  Error: {:?} can't be read: {}", file, e);
+                problems += 1;
+            }
+        }
+    }
+
+    if expected.is_empty() {
+        info!("No checksums were recorded, run with --checksums to record them.");
+    } else {
+        info!("{} files checked, {} missing or changed", expected.len(), problems);
+    }
+    Ok(problems)
+}
@@ -0,0 +1,218 @@
+use crate::filename::TargetFs;
+use crate::geocoder::Provider;
+use crate::geofence::Geofence;
+use crate::label::{Granularity, LocationField, Transliteration};
+use crate::plan::Transfer;
+use crate::template::GroupBy;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings read from `config.toml` in the user's configuration directory (or the file passed
+/// with `--config`). Command line options and environment variables take precedence over
+/// anything set here.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Key for whichever provider is selected, used when `api_keys` has no entry for it
+    #[serde(serialize_with = "hide_key")]
+    pub api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "ApiKeys::is_empty")]
+    pub api_keys: ApiKeys,
+    pub provider: Option<Provider>,
+    pub language: Option<String>,
+    /// Address of a self-hosted Nominatim instance
+    pub geocoder_url: Option<String>,
+    /// Spelling of accented and non-Latin place names in filenames
+    pub transliterate: Option<Transliteration>,
+    /// Maximum geocoding requests per second, or 0 for no limit
+    pub rate_limit: Option<f64>,
+    /// Requests that may go out at once before the rate limit kicks in
+    pub burst: Option<u32>,
+    /// Geocoding requests the provider's plan allows a day, over every run
+    pub daily_quota: Option<usize>,
+    /// Proxy for geocoding requests, instead of the one in HTTPS_PROXY
+    pub proxy: Option<String>,
+    /// Seconds a geocoding request may take
+    pub timeout: Option<u64>,
+    /// PEM file with extra certificate authorities to trust
+    pub ca_bundle: Option<PathBuf>,
+    pub template: Option<String>,
+    /// Layout of the destination tree when organizing into `--output-dir`
+    pub folder_template: Option<String>,
+    /// Filesystem the new names have to be valid on
+    pub target_fs: Option<TargetFs>,
+    pub offline_dataset: Option<PathBuf>,
+    /// Files and directories to skip while scanning, instead of the built-in list of hidden,
+    /// system and partially downloaded files
+    pub ignore: Option<Vec<String>>,
+    /// Files geocoded and renamed at a time
+    pub chunk_size: Option<usize>,
+    /// Named places that take precedence over the geocoder, as `[[places]]` tables
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub places: Vec<Geofence>,
+    /// Named sets of settings selected with `--profile`, as `[profile.NAME]` tables
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+/// Settings for one way of running, e.g. `[profile.travel]`, selected with `--profile travel`.
+/// They take precedence over the rest of the config file, and the command line over them.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub provider: Option<Provider>,
+    pub language: Option<String>,
+    pub template: Option<String>,
+    /// How specific `{location}` is, e.g. "region"
+    pub granularity: Option<Granularity>,
+    /// Address components that make up `{location}`, in order
+    pub location_fields: Option<Vec<LocationField>>,
+    pub transliterate: Option<Transliteration>,
+    pub target_fs: Option<TargetFs>,
+    /// Directory to organize files into instead of renaming them in place
+    pub output_dir: Option<PathBuf>,
+    /// Whether to copy, move or hard link files into `output_dir`
+    pub organize: Option<Transfer>,
+    pub folder_template: Option<String>,
+    pub group_by: Option<GroupBy>,
+}
+
+/// Per-provider API keys, e.g. `[api_keys]` with `opencage = "..."`.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeys {
+    #[serde(serialize_with = "hide_key")]
+    pub maps_co: Option<String>,
+    #[serde(serialize_with = "hide_key")]
+    pub opencage: Option<String>,
+    #[serde(serialize_with = "hide_key")]
+    pub mapbox: Option<String>,
+    #[serde(serialize_with = "hide_key")]
+    pub google: Option<String>,
+    /// For {w3w}, which isn't tied to the geocoding provider
+    #[serde(serialize_with = "hide_key")]
+    pub what3words: Option<String>,
+}
+
+// Keys are only shown as being set, so `config show` output can be shared safely
+fn hide_key<S: Serializer>(key: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    key.as_ref().map(|_| "********").serialize(serializer)
+}
+
+/// Written by `config init` as a starting point, with every setting commented out.
+const STARTER_CONFIG: &str = r#"# Settings for image-labeler. Command line options and environment variables take precedence.
+
+# Key for the selected provider, unless overridden in [api_keys]
+# api_key = "..."
+# provider = "maps-co"          # maps-co, nominatim, opencage, mapbox, google, offline or fixture
+# geocoder_url = "http://localhost:8080"  # self-hosted Nominatim, no API key needed
+# language = "en"
+# transliterate = "ascii"       # umlauts (München → Muenchen) or ascii (München → Munchen)
+# rate_limit = 1.0              # geocoding requests per second, 0 for no limit
+# burst = 1                     # requests that may go out at once
+# daily_quota = 2500            # requests a day the provider's plan allows
+# proxy = "http://proxy.example.com:3128"  # defaults to HTTPS_PROXY
+# timeout = 30                  # seconds a geocoding request may take
+# ca_bundle = "/path/to/corporate-ca.pem"
+# template = "{date}_{seq}_{country_code}, {location}"
+# folder_template = "{year}/{month} - {month_name}/{city}"
+# target_fs = "posix"           # posix, windows or onedrive
+# offline_dataset = "/path/to/cities1000.txt"
+# ignore = [".*", "Thumbs.db", "*.partial"]  # replaces the built-in list of hidden and system files
+# chunk_size = 1000             # files geocoded and renamed at a time, the folder is still scanned whole
+
+# [api_keys]
+# opencage = "..."
+# mapbox = "..."
+# google = "..."
+# what3words = "..."             # for {w3w} in templates
+
+# Settings for one way of running, selected with --profile travel
+# [profile.travel]
+# template = "{date}_{seq} {event}"
+# granularity = "region"       # road, suburb, city, region or country
+# provider = "mapbox"
+# output_dir = "/path/to/Trips"
+# organize = "move"             # copy, move or link
+# folder_template = "{year}/{event}"
+
+# Photos taken inside one of these places are labeled with its name without geocoding
+# [[places]]
+# name = "Home"
+# lat = 52.0907
+# lon = 5.1214
+# radius = 150                  # meters
+"#;
+
+impl ApiKeys {
+    pub fn is_empty(&self) -> bool {
+        self.maps_co.is_none() && self.opencage.is_none() && self.mapbox.is_none() && self.google.is_none()
+    }
+
+    pub fn get(&self, provider: Provider) -> Option<&str> {
+        match provider {
+            Provider::MapsCo => self.maps_co.as_deref(),
+            Provider::Opencage => self.opencage.as_deref(),
+            Provider::Mapbox => self.mapbox.as_deref(),
+            Provider::Google => self.google.as_deref(),
+            Provider::Nominatim | Provider::Offline | Provider::Fixture => None,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("image-labeler").join("config.toml"))
+    }
+
+    /// Writes a starter config file to `path`, or the default location when no path is given, and
+    /// returns where it went. Refuses to replace an existing file unless `force` is set.
+    pub fn init(path: Option<&Path>, force: bool) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+        let path = path.map(Path::to_path_buf)
+            .or_else(Config::path)
+            .ok_or("no configuration directory found, pass --config")?;
+        if path.exists() && !force {
+            return Err(format!("{} already exists, pass --force to replace it", path.display()).into());
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, STARTER_CONFIG)?;
+        Ok(path)
+    }
+
+    /// The profile called `name`, or an error listing the profiles there are.
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profile.get(name).ok_or_else(|| match self.profile.keys().map(String::as_str).collect::<Vec<_>>() {
+            names if names.is_empty() => format!("no profile \"{}\", the config file has no [profile.NAME] tables", name),
+            names => format!("no profile \"{}\", expected one of: {}", name, names.join(", ")),
+        })
+    }
+
+    /// Loads the given config file, or the default one if no path is given. Only the default
+    /// file is allowed to be missing.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Config::path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let config: Config = toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+                for place in &config.places {
+                    place.validate().map_err(|e| format!("{}: {}", path.display(), e))?;
+                }
+                Ok(config)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta};
+
+// Formats seconds since the Unix epoch as (yyyyMMdd, HHmmss) in UTC
+pub fn format_unix_time(seconds: u64) -> (String, String) {
+    let instant = i64::try_from(seconds).ok().and_then(|seconds| DateTime::from_timestamp(seconds, 0)).unwrap_or_default();
+    format_date_time(instant.naive_utc())
+}
+
+/// Formats a yyyyMMdd date and HHmmss time as ISO 8601, e.g. "2023-10-24T12:00:00", or only the
+/// date when the time isn't known.
+pub fn iso_8601(date: &str, time: Option<&str>) -> String {
+    let date = match (date.get(..4), date.get(4..6), date.get(6..8)) {
+        (Some(year), Some(month), Some(day)) => format!("{}-{}-{}", year, month, day),
+        _ => date.to_string(),
+    };
+    match time.and_then(|time| Some((time.get(..2)?, time.get(2..4)?, time.get(4..6)?))) {
+        Some((hours, minutes, seconds)) => format!("{}T{}:{}:{}", date, hours, minutes, seconds),
+        None => date,
+    }
+}
+
+/// Seconds since the Unix epoch for a yyyyMMdd date and HHmmss time, read as UTC.
+pub fn unix_time(date: &str, time: &str) -> Option<i64> {
+    Some(date_time(date, time)?.and_utc().timestamp())
+}
+
+/// Moves a yyyyMMdd date and HHmmss time by `seconds`, carrying over into the next or previous day.
+pub fn shift_date_time(date: &str, time: &str, seconds: i64) -> Option<(String, String)> {
+    let shifted = date_time(date, time)?.checked_add_signed(TimeDelta::try_seconds(seconds)?)?;
+    Some(format_date_time(shifted))
+}
+
+fn date_time(date: &str, time: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(&format!("{}{}", date.get(..8)?, time.get(..6)?), "%Y%m%d%H%M%S").ok()
+}
+
+fn format_date_time(date_time: NaiveDateTime) -> (String, String) {
+    (date_time.format("%Y%m%d").to_string(), date_time.format("%H%M%S").to_string())
+}
+
+/// Parses a clock offset such as "+02:00" or "-00:01:30", as hours, minutes and optionally
+/// seconds, into seconds.
+pub fn parse_clock_offset(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (sign, rest) = match value.split_at_checked(1)? {
+        ("-", rest) => (-1, rest),
+        ("+", rest) => (1, rest),
+        _ => (1, value),
+    };
+    let parts = rest.split(':').map(|part| part.parse::<i64>().ok()).collect::<Option<Vec<_>>>()?;
+    let seconds = match parts[..] {
+        [hours, minutes] if minutes < 60 => hours * 3600 + minutes * 60,
+        [hours, minutes, seconds] if minutes < 60 && seconds < 60 => hours * 3600 + minutes * 60 + seconds,
+        _ => return None,
+    };
+    Some(sign * seconds)
+}
+
+/// Parses an ISO 8601 timestamp such as "2023-10-24T12:00:00Z" or "2023-10-24T14:00:00.5+02:00"
+/// into seconds since the Unix epoch. Timestamps without an offset are taken to be UTC.
+pub fn parse_iso8601(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let instant = DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        .map(|instant| instant.to_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").map(|instant| instant.and_utc()))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").map(|instant| instant.and_utc()))
+        .ok()?;
+    Some(instant.timestamp() as f64 + instant.timestamp_subsec_nanos() as f64 / 1e9)
+}
+
+/// Parses a UTC offset such as "+02:00" or "-0530" into seconds.
+pub fn parse_utc_offset(value: &str) -> Option<i64> {
+    let (sign, digits) = match value.trim().split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let digits = digits.replace(':', "");
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours = digits[..2].parse::<i64>().ok()?;
+    let minutes = digits[2..].parse::<i64>().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Finds a date, and the time if it follows, in a file name such as "IMG_20231024_120000",
+/// "PXL_20231024_120000123" or "Screenshot 2023-10-24 at 12.00.00". Returns (yyyyMMdd, HHmmss).
+pub fn parse_filename_date(name: &str) -> Option<(String, Option<String>)> {
+    let chars = name.chars().collect::<Vec<_>>();
+
+    (0..chars.len())
+        // A date can't start in the middle of a longer number
+        .filter(|&start| start == 0 || !chars[start - 1].is_ascii_digit())
+        .find_map(|start| {
+            let mut position = start;
+            let year = take_digits(&chars, &mut position, 4)?;
+            if !year.starts_with("19") && !year.starts_with("20") {
+                return None;
+            }
+            let separator = chars.get(position).copied().filter(|c| matches!(c, '-' | '_' | '.'));
+            position += separator.is_some() as usize;
+            let month = take_digits(&chars, &mut position, 2)?;
+            if separator.is_some() && chars.get(position) != separator.as_ref() {
+                return None;
+            }
+            position += separator.is_some() as usize;
+            let day = take_digits(&chars, &mut position, 2)?;
+
+            NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)?;
+            let date = format!("{}{}{}", year, month, day);
+
+            // Only a time may follow an unseparated date directly, e.g. "20231024120000"
+            if chars.get(position).is_some_and(char::is_ascii_digit) {
+                let time = parse_filename_time(&chars, position).filter(|_| separator.is_none())?;
+                return Some((date, Some(time)));
+            }
+
+            let time = match chars[position..].iter().collect::<String>() {
+                rest if rest.starts_with(" at ") => parse_filename_time(&chars, position + 4),
+                rest if rest.starts_with([' ', '_', '-', 'T']) => parse_filename_time(&chars, position + 1),
+                _ => None,
+            };
+            Some((date, time))
+        })
+}
+
+// Reads HHmmss, optionally separated by ":", "." or "-"
+fn parse_filename_time(chars: &[char], mut position: usize) -> Option<String> {
+    let mut time = String::new();
+    for index in 0..3 {
+        if index > 0 && chars.get(position).is_some_and(|c| matches!(c, ':' | '.' | '-')) {
+            position += 1;
+        }
+        time.push_str(&take_digits(chars, &mut position, 2)?);
+    }
+
+    let hours = time[..2].parse::<u32>().ok()?;
+    let minutes = time[2..4].parse::<u32>().ok()?;
+    let seconds = time[4..].parse::<u32>().ok()?;
+    (hours < 24 && minutes < 60 && seconds < 60).then_some(time)
+}
+
+fn take_digits(chars: &[char], position: &mut usize, count: usize) -> Option<String> {
+    let digits = chars.get(*position..*position + count)?;
+    if !digits.iter().all(char::is_ascii_digit) {
+        return None;
+    }
+    *position += count;
+    Some(digits.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str, time: Option<&str>) -> Option<(String, Option<String>)> {
+        Some((date.to_string(), time.map(str::to_string)))
+    }
+
+    #[test]
+    fn dates_are_found_in_camera_and_phone_names() {
+        assert_eq!(parse_filename_date("IMG_20231024_120000"), date("20231024", Some("120000")));
+        assert_eq!(parse_filename_date("PXL_20231024_120000123"), date("20231024", Some("120000")));
+        assert_eq!(parse_filename_date("VID-20231024-WA0001"), date("20231024", None));
+        assert_eq!(parse_filename_date("Screenshot 2023-10-24 at 12.00.00"), date("20231024", Some("120000")));
+        assert_eq!(parse_filename_date("2023-10-24T12:00:00"), date("20231024", Some("120000")));
+        assert_eq!(parse_filename_date("20231024120000"), date("20231024", Some("120000")));
+    }
+
+    #[test]
+    fn numbers_that_arent_dates_are_ignored() {
+        assert_eq!(parse_filename_date("IMG_1234"), None);
+        assert_eq!(parse_filename_date("IMG_20231324"), None);
+        assert_eq!(parse_filename_date("IMG_20230230"), None);
+        assert_eq!(parse_filename_date("123420231024"), None);
+        assert_eq!(parse_filename_date("2023-10_24"), None);
+        assert_eq!(parse_filename_date("IMG_20231024_250000"), date("20231024", None));
+    }
+
+    #[test]
+    fn clock_offsets_are_read_as_seconds() {
+        assert_eq!(parse_clock_offset("+02:00"), Some(7200));
+        assert_eq!(parse_clock_offset("-00:01:30"), Some(-90));
+        assert_eq!(parse_clock_offset("1:30"), Some(5400));
+        assert_eq!(parse_clock_offset("+02:60"), None);
+        assert_eq!(parse_clock_offset("2"), None);
+        assert_eq!(parse_clock_offset("+ab:00"), None);
+    }
+
+    #[test]
+    fn shifted_times_carry_over_into_other_days() {
+        assert_eq!(shift_date_time("20231024", "120000", -90), Some(("20231024".to_string(), "115830".to_string())));
+        assert_eq!(shift_date_time("20231231", "233000", 3600), Some(("20240101".to_string(), "003000".to_string())));
+        assert_eq!(shift_date_time("20240301", "001000", -1200), Some(("20240229".to_string(), "235000".to_string())));
+        assert_eq!(shift_date_time("20231024", "12", 60), None);
+    }
+
+    #[test]
+    fn timestamps_are_read_as_utc() {
+        assert_eq!(unix_time("20231024", "120000"), Some(1_698_148_800));
+        assert_eq!(format_unix_time(1_698_148_800), ("20231024".to_string(), "120000".to_string()));
+        assert_eq!(parse_iso8601("2023-10-24T12:00:00Z"), Some(1_698_148_800.0));
+        assert_eq!(parse_iso8601("2023-10-24T14:00:00.5+02:00"), Some(1_698_148_800.5));
+        assert_eq!(parse_iso8601("2023-10-24T12:00:00"), Some(1_698_148_800.0));
+        assert_eq!(parse_iso8601("24/10/2023"), None);
+    }
+}
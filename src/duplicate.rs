@@ -0,0 +1,108 @@
+use crate::trash;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What to do with a file whose content is identical to one seen before it.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// Leave the duplicate where it is, without a new name
+    Skip,
+    /// Replace the duplicate with a hard link to the original to free up its space
+    Link,
+    /// Move the duplicate into the directory given by --duplicates-dir
+    MoveTo,
+}
+
+// A file seen so far, hashed only once another file turns up with the same size
+#[derive(Debug)]
+struct Candidate {
+    path: PathBuf,
+    hash: Option<blake3::Hash>,
+}
+
+/// Finds files with identical content. Files are compared by size first so only those that
+/// could be duplicates get hashed.
+#[derive(Debug, Default)]
+pub struct DuplicateFinder {
+    by_size: HashMap<u64, Vec<Candidate>>,
+}
+
+impl DuplicateFinder {
+    pub fn new() -> DuplicateFinder {
+        DuplicateFinder::default()
+    }
+
+    /// Remembers `path` as an original without checking it.
+    pub fn add(&mut self, path: &Path) -> std::io::Result<()> {
+        let candidates = self.by_size.entry(fs::metadata(path)?.len()).or_default();
+        candidates.push(Candidate { path: path.to_path_buf(), hash: None });
+        Ok(())
+    }
+
+    /// Returns the earlier file `path` is a copy of, or remembers it as an original when there is
+    /// none.
+    pub fn check(&mut self, path: &Path) -> std::io::Result<Option<PathBuf>> {
+        let candidates = self.by_size.entry(fs::metadata(path)?.len()).or_default();
+        if !candidates.is_empty() {
+            let hash = content_hash(path)?;
+            for candidate in candidates.iter_mut() {
+                let candidate_hash = match candidate.hash {
+                    Some(hash) => hash,
+                    None => *candidate.hash.insert(content_hash(&candidate.path)?),
+                };
+                if candidate_hash == hash {
+                    return Ok(Some(candidate.path.clone()));
+                }
+            }
+            candidates.push(Candidate { path: path.to_path_buf(), hash: Some(hash) });
+        } else {
+            candidates.push(Candidate { path: path.to_path_buf(), hash: None });
+        }
+        Ok(None)
+    }
+}
+
+fn content_hash(path: &Path) -> std::io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+/// Replaces `duplicate` with a hard link to `original`. The link is made under a temporary name
+/// first so the duplicate is never gone without the link in its place. With --use-trash the
+/// duplicate goes to the trash rather than being overwritten by the link.
+pub fn link(duplicate: &Path, original: &Path) -> std::io::Result<()> {
+    let file_name = duplicate.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let partial = duplicate.with_file_name(format!(".{}.link", file_name));
+    let linked = fs::hard_link(original, &partial)
+        .and_then(|_| if trash::is_enabled() { trash::put(duplicate) } else { Ok(()) })
+        .and_then(|_| fs::rename(&partial, duplicate));
+    if let Err(e) = linked {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Moves `duplicate` into `dir`, appending a counter to its name when that is taken. Returns where
+/// it ended up.
+pub fn move_to(duplicate: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let stem = duplicate.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let extension = duplicate.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    let target = std::iter::once(dir.join(format!("{}.{}", stem, extension)))
+        .chain((2..).map(|n| dir.join(format!("{}_{}.{}", stem, n, extension))))
+        .find(|target| !target.exists())
+        .expect("there is always a free name");
+
+    match fs::rename(duplicate, &target) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(duplicate, &target)?;
+            fs::remove_file(duplicate)?;
+        }
+        result => result?,
+    }
+    Ok(target)
+}
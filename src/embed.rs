@@ -0,0 +1,123 @@
+use crate::datetime::parse_filename_date;
+use crate::exif_write::write_exif;
+use crate::format;
+use crate::output;
+use crate::scan::{is_sidecar, is_video, scan_directory, FileFilter};
+use exif::{In, Tag};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info};
+
+/// Where and when a file was taken, as listed in a manifest or read from its name.
+#[derive(Debug, Clone, Default)]
+pub struct EmbedEntry {
+    pub path: PathBuf,
+    pub position: Option<(f64, f64)>,
+    /// Capture date as yyyyMMdd and the time as HHmmss, if known
+    pub capture: Option<(String, Option<String>)>,
+}
+
+/// Reads the files to update from a manifest written by `--manifest`, as CSV or as JSON when the
+/// path ends in `.json`. Only the path column is required, so hand-made manifests with just
+/// `path,lat,lon,date` work too. Files are looked up under their new name when they have one.
+pub fn read_manifest(path: &Path) -> std::io::Result<Vec<EmbedEntry>> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+
+    output::read_manifest(path)?.into_iter()
+        .map(|record| {
+            let capture = record.date.as_deref()
+                .map(|date| parse_filename_date(date).ok_or_else(|| invalid(format!("invalid date {:?}", date))))
+                .transpose()?;
+            let path = record.new_path.filter(|path| path.exists()).unwrap_or(record.path);
+            let position = record.lat.zip(record.lon);
+            Ok(EmbedEntry { path, position, capture })
+        })
+        .collect()
+}
+
+/// Lists the photos in `dir` with the capture date found in their name, such as one given by an
+/// earlier run. Names don't hold coordinates, so none of the entries have a position.
+pub fn from_filenames(dir: &Path, max_depth: usize) -> std::io::Result<Vec<EmbedEntry>> {
+    let filter = FileFilter::new(dir, &[], &[]).map_err(std::io::Error::other)?;
+    let mut groups = Vec::new();
+    scan_directory(dir, max_depth, &filter, &mut groups)?;
+
+    Ok(groups.iter()
+        .flat_map(|group| &group.members)
+        .filter(|member| !is_sidecar(member) && !is_video(member))
+        .filter_map(|member| {
+            let stem = member.file_stem()?.to_str()?;
+            let capture = parse_filename_date(stem);
+            if capture.is_none() {
+                debug!("No date in the name of {:?}", member);
+            }
+            Some(EmbedEntry { path: member.clone(), position: None, capture: Some(capture?) })
+        })
+        .collect())
+}
+
+/// Writes the positions and capture dates of the entries into the EXIF data of the files that
+/// are missing them, or into every file with `overwrite`. Only JPEG files can be written to.
+pub fn embed(entries: &[EmbedEntry], overwrite: bool, dry_run: bool) -> std::io::Result<()> {
+    let mut written = 0;
+    let mut failed = 0;
+
+    for entry in entries {
+        let path = &entry.path;
+        if !path.exists() {
+            error!("  Error: {:?} doesn't exist, skipping.", path);
+            failed += 1;
+            continue;
+        }
+
+        let (has_position, has_capture) = if overwrite { (false, false) } else { recorded(path) };
+        let position = entry.position.filter(|_| !has_position);
+        let capture = entry.capture.as_ref().filter(|_| !has_capture);
+        if position.is_none() && capture.is_none() {
+            debug!("Nothing to write to {:?}", path);
+            continue;
+        }
+        if !format::is_jpeg(path) {
+            info!("Skipping {:?}: only JPEG files can be written to", path);
+            continue;
+        }
+
+        if let Some((lat, lon)) = position {
+            info!("{:?}: coordinates {}, {}", path, lat, lon);
+        }
+        match capture {
+            Some((date, Some(time))) => info!("{:?}: taken on {} at {}", path, date, time),
+            Some((date, None)) => info!("{:?}: taken on {}", path, date),
+            None => {}
+        }
+        written += 1;
+        if dry_run {
+            continue;
+        }
+
+        let capture = capture.map(|(date, time)| (date.as_str(), time.as_deref()));
+        if let Err(e) = write_exif(path, position, capture) {
+            error!("  Error writing metadata to {:?}: {}", path, e);
+            written -= 1;
+            failed += 1;
+        }
+    }
+
+    if dry_run {
+        info!("Dry run, {} files would be updated", written);
+    } else {
+        info!("{} files were updated", written);
+    }
+    if failed > 0 {
+        info!("{} files couldn't be updated", failed);
+    }
+    Ok(())
+}
+
+// Whether the file already records a position and a capture time
+fn recorded(path: &Path) -> (bool, bool) {
+    let exif = fs::File::open(path).ok()
+        .and_then(|file| exif::Reader::new().read_from_container(&mut std::io::BufReader::new(&file)).ok());
+    let has = |tag| exif.as_ref().is_some_and(|exif| exif.get_field(tag, In::PRIMARY).is_some());
+    (has(Tag::GPSLatitude) && has(Tag::GPSLongitude), has(Tag::DateTimeOriginal))
+}
@@ -0,0 +1,99 @@
+use serde_json::json;
+
+/// Exit codes, also listed in `--help`, so scripts can tell what went wrong without parsing the
+/// log. Invalid command lines exit with 2, as clap does.
+pub mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    /// The run couldn't start or had to stop, e.g. an unreadable directory or an invalid template
+    pub const ERROR: i32 = 1;
+    pub const USAGE: i32 = 2;
+    /// Some files couldn't be geocoded, renamed or written to, the rest were handled
+    pub const PARTIAL_FAILURE: i32 = 3;
+    /// The geocoding service rejected the API key
+    pub const UNAUTHORIZED: i32 = 4;
+    /// No photos or videos were found, with --fail-on-empty
+    pub const NO_FILES: i32 = 5;
+    /// verify found names to fix, or check found files that changed
+    pub const MISMATCH: i32 = 6;
+    /// Stopped before going over --max-api-calls or the daily quota
+    pub const OVER_BUDGET: i32 = 7;
+    /// Stopped with Ctrl-C
+    pub const INTERRUPTED: i32 = 130;
+}
+
+/// Why a run didn't end cleanly.
+#[derive(Debug, thiserror::Error)]
+pub enum RunError {
+    /// The options can't be used together or have invalid values
+    #[error("{0}")]
+    Usage(String),
+    /// Setting up the run failed, e.g. the config, template or geocoder is invalid
+    #[error("{0}")]
+    Setup(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("{0} files couldn't be handled")]
+    PartialFailure(usize),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("no files were processed")]
+    NoFiles,
+    #[error("{0} files don't match")]
+    Mismatch(usize),
+    /// Stopped before going over the budget, with what was resolved so far saved for --resume
+    #[error("{0}")]
+    OverBudget(String),
+    /// Stopped at the user's request, with what was resolved so far saved for --resume
+    #[error("{0}")]
+    Interrupted(String),
+}
+
+impl RunError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::Usage(_) => exit_code::USAGE,
+            RunError::Setup(_) | RunError::Io(_) | RunError::Other(_) => exit_code::ERROR,
+            RunError::PartialFailure(_) => exit_code::PARTIAL_FAILURE,
+            RunError::Unauthorized(_) => exit_code::UNAUTHORIZED,
+            RunError::NoFiles => exit_code::NO_FILES,
+            RunError::Mismatch(_) => exit_code::MISMATCH,
+            RunError::OverBudget(_) => exit_code::OVER_BUDGET,
+            RunError::Interrupted(_) => exit_code::INTERRUPTED,
+        }
+    }
+
+    /// A short name for the kind of error, e.g. "unauthorized".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RunError::Usage(_) => "usage",
+            RunError::Setup(_) => "setup",
+            RunError::Io(_) => "io",
+            RunError::Other(_) => "error",
+            RunError::PartialFailure(_) => "partial_failure",
+            RunError::Unauthorized(_) => "unauthorized",
+            RunError::NoFiles => "no_files",
+            RunError::Mismatch(_) => "mismatch",
+            RunError::OverBudget(_) => "over_budget",
+            RunError::Interrupted(_) => "interrupted",
+        }
+    }
+
+    /// The error as a JSON object, e.g. `{"error": {"kind": "unauthorized", ...}}`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({ "error": { "kind": self.kind(), "exit_code": self.exit_code(), "message": self.to_string() } })
+    }
+}
+
+impl From<String> for RunError {
+    fn from(message: String) -> RunError {
+        RunError::Setup(message)
+    }
+}
+
+impl From<&str> for RunError {
+    fn from(message: &str) -> RunError {
+        RunError::Setup(message.to_string())
+    }
+}
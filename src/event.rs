@@ -0,0 +1,74 @@
+use crate::geocoder::GeocodeResponse;
+use crate::label::{sanitize, town_or_city};
+use crate::metadata::PhotoMetadata;
+
+/// Splits photos, sorted by capture time, into events: runs where no two consecutive photos are
+/// more than `max_gap` seconds apart. Photos without a capture time stay with the event of the
+/// photo before them when taken on the same day. Returns the name of each photo's event, such as
+/// "2023-10-24 Amsterdam", after the date it started and the place most of its photos were taken.
+/// Photos without metadata aren't part of any event.
+pub fn detect_events(
+    metadata: &[Option<PhotoMetadata>],
+    locations: &[Option<Result<GeocodeResponse, String>>],
+    max_gap: f64,
+) -> Vec<Option<String>> {
+    let mut events: Vec<Vec<usize>> = Vec::new();
+    let mut previous: Option<&PhotoMetadata> = None;
+
+    for (index, metadata) in metadata.iter().enumerate() {
+        let Some(metadata) = metadata else {
+            continue;
+        };
+        let continues = previous.is_some_and(|previous| same_event(previous, metadata, max_gap));
+        match events.last_mut() {
+            Some(event) if continues => event.push(index),
+            _ => events.push(vec![index]),
+        }
+        previous = Some(metadata);
+    }
+
+    let mut names = vec![None; metadata.len()];
+    for event in events {
+        let name = event_name(&event, metadata, locations);
+        for index in event {
+            names[index] = Some(name.clone());
+        }
+    }
+    names
+}
+
+/// Whether a photo belongs to the same event as the one taken before it.
+pub fn same_event(previous: &PhotoMetadata, metadata: &PhotoMetadata, max_gap: f64) -> bool {
+    match (previous.timestamp, metadata.timestamp) {
+        (Some(before), Some(after)) => after - before <= max_gap,
+        _ => previous.date == metadata.date,
+    }
+}
+
+// The start date followed by the town or city most photos in the event were taken in
+fn event_name(event: &[usize], metadata: &[Option<PhotoMetadata>], locations: &[Option<Result<GeocodeResponse, String>>]) -> String {
+    let date = metadata[event[0]].as_ref().map(|metadata| metadata.date.as_str()).unwrap_or_default();
+    let date = match date.len() {
+        8 => format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]),
+        _ => date.to_string(),
+    };
+
+    // Places in the order they were first seen, so a tie goes to the one visited first
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for place in event.iter().filter_map(|&index| place(locations.get(index)?.as_ref()?.as_ref().ok()?)) {
+        match counts.iter_mut().find(|(seen, _)| *seen == place) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((place, 1)),
+        }
+    }
+    let place = counts.iter().rev().max_by_key(|(_, count)| *count).map(|(place, _)| *place);
+
+    match place {
+        Some(place) => format!("{} {}", date, sanitize(place)),
+        None => date,
+    }
+}
+
+fn place(response: &GeocodeResponse) -> Option<&str> {
+    town_or_city(response).or(response.address.country.as_deref())
+}
@@ -0,0 +1,244 @@
+use crate::attributes;
+use crate::checksum;
+use crate::exif_write::strip_gps;
+use crate::format;
+use crate::journal::Journal;
+use crate::output::{FileRecord, FileStatus, Report};
+use crate::plan::{execute_plan, RenamePlan, Transfer};
+use crate::rotate;
+use crate::scan::{is_sidecar, is_xmp};
+use crate::xmp::{self, MetadataTarget, XmpProperties};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::{error, info, warn};
+
+/// What's done to the files once they're under their new names.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Finishing {
+    /// Compare the SHA-256 of every file before and after the transfer, and journal it
+    pub checksums: bool,
+    pub auto_rotate: bool,
+    pub write_metadata: Option<MetadataTarget>,
+    pub strip_gps: bool,
+}
+
+/// What a chunk writes into its files after renaming them, per group of files.
+#[derive(Debug, Default)]
+pub struct Writes {
+    pub metadata: Vec<(Vec<PathBuf>, XmpProperties)>,
+    /// Capture times to set as the modification time
+    pub mtimes: Vec<(Vec<PathBuf>, SystemTime)>,
+}
+
+impl Writes {
+    pub fn clear(&mut self) {
+        self.metadata.clear();
+        self.mtimes.clear();
+    }
+}
+
+/// Carries out the plan of a chunk and finishes the files under their new names. The records of
+/// `planned` files are moved to the report once their file is in its final state, so their
+/// checksums can go in. `on_progress` is called with the files done and the total after each one.
+/// Groups with a file that couldn't be rotated, written to or stripped of its position afterwards
+/// are reported as failed. When the plan stops part way, every record is reported before the
+/// error is returned, with the files that weren't reached marked as failed.
+pub fn execute_chunk(
+    plan: &RenamePlan,
+    journal: &mut Journal,
+    planned: &mut HashMap<PathBuf, FileRecord>,
+    writes: &Writes,
+    finishing: Finishing,
+    report: &mut Report,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> std::io::Result<()> {
+    let done = match plan.transfer {
+        Transfer::Rename => FileStatus::Renamed,
+        Transfer::Copy => FileStatus::Copied,
+        Transfer::Move => FileStatus::Moved,
+        Transfer::Link => FileStatus::Linked,
+    };
+    let before = if finishing.checksums { checksums(plan.renames.iter().map(|rename| &rename.from)) } else { HashMap::new() };
+    let mut finished = Vec::new();
+    let total = plan.renames.iter().filter(|rename| rename.from != rename.to).count();
+    let result = execute_plan(plan, journal, &mut |rename| {
+        if let Some(mut record) = planned.remove(&rename.from) {
+            record.status = done;
+            finished.push(record);
+        }
+        on_progress(finished.len(), total);
+    });
+    // Whatever is left either had its name already or wasn't reached
+    for mut record in plan.renames.iter().filter_map(|rename| planned.remove(&rename.from)) {
+        match &result {
+            Ok(()) => record.status = FileStatus::Unchanged,
+            Err(e) => {
+                record.status = FileStatus::Failed;
+                record.reason = Some(e.to_string());
+            }
+        }
+        finished.push(record);
+    }
+    if let Err(e) = result {
+        finished.into_iter().for_each(|record| report.add(record));
+        return Err(e);
+    }
+
+    // Compared before anything below rewrites the files on purpose
+    let mut after = if finishing.checksums { checksums(plan.renames.iter().map(|rename| &rename.to)) } else { HashMap::new() };
+    let mut corrupted = HashSet::new();
+    for rename in &plan.renames {
+        if let (Some(before), Some(after)) = (before.get(&rename.from), after.get(&rename.to)) && before != after {
+            error!("Error: {:?} doesn't match {:?} after the transfer, its contents were corrupted.", rename.to, rename.from);
+            corrupted.insert(rename.to.clone());
+        }
+    }
+
+    // Files that couldn't be finished, by the name they had before, with what went wrong
+    let mut failures = Vec::new();
+    if finishing.auto_rotate {
+        failures.extend(rotate_images(plan));
+    }
+    if let Some(target) = finishing.write_metadata {
+        failures.extend(write_metadata(plan, &writes.metadata, target));
+    }
+    if finishing.strip_gps {
+        failures.extend(strip_gps_positions(plan));
+    }
+
+    if finishing.checksums {
+        let rewritten = finishing.auto_rotate || finishing.strip_gps || finishing.write_metadata == Some(MetadataTarget::Embedded);
+        if rewritten {
+            after = checksums(plan.renames.iter().map(|rename| &rename.to));
+        }
+        for (path, sha256) in &after {
+            journal.set_checksum(path, sha256);
+        }
+        journal.save()?;
+        for record in &mut finished {
+            let Some(new_path) = record.new_path.clone() else {
+                continue;
+            };
+            record.sha256 = after.get(&new_path).cloned();
+            if corrupted.contains(&new_path) {
+                record.status = FileStatus::Failed;
+                record.reason = Some("contents changed during the transfer".to_string());
+            }
+        }
+    }
+    // Last, since writing metadata into a file changes its modification time too
+    failures.extend(set_capture_mtimes(plan, &writes.mtimes));
+
+    // A file that couldn't be finished fails its whole group, which is recorded under its primary
+    let primaries = writes.metadata.iter()
+        .flat_map(|(members, _)| members.iter().map(|member| (member, &members[0])))
+        .collect::<HashMap<_, _>>();
+    for (path, reason) in failures {
+        let primary = primaries.get(&path).copied().unwrap_or(&path);
+        if let Some(record) = finished.iter_mut().find(|record| record.path == *primary && record.status != FileStatus::Failed) {
+            record.status = FileStatus::Failed;
+            record.reason = Some(reason);
+        }
+    }
+    finished.into_iter().for_each(|record| report.add(record));
+    Ok(())
+}
+
+// Writes each group's location into the files under their new names. Paired files share one
+// sidecar, since photo managers look it up by base name. Returns the files that couldn't be
+// written to.
+fn write_metadata(plan: &RenamePlan, writes: &[(Vec<PathBuf>, XmpProperties)], target: MetadataTarget) -> Vec<(PathBuf, String)> {
+    let renamed = plan.renames.iter().map(|r| (&r.from, &r.to)).collect::<HashMap<_, _>>();
+    let mut written = HashSet::new();
+    let mut failures = Vec::new();
+
+    for (members, properties) in writes {
+        // AAE files hold edits in Apple's own format, there's nothing to write into them
+        for member in members.iter().filter(|member| !is_sidecar(member) || is_xmp(member)) {
+            let path = renamed.get(member).copied().unwrap_or(member);
+            let uses_sidecar = target == MetadataTarget::Sidecar || !format::is_jpeg(path);
+            if uses_sidecar && !written.insert(xmp::sidecar_path(path)) {
+                continue;
+            }
+
+            let mut properties = properties.clone();
+            properties.preserved_file_name = attributes::original_name(path)
+                .or_else(|| member.file_name().and_then(|name| name.to_str()).map(str::to_string));
+            match xmp::write_properties(path, &properties, target) {
+                Ok(written_to) => info!("Wrote location metadata: {:?}", written_to),
+                Err(e) => {
+                    error!("Error writing location metadata to {:?}: {}", path, e);
+                    failures.push((member.clone(), format!("couldn't write the location metadata: {}", e)));
+                }
+            }
+        }
+    }
+    failures
+}
+
+// SHA-256 of every file that could be read, by path
+fn checksums<'a>(paths: impl Iterator<Item = &'a PathBuf>) -> HashMap<PathBuf, String> {
+    paths
+        .filter_map(|path| match checksum::sha256(path) {
+            Ok(sha256) => Some((path.clone(), sha256)),
+            Err(e) => {
+                error!("Error computing the checksum of {:?}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// Turns every labeled JPEG upright under its new name, and returns those that couldn't be
+fn rotate_images(plan: &RenamePlan) -> Vec<(PathBuf, String)> {
+    let mut failures = Vec::new();
+    for rename in plan.renames.iter().filter(|rename| !is_sidecar(&rename.to) && format::is_jpeg(&rename.to)) {
+        match rotate::auto_rotate(&rename.to) {
+            Ok(true) => info!("Rotated: {:?}", rename.to),
+            Ok(false) => {}
+            Err(e) => {
+                error!("Error rotating {:?}: {}", rename.to, e);
+                failures.push((rename.from.clone(), format!("couldn't rotate it: {}", e)));
+            }
+        }
+    }
+    failures
+}
+
+// Removes the position from every labeled file under its new name. Copies lose theirs while the
+// originals keep it. Returns the files it couldn't be removed from.
+fn strip_gps_positions(plan: &RenamePlan) -> Vec<(PathBuf, String)> {
+    let mut failures = Vec::new();
+    for rename in plan.renames.iter().filter(|rename| !is_sidecar(&rename.to)) {
+        if !format::is_jpeg(&rename.to) {
+            warn!("Warning: Can't remove the GPS position from {:?}, only JPEG files are supported", rename.to);
+            continue;
+        }
+        match strip_gps(&rename.to) {
+            Ok(true) => info!("Removed GPS position: {:?}", rename.to),
+            Ok(false) => {}
+            Err(e) => {
+                error!("Error removing the GPS position from {:?}: {}", rename.to, e);
+                failures.push((rename.from.clone(), format!("couldn't remove the GPS position: {}", e)));
+            }
+        }
+    }
+    failures
+}
+
+fn set_capture_mtimes(plan: &RenamePlan, writes: &[(Vec<PathBuf>, SystemTime)]) -> Vec<(PathBuf, String)> {
+    let renamed = plan.renames.iter().map(|r| (&r.from, &r.to)).collect::<HashMap<_, _>>();
+    let mut failures = Vec::new();
+    for (members, capture_time) in writes {
+        for member in members {
+            let path = renamed.get(member).copied().unwrap_or(member);
+            if let Err(e) = attributes::set_modified(path, *capture_time) {
+                error!("Error setting the modification time of {:?}: {}", path, e);
+                failures.push((member.clone(), format!("couldn't set the modification time: {}", e)));
+            }
+        }
+    }
+    failures
+}
+
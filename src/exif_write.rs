@@ -0,0 +1,163 @@
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Rational, Tag, Value};
+use std::error::Error;
+use crate::jpeg::{self, replace_file, segments};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Stores a GPS position in a JPEG's EXIF block, keeping every other field. Only JPEG files are
+/// supported; the file is replaced through a temporary copy so an interrupted write can't leave it
+/// half written.
+pub fn write_gps_position(path: &Path, lat: f64, lon: f64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    write_exif(path, Some((lat, lon)), None)
+}
+
+/// Stores a GPS position and a capture time, given as yyyyMMdd and HHmmss, in a JPEG's EXIF block
+/// like `write_gps_position`. A date without a time is stored as midnight.
+pub fn write_exif(
+    path: &Path,
+    position: Option<(f64, f64)>,
+    capture: Option<(&str, Option<&str>)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let capture = capture.map(|(date, time)| capture_fields(date, time.unwrap_or("000000"))).transpose()?;
+    let capture_tags = [Tag::DateTimeOriginal, Tag::DateTimeDigitized];
+
+    rewrite_exif(path, |fields| {
+        // Any previous position is replaced as a whole
+        fields.retain(|field| position.is_none() || field.tag.context() != Context::Gps);
+        fields.retain(|field| capture.is_none() || !capture_tags.contains(&field.tag));
+        if let Some((lat, lon)) = position {
+            fields.extend(gps_fields(lat, lon));
+        }
+        fields.extend(capture.into_iter().flatten());
+        true
+    })?;
+    Ok(())
+}
+
+/// Removes every GPS tag from a JPEG's EXIF block, keeping the other fields, so the file can be
+/// shared without giving away where it was taken. Returns whether there was anything to remove.
+pub fn strip_gps(path: &Path) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    rewrite_exif(path, |fields| {
+        let before = fields.len();
+        fields.retain(|field| field.tag.context() != Context::Gps);
+        fields.len() != before
+    })
+}
+
+/// Marks a JPEG as stored the right way up after its pixels were rotated. With `transposed` the
+/// image turned a quarter, so its recorded width and height are swapped as well.
+pub fn reset_orientation(path: &Path, transposed: bool) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    rewrite_exif(path, |fields| {
+        let mut changed = false;
+        for field in fields.iter_mut().filter(|field| field.ifd_num == In::PRIMARY) {
+            match field.tag {
+                Tag::Orientation => {
+                    field.value = Value::Short(vec![1]);
+                    changed = true;
+                }
+                Tag::PixelXDimension if transposed => field.tag = Tag::PixelYDimension,
+                Tag::PixelYDimension if transposed => field.tag = Tag::PixelXDimension,
+                _ => {}
+            }
+        }
+        changed
+    })
+}
+
+// Hands the EXIF fields of a JPEG to `edit` and writes them back when it reports a change. Only
+// JPEG files are supported; the file is replaced through a temporary copy.
+fn rewrite_exif(path: &Path, edit: impl FnOnce(&mut Vec<Field>) -> bool) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let data = fs::read(path)?;
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("only JPEG files are supported".into());
+    }
+
+    let segments = segments(&data)?;
+    let existing = segments.iter().find(|s| s.marker == 0xE1 && data[s.payload.clone()].starts_with(EXIF_HEADER));
+
+    let exif = match existing {
+        Some(segment) => Some(exif::Reader::new().read_raw(data[segment.payload.start + EXIF_HEADER.len()..segment.payload.end].to_vec())?),
+        None => None,
+    };
+
+    let mut fields = exif.iter().flat_map(|exif| exif.fields()).cloned().collect::<Vec<_>>();
+    if !edit(&mut fields) {
+        return Ok(false);
+    }
+
+    let thumbnail = exif.as_ref().and_then(thumbnail);
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    if let Some(thumbnail) = thumbnail {
+        writer.set_jpeg(thumbnail, In::THUMBNAIL);
+    }
+
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, exif.as_ref().is_some_and(|exif| exif.little_endian()))?;
+    let tiff = tiff.into_inner();
+    if EXIF_HEADER.len() + tiff.len() > jpeg::MAX_SEGMENT_LEN {
+        return Err("EXIF data is too large to fit in a JPEG segment".into());
+    }
+
+    let segment = jpeg::app1_segment(EXIF_HEADER, &tiff);
+
+    replace_file(path, &jpeg::splice(&data, &segments, existing, &segment))?;
+    Ok(true)
+}
+
+fn gps_fields(lat: f64, lon: f64) -> Vec<Field> {
+    let field = |tag, value| Field { tag, ifd_num: In::PRIMARY, value };
+    let ascii = |value: &str| Value::Ascii(vec![value.as_bytes().to_vec()]);
+
+    vec![
+        field(Tag::GPSVersionID, Value::Byte(vec![2, 3, 0, 0])),
+        field(Tag::GPSLatitudeRef, ascii(if lat < 0.0 { "S" } else { "N" })),
+        field(Tag::GPSLatitude, Value::Rational(degrees_minutes_seconds(lat.abs()))),
+        field(Tag::GPSLongitudeRef, ascii(if lon < 0.0 { "W" } else { "E" })),
+        field(Tag::GPSLongitude, Value::Rational(degrees_minutes_seconds(lon.abs()))),
+    ]
+}
+
+fn capture_fields(date: &str, time: &str) -> Result<Vec<Field>, Box<dyn Error + Send + Sync>> {
+    let (Some(date), Some(time)) = (split_digits(date, 8, ':'), split_digits(time, 6, ':')) else {
+        return Err(format!("invalid capture time {} {}", date, time).into());
+    };
+    let value = Value::Ascii(vec![format!("{} {}", date, time).into_bytes()]);
+    Ok([Tag::DateTimeOriginal, Tag::DateTimeDigitized].into_iter()
+        .map(|tag| Field { tag, ifd_num: In::PRIMARY, value: value.clone() })
+        .collect())
+}
+
+// Turns "20231024" into "2023:10:24" and "120000" into "12:00:00", the way EXIF spells them
+fn split_digits(value: &str, len: usize, separator: char) -> Option<String> {
+    if value.len() != len || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let start = len - 6;
+    Some(format!("{}{}{}{}{}", &value[..start + 2], separator, &value[start + 2..start + 4], separator, &value[start + 4..]))
+}
+
+fn degrees_minutes_seconds(value: f64) -> Vec<Rational> {
+    let degrees = value.trunc();
+    let minutes = ((value - degrees) * 60.0).trunc();
+    let seconds = (value - degrees - minutes / 60.0) * 3600.0;
+    vec![
+        Rational { num: degrees as u32, denom: 1 },
+        Rational { num: minutes as u32, denom: 1 },
+        Rational { num: (seconds * 10_000.0).round() as u32, denom: 10_000 },
+    ]
+}
+
+/// The small JPEG preview cameras embed in the EXIF data. It has to be handed to the writer
+/// separately, it isn't a regular field.
+pub fn thumbnail(exif: &exif::Exif) -> Option<&[u8]> {
+    let offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    let len = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    exif.buf().get(offset..offset + len)
+}
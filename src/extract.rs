@@ -0,0 +1,71 @@
+use crate::metadata::{recorded_metadata, RecordedMetadata};
+use crate::output::csv_field;
+use crate::scan::{is_sidecar, scan_directory, FileFilter};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How `extract` writes what it read.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExtractFormat {
+    /// A row per file, for spreadsheets
+    #[default]
+    Csv,
+    /// An array with an object per file
+    Json,
+}
+
+/// A file and what its metadata records.
+#[derive(Serialize, Debug)]
+pub struct ExtractedFile {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub metadata: RecordedMetadata,
+}
+
+/// Reads the metadata of every photo and video in `dir` and writes it to `out`, without geocoding
+/// or renaming anything. Files are listed whether or not they have a position or capture date.
+/// Returns how many were written.
+pub fn extract(dir: &Path, max_depth: usize, format: ExtractFormat, out: &mut dyn Write) -> std::io::Result<usize> {
+    let filter = FileFilter::new(dir, &[], &[]).map_err(std::io::Error::other)?;
+    let mut groups = Vec::new();
+    scan_directory(dir, max_depth, &filter, &mut groups)?;
+
+    let mut paths = groups.into_iter()
+        .flat_map(|group| group.members)
+        .filter(|member| !is_sidecar(member))
+        .collect::<Vec<_>>();
+    paths.sort();
+    let files = paths.into_par_iter()
+        .map(|path| ExtractedFile { metadata: recorded_metadata(&path), path })
+        .collect::<Vec<_>>();
+
+    match format {
+        ExtractFormat::Csv => write_csv(&files, out)?,
+        ExtractFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&files)?)?,
+    }
+    Ok(files.len())
+}
+
+fn write_csv(files: &[ExtractedFile], out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "path,lat,lon,altitude,taken,taken_utc,make,camera,lens")?;
+    for file in files {
+        let metadata = &file.metadata;
+        let fields = [
+            Some(file.path.display().to_string()),
+            metadata.lat.map(|lat| lat.to_string()),
+            metadata.lon.map(|lon| lon.to_string()),
+            metadata.altitude.map(|altitude| altitude.to_string()),
+            metadata.taken.clone(),
+            metadata.taken_utc.clone(),
+            metadata.make.clone(),
+            metadata.camera.clone(),
+            metadata.lens.clone(),
+        ];
+        let row = fields.iter().map(|field| csv_field(field.as_deref().unwrap_or(""))).collect::<Vec<_>>();
+        writeln!(out, "{}", row.join(","))?;
+    }
+    Ok(())
+}
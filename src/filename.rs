@@ -0,0 +1,89 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Longest name most filesystems accept, in bytes on POSIX and in UTF-16 units on Windows.
+pub const MAX_NAME_LEN: usize = 255;
+
+// Names Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether Windows reads `name` as a device, such as "CON" or "com1.jpg".
+pub fn is_reserved_name(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or_default();
+    RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base))
+}
+
+/// The filesystem renamed files have to be valid on.
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetFs {
+    /// Linux and macOS: anything but "/" is allowed
+    #[default]
+    Posix,
+    /// Windows, NTFS and SMB shares: no reserved device names, no <>:"\|?* and no trailing dots
+    /// or spaces
+    Windows,
+    /// OneDrive and SharePoint: the Windows rules, plus no leading spaces, "~$" or "_vti_"
+    Onedrive,
+}
+
+impl TargetFs {
+    /// Length of a name as the filesystem counts it.
+    pub fn name_len(self, name: &str) -> usize {
+        match self {
+            TargetFs::Posix => name.len(),
+            TargetFs::Windows | TargetFs::Onedrive => name.encode_utf16().count(),
+        }
+    }
+
+    /// Cuts `name` short to at most `max_len`, without splitting a character.
+    pub fn truncate(self, name: &str, max_len: usize) -> String {
+        let mut len = 0;
+        name.chars()
+            .take_while(|&c| {
+                len += match self {
+                    TargetFs::Posix => c.len_utf8(),
+                    TargetFs::Windows | TargetFs::Onedrive => c.len_utf16(),
+                };
+                len <= max_len
+            })
+            .collect()
+    }
+
+    /// Replaces every character the filesystem doesn't allow with "_" and renames anything it
+    /// reserves, e.g. "CON" becomes "CON_" on Windows.
+    pub fn sanitize(self, name: &str) -> String {
+        let mut name = name.chars()
+            .map(|c| if self.is_illegal(c) { '_' } else { c })
+            .collect::<String>();
+        if self == TargetFs::Posix {
+            return name;
+        }
+
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+        let base = name.split('.').next().unwrap_or_default();
+        if is_reserved_name(base) {
+            name.insert(base.len(), '_');
+        }
+
+        if self == TargetFs::Onedrive {
+            name = name.trim_start().replace("_vti_", "_vti");
+            if name.starts_with("~$") {
+                name.replace_range(..2, "~_");
+            }
+        }
+
+        name
+    }
+
+    fn is_illegal(self, c: char) -> bool {
+        match self {
+            TargetFs::Posix => c == '/' || c == '\0',
+            TargetFs::Windows | TargetFs::Onedrive => c.is_control() || "<>:\"/\\|?*".contains(c),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::geo::haversine_km;
+use crate::metadata::PhotoMetadata;
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime};
+
+/// A circle on the map, e.g. from `--within 52.37,4.89,25km`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub lat: f64,
+    pub lon: f64,
+    pub radius_km: f64,
+}
+
+impl Area {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        haversine_km(self.lat, self.lon, lat, lon) <= self.radius_km
+    }
+}
+
+/// Restricts a run to the photos taken in a period or an area.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhotoFilter {
+    /// First day to include, as yyyyMMdd
+    pub after: Option<u32>,
+    /// First day to leave out, as yyyyMMdd
+    pub before: Option<u32>,
+    pub within: Option<Area>,
+}
+
+impl PhotoFilter {
+    pub fn is_empty(&self) -> bool {
+        self.after.is_none() && self.before.is_none() && self.within.is_none()
+    }
+
+    /// Why the photo is left out, or None when it passes.
+    pub fn rejects(&self, metadata: &PhotoMetadata) -> Option<&'static str> {
+        let date = metadata.date.parse::<u32>().ok();
+        if self.after.is_some_and(|after| date.is_none_or(|date| date < after))
+            || self.before.is_some_and(|before| date.is_none_or(|date| date >= before))
+        {
+            return Some("taken outside the date range");
+        }
+        if self.within.is_some_and(|area| !area.contains(metadata.lat, metadata.lon)) {
+            return Some("taken outside the area");
+        }
+        None
+    }
+}
+
+/// Restricts a run to files of a size or age, as the filesystem records them, so they can be
+/// picked out before anything is read from them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSelection {
+    /// Smallest size in bytes to include
+    pub min_size: Option<u64>,
+    /// Largest size in bytes to include
+    pub max_size: Option<u64>,
+    /// Only files last modified at least this long ago
+    pub older_than: Option<Duration>,
+    /// Only files last modified less than this long ago
+    pub newer_than: Option<Duration>,
+}
+
+impl FileSelection {
+    pub fn is_empty(&self) -> bool {
+        self.min_size.is_none() && self.max_size.is_none() && self.older_than.is_none() && self.newer_than.is_none()
+    }
+
+    /// Whether a file with this metadata is picked up at `now`. Files without a modification time
+    /// are left out when an age is asked for.
+    pub fn selects(&self, metadata: &Metadata, now: SystemTime) -> bool {
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        if self.older_than.is_none() && self.newer_than.is_none() {
+            return true;
+        }
+
+        // Files from the future are as new as they get
+        let Ok(modified) = metadata.modified() else {
+            return false;
+        };
+        let age = now.duration_since(modified).unwrap_or_default();
+        self.older_than.is_none_or(|older_than| age >= older_than) && self.newer_than.is_none_or(|newer_than| age < newer_than)
+    }
+}
+
+/// Parses a size in bytes, or with a unit as in "500KB", "2MB" or "1.5GB". Units are powers of
+/// 1024, as file managers count them.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size {:?}, expected e.g. 500KB, 2MB or 1.5GB", value);
+    let upper = value.trim().to_uppercase();
+    let number = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match upper[number.len()..].trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+
+    let size = number.trim().parse::<f64>().map_err(|_| invalid())?;
+    if !size.is_finite() || size < 0.0 {
+        return Err(invalid());
+    }
+    Ok((size * unit as f64).round() as u64)
+}
+
+/// Parses a day given as "2023-06-01" or "20230601" into yyyyMMdd.
+pub fn parse_date(value: &str) -> Result<u32, String> {
+    let digits = value.trim().replace('-', "");
+    let invalid = || format!("invalid date {:?}, expected e.g. 2023-06-01", value);
+    if digits.len() != 8 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    let date = digits.parse::<u32>().map_err(|_| invalid())?;
+    let (month, day) = (date / 100 % 100, date % 100);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    Ok(date)
+}
+
+/// Parses "lat,lon,radius" where the radius is in kilometers unless it ends in "m", e.g.
+/// "52.37,4.89,25km" or "52.37,4.89,500m".
+pub fn parse_area(value: &str) -> Result<Area, String> {
+    let invalid = || format!("invalid area {:?}, expected e.g. 52.37,4.89,25km", value);
+    let parts = value.split(',').map(str::trim).collect::<Vec<_>>();
+    let [lat, lon, radius] = parts[..] else {
+        return Err(invalid());
+    };
+
+    let lat = lat.parse::<f64>().map_err(|_| invalid())?;
+    let lon = lon.parse::<f64>().map_err(|_| invalid())?;
+    let radius_km = if let Some(km) = radius.strip_suffix("km") {
+        km.trim().parse::<f64>().map_err(|_| invalid())?
+    } else if let Some(m) = radius.strip_suffix('m') {
+        m.trim().parse::<f64>().map_err(|_| invalid())? / 1000.0
+    } else {
+        radius.parse::<f64>().map_err(|_| invalid())?
+    };
+
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) || radius_km.is_nan() || radius_km <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(Area { lat, lon, radius_km })
+}
@@ -0,0 +1,114 @@
+use crate::geo::haversine_km;
+use crate::geocoder::{GeocodeError, GeocodeResponse, ReverseGeocoder};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Recorded responses further away than this from the requested coordinates don't count
+const MAX_DISTANCE_KM: f64 = 1.0;
+
+/// Answers with the same response for any coordinates, e.g. to try out templates or in tests.
+pub struct StaticGeocoder {
+    response: GeocodeResponse,
+}
+
+impl StaticGeocoder {
+    pub fn new(response: GeocodeResponse) -> StaticGeocoder {
+        StaticGeocoder { response }
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for StaticGeocoder {
+    async fn reverse(&self, _lat: f64, _lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        Ok(self.response.clone())
+    }
+
+    fn rate_limited(&self) -> bool {
+        false
+    }
+}
+
+/// Replays recorded responses without any network access, answering with the one recorded
+/// closest to the requested coordinates. Fixtures can be an exported geocode cache or the output
+/// of `--geocode-only`, so an earlier run can be repeated fully offline.
+pub struct FixtureGeocoder {
+    responses: Vec<(f64, f64, GeocodeResponse)>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureFile {
+    /// The geocode cache, keyed by "lat,lon"
+    Cache(HashMap<String, GeocodeResponse>),
+    /// Records printed by --geocode-only
+    Records(Vec<FixtureRecord>),
+}
+
+#[derive(Deserialize)]
+struct FixtureRecord {
+    lat: f64,
+    lon: f64,
+    response: GeocodeResponse,
+}
+
+impl FixtureGeocoder {
+    pub fn new(responses: Vec<(f64, f64, GeocodeResponse)>) -> FixtureGeocoder {
+        FixtureGeocoder { responses }
+    }
+
+    /// Reads a fixture file. `--geocode-only` prints a record per line, so a file of JSON lines
+    /// is read as well as a single JSON document.
+    pub fn load(path: &Path) -> Result<FixtureGeocoder, GeocodeError> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("failed to read fixture {}: {}", path.display(), e))?;
+        let invalid = |e: serde_json::Error| format!("{}: {}", path.display(), e);
+
+        let file = match serde_json::from_str::<FixtureFile>(&contents) {
+            Ok(file) => file,
+            Err(e) => {
+                let lines = contents.lines().filter(|line| !line.trim().is_empty());
+                let records = lines.map(serde_json::from_str::<FixtureRecord>).collect::<Result<Vec<_>, _>>();
+                FixtureFile::Records(records.map_err(|_| invalid(e))?)
+            }
+        };
+
+        let responses = match file {
+            FixtureFile::Cache(entries) => entries.into_iter()
+                .map(|(key, response)| {
+                    let (lat, lon) = key.split_once(',').ok_or_else(|| format!("{}: invalid coordinates {:?}", path.display(), key))?;
+                    let parse = |value: &str| value.trim().parse::<f64>().map_err(|_| format!("{}: invalid coordinates {:?}", path.display(), key));
+                    Ok((parse(lat)?, parse(lon)?, response))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            FixtureFile::Records(records) => records.into_iter().map(|record| (record.lat, record.lon, record.response)).collect(),
+        };
+
+        Ok(FixtureGeocoder::new(responses))
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for FixtureGeocoder {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        self.responses.iter()
+            .map(|(fixture_lat, fixture_lon, response)| (haversine_km(lat, lon, *fixture_lat, *fixture_lon), response))
+            .filter(|(distance, _)| *distance <= MAX_DISTANCE_KM)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, response)| response.clone())
+            .ok_or_else(|| format!("no recorded response near {}, {}", lat, lon).into())
+    }
+
+    fn rate_limited(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,62 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// Image containers that can carry EXIF, told apart by their first bytes rather than their
+/// extension, which cameras, phones and messaging apps don't always get right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    /// Plain TIFF files as well as the TIFF-based RAW formats (CR2, NEF, ARW, DNG)
+    Tiff,
+    /// HEIC and the other HEIF variants
+    Heif,
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tif",
+            ImageFormat::Heif => "heic",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
+// ISO base media brands of HEIF images, as opposed to those of MP4 and MOV videos
+const HEIF_BRANDS: &[&[u8]] = &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1", b"avif"];
+
+/// The format of the file at `path`, from its contents. None when it can't be read or isn't an
+/// image format that can carry EXIF.
+pub fn detect(path: &Path) -> Option<ImageFormat> {
+    let mut header = Vec::with_capacity(16);
+    fs::File::open(path).ok()?.take(16).read_to_end(&mut header).ok()?;
+    detect_bytes(&header)
+}
+
+/// The format of a file starting with `header`, which needs to be at least 12 bytes long.
+pub fn detect_bytes(header: &[u8]) -> Option<ImageFormat> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageFormat::Jpeg)
+    } else if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageFormat::Png)
+    } else if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        Some(ImageFormat::Tiff)
+    } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        Some(ImageFormat::Webp)
+    } else if header.get(4..8) == Some(b"ftyp") && header.get(8..12).is_some_and(|brand| HEIF_BRANDS.contains(&brand)) {
+        Some(ImageFormat::Heif)
+    } else {
+        None
+    }
+}
+
+/// Whether the file at `path` really is a JPEG, the only format XMP and GPS positions are written
+/// into directly.
+pub fn is_jpeg(path: &Path) -> bool {
+    detect(path) == Some(ImageFormat::Jpeg)
+}
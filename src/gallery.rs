@@ -0,0 +1,112 @@
+use crate::exif_write::thumbnail;
+use crate::map::file_url;
+use crate::output::FileRecord;
+use crate::xmp::escape;
+use base64::Engine;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const LEAFLET: &str = "https://unpkg.com/leaflet@1.9.4/dist/leaflet";
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:0 auto;max-width:1200px;padding:1em}\
+#map{height:420px;border-radius:6px}\
+.grid{display:grid;grid-template-columns:repeat(auto-fill,minmax(160px,1fr));gap:8px}\
+figure{margin:0}figure img{width:100%;height:120px;object-fit:cover;border-radius:4px;background:#eee}\
+figcaption{font-size:.75em;overflow-wrap:anywhere;color:#555}";
+
+/// Writes a single HTML page to review a run: a Leaflet map with a marker per located file and
+/// a grid of thumbnails per day and place. Thumbnails are the previews embedded in the files'
+/// EXIF data, so the page works on its own; files without one are linked instead.
+pub fn write_html(path: &Path, records: &[FileRecord]) -> std::io::Result<()> {
+    let mut sections: BTreeMap<(String, String), Vec<(&FileRecord, String)>> = BTreeMap::new();
+    for record in records {
+        let file = record.new_path.as_ref().filter(|path| path.exists()).unwrap_or(&record.path);
+        let source = embedded_thumbnail(file).unwrap_or_else(|| file_url(file));
+        sections.entry((record.date.clone().unwrap_or_default(), place(record))).or_default().push((record, source));
+    }
+
+    let markers = sections.values()
+        .flatten()
+        .filter_map(|(record, source)| Some(json!({
+            "lat": record.lat?,
+            "lon": record.lon?,
+            "name": file_name(record),
+            "thumbnail": source,
+        })))
+        .collect::<Vec<_>>();
+    let located = markers.len();
+    // Keeps file names from ending the script early
+    let markers = serde_json::to_string(&markers)?.replace("</", "<\\/");
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Photo report</title>\n\
+         <link rel=\"stylesheet\" href=\"{leaflet}.css\">\n<script src=\"{leaflet}.js\"></script>\n\
+         <style>{style}</style>\n</head>\n<body>\n<h1>Photo report</h1>\n<p>{count} files, {located} with a location</p>\n",
+        leaflet = LEAFLET,
+        style = STYLE,
+        count = records.len(),
+        located = located,
+    );
+    html.push_str("<div id=\"map\"></div>\n");
+
+    for ((date, place), files) in &sections {
+        html.push_str(&format!("<h2>{}</h2>\n<div class=\"grid\">\n", escape(&heading(date, place))));
+        for (record, source) in files {
+            let name = file_name(record);
+            html.push_str(&format!(
+                "<figure><img src=\"{}\" alt=\"{}\" loading=\"lazy\"><figcaption>{}</figcaption></figure>\n",
+                escape(source),
+                escape(&name),
+                escape(&name)
+            ));
+        }
+        html.push_str("</div>\n");
+    }
+
+    html.push_str(&format!(
+        "<script>\nconst photos = {};\n\
+         const map = L.map('map');\n\
+         L.tileLayer('https://tile.openstreetmap.org/{{z}}/{{x}}/{{y}}.png', {{maxZoom: 19, attribution: '&copy; OpenStreetMap contributors'}}).addTo(map);\n\
+         const text = value => {{ const span = document.createElement('span'); span.textContent = value; return span.innerHTML; }};\n\
+         const markers = photos.map(p => L.marker([p.lat, p.lon]).bindPopup(`<img src=\"${{p.thumbnail}}\" width=\"200\"><br>${{text(p.name)}}`));\n\
+         if (markers.length) {{ map.fitBounds(L.featureGroup(markers).addTo(map).getBounds(), {{padding: [20, 20], maxZoom: 15}}); }} else {{ map.setView([20, 0], 2); }}\n\
+         </script>\n</body>\n</html>\n",
+        markers
+    ));
+
+    fs::write(path, html)
+}
+
+// The EXIF preview of a photo as a data URL
+fn embedded_thumbnail(path: &Path) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(&file)).ok()?;
+    let preview = thumbnail(&exif)?;
+    Some(format!("data:image/jpeg;base64,{}", base64::engine::general_purpose::STANDARD.encode(preview)))
+}
+
+fn file_name(record: &FileRecord) -> String {
+    let file = record.new_path.as_ref().unwrap_or(&record.path);
+    file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// The town or city and country a file was taken in, as far as they're known
+fn place(record: &FileRecord) -> String {
+    let Some(address) = &record.address else {
+        return String::new();
+    };
+    let city = address.city.as_ref().or(address.town.as_ref()).or(address.village.as_ref());
+    [city, address.country.as_ref()].into_iter().flatten().cloned().collect::<Vec<_>>().join(", ")
+}
+
+// E.g. "2023-10-24 · Amsterdam, Netherlands"
+fn heading(date: &str, place: &str) -> String {
+    let date = match date.len() {
+        8 => format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]),
+        0 => "Unknown date".to_string(),
+        _ => date.to_string(),
+    };
+    if place.is_empty() { date } else { format!("{} · {}", date, place) }
+}
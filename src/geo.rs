@@ -0,0 +1,31 @@
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance between two coordinates in kilometers.
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Groups points that lie within `radius_m` meters of a cluster's first point and returns, for
+/// every point, the index of the point representing its cluster.
+pub fn cluster(points: &[(f64, f64)], radius_m: f64) -> Vec<usize> {
+    let radius_km = radius_m / 1000.0;
+    let mut leaders: Vec<usize> = Vec::new();
+
+    points.iter()
+        .enumerate()
+        .map(|(index, &(lat, lon))| {
+            let leader = leaders.iter().copied().find(|&leader| {
+                let (leader_lat, leader_lon) = points[leader];
+                haversine_km(lat, lon, leader_lat, leader_lon) <= radius_km
+            });
+
+            leader.unwrap_or_else(|| {
+                leaders.push(index);
+                index
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,778 @@
+use crate::fixture::FixtureGeocoder;
+use crate::offline::OfflineGeocoder;
+use crate::rate_limit::{Paced, RateLimiter};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub type GeocodeError = Box<dyn std::error::Error + Send + Sync>;
+
+const USER_AGENT: &str = "image-labeler/0.1.0";
+
+// A request that hangs this long is treated like any other transient failure
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Rate limited, timed out or the service is having trouble; trying again may work
+    Transient,
+    /// The request will keep failing no matter how often it's sent
+    Permanent,
+    /// The API key was rejected, so every other request will fail as well
+    Unauthorized,
+    /// The run's budget of requests is used up, so the request wasn't sent
+    OverBudget,
+}
+
+/// A failed request to a geocoding service, classified so callers know whether to retry.
+#[derive(Debug)]
+pub struct ServiceError {
+    pub kind: FailureKind,
+    pub message: String,
+    /// How long the service asked to wait before trying again
+    pub retry_after: Option<Duration>,
+}
+
+impl ServiceError {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> ServiceError {
+        ServiceError { kind, message: message.into(), retry_after: None }
+    }
+
+    /// The kind of failure behind a geocoding error. Errors that weren't classified, such as a
+    /// response without results, are permanent.
+    pub fn kind_of(error: &GeocodeError) -> FailureKind {
+        error.downcast_ref::<ServiceError>().map(|e| e.kind).unwrap_or(FailureKind::Permanent)
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+// Sends the request and turns transport failures and error statuses into a ServiceError
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, GeocodeError> {
+    let response = request.send().await.map_err(request_error)?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let kind = match status.as_u16() {
+        401 | 403 => FailureKind::Unauthorized,
+        408 | 425 | 429 | 500..=599 => FailureKind::Transient,
+        _ => FailureKind::Permanent,
+    };
+    // Only the delay-seconds form of Retry-After is used by the supported services
+    let retry_after = response.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+
+    let host = response.url().host_str().unwrap_or("geocoder").to_string();
+    let message = match kind {
+        FailureKind::Unauthorized => format!("{} rejected the API key ({})", host, status),
+        _ => format!("{} returned {}", host, status),
+    };
+    Err(Box::new(ServiceError { kind, message, retry_after }))
+}
+
+pub(crate) fn request_error(error: reqwest::Error) -> GeocodeError {
+    let kind = if error.is_decode() { FailureKind::Permanent } else { FailureKind::Transient };
+    // The URL carries the API key in its query, and the message ends up in logs and reports
+    let host = error.url().and_then(|url| url.host_str()).unwrap_or("geocoder").to_string();
+    let error = error.without_url();
+    Box::new(ServiceError::new(kind, format!("{} ({})", error, host)))
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    /// geocode.maps.co, a hosted Nominatim instance
+    MapsCo,
+    /// A self-hosted Nominatim instance, see --geocoder-url
+    Nominatim,
+    /// OpenCage
+    Opencage,
+    /// Mapbox
+    Mapbox,
+    /// Google Maps
+    Google,
+    /// A local GeoNames dataset, see --offline-dataset
+    Offline,
+    /// Responses recorded earlier, such as an exported geocode cache, see --fixture
+    Fixture,
+}
+
+#[async_trait]
+pub trait ReverseGeocoder: Send + Sync {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError>;
+
+    /// Whether requests need to be paced to stay within the service's rate limits
+    fn rate_limited(&self) -> bool {
+        true
+    }
+
+    /// The name of the point of interest closest to the position, such as a mountain peak or a
+    /// museum. Services that can't look those up have none.
+    async fn nearby_poi(&self, _lat: f64, _lon: f64) -> Result<Option<String>, GeocodeError> {
+        Ok(None)
+    }
+
+    /// How many positions `reverse_batch` looks up in a single request. Services without a batch
+    /// endpoint take them one at a time.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Looks up several positions at once, with a result per position in the same order. The
+    /// outer error is for the request as a whole, such as a rejected API key, and is retried like
+    /// a failed `reverse`.
+    async fn reverse_batch(&self, positions: &[(f64, f64)]) -> Result<Vec<Result<GeocodeResponse, GeocodeError>>, GeocodeError> {
+        let mut results = Vec::with_capacity(positions.len());
+        for &(lat, lon) in positions {
+            results.push(self.reverse(lat, lon).await);
+        }
+        Ok(results)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Address {
+    pub road: Option<String>,
+    pub neighbourhood: Option<String>,
+    pub suburb: Option<String>,
+    pub city: Option<String>,
+    pub town: Option<String>,
+    pub village: Option<String>,
+    pub county: Option<String>,
+    pub state_district: Option<String>,
+    pub state: Option<String>,
+    pub postcode: Option<String>,
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GeocodeResponse {
+    pub display_name: String,
+    pub address: Address,
+}
+
+// The Nominatim schema as checked by --strict-schema: every field has to be one Nominatim
+// documents, with the documented type, so a renamed or new field fails the lookup instead of
+// quietly going missing. Fields that are checked but not used start with an underscore.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct StrictAddress {
+    road: Option<String>,
+    neighbourhood: Option<String>,
+    suburb: Option<String>,
+    city: Option<String>,
+    town: Option<String>,
+    village: Option<String>,
+    county: Option<String>,
+    state_district: Option<String>,
+    state: Option<String>,
+    postcode: Option<String>,
+    country: Option<String>,
+    country_code: Option<String>,
+    // Parts of the address that aren't used, and the kinds of place a point of interest can be
+    #[serde(rename = "house_number")]
+    _house_number: Option<String>,
+    #[serde(rename = "house_name")]
+    _house_name: Option<String>,
+    #[serde(rename = "allotments")]
+    _allotments: Option<String>,
+    #[serde(rename = "quarter")]
+    _quarter: Option<String>,
+    #[serde(rename = "city_block")]
+    _city_block: Option<String>,
+    #[serde(rename = "residential")]
+    _residential: Option<String>,
+    #[serde(rename = "farm")]
+    _farm: Option<String>,
+    #[serde(rename = "farmyard")]
+    _farmyard: Option<String>,
+    #[serde(rename = "industrial")]
+    _industrial: Option<String>,
+    #[serde(rename = "commercial")]
+    _commercial: Option<String>,
+    #[serde(rename = "retail")]
+    _retail: Option<String>,
+    #[serde(rename = "subdivision")]
+    _subdivision: Option<String>,
+    #[serde(rename = "city_district")]
+    _city_district: Option<String>,
+    #[serde(rename = "hamlet")]
+    _hamlet: Option<String>,
+    #[serde(rename = "croft")]
+    _croft: Option<String>,
+    #[serde(rename = "isolated_dwelling")]
+    _isolated_dwelling: Option<String>,
+    #[serde(rename = "municipality")]
+    _municipality: Option<String>,
+    #[serde(rename = "region")]
+    _region: Option<String>,
+    #[serde(rename = "province")]
+    _province: Option<String>,
+    #[serde(rename = "continent")]
+    _continent: Option<String>,
+    #[serde(rename = "emergency")]
+    _emergency: Option<String>,
+    #[serde(rename = "historic")]
+    _historic: Option<String>,
+    #[serde(rename = "military")]
+    _military: Option<String>,
+    #[serde(rename = "natural")]
+    _natural: Option<String>,
+    #[serde(rename = "landuse")]
+    _landuse: Option<String>,
+    #[serde(rename = "place")]
+    _place: Option<String>,
+    #[serde(rename = "railway")]
+    _railway: Option<String>,
+    #[serde(rename = "man_made")]
+    _man_made: Option<String>,
+    #[serde(rename = "aerialway")]
+    _aerialway: Option<String>,
+    #[serde(rename = "boundary")]
+    _boundary: Option<String>,
+    #[serde(rename = "amenity")]
+    _amenity: Option<String>,
+    #[serde(rename = "aeroway")]
+    _aeroway: Option<String>,
+    #[serde(rename = "club")]
+    _club: Option<String>,
+    #[serde(rename = "craft")]
+    _craft: Option<String>,
+    #[serde(rename = "leisure")]
+    _leisure: Option<String>,
+    #[serde(rename = "office")]
+    _office: Option<String>,
+    #[serde(rename = "mountain_pass")]
+    _mountain_pass: Option<String>,
+    #[serde(rename = "shop")]
+    _shop: Option<String>,
+    #[serde(rename = "tourism")]
+    _tourism: Option<String>,
+    #[serde(rename = "bridge")]
+    _bridge: Option<String>,
+    #[serde(rename = "tunnel")]
+    _tunnel: Option<String>,
+    #[serde(rename = "waterway")]
+    _waterway: Option<String>,
+    // Subdivision codes of the administrative levels, e.g. "NL-NH" at level 4
+    #[serde(rename = "ISO3166-2-lvl3")]
+    _iso3166_2_lvl3: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl4")]
+    _iso3166_2_lvl4: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl5")]
+    _iso3166_2_lvl5: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl6")]
+    _iso3166_2_lvl6: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl7")]
+    _iso3166_2_lvl7: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl8")]
+    _iso3166_2_lvl8: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl9")]
+    _iso3166_2_lvl9: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl10")]
+    _iso3166_2_lvl10: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct StrictGeocodeResponse {
+    #[serde(rename = "place_id")]
+    _place_id: Option<u64>,
+    #[serde(rename = "licence")]
+    _licence: Option<String>,
+    #[serde(rename = "osm_type")]
+    _osm_type: Option<String>,
+    #[serde(rename = "osm_id")]
+    _osm_id: Option<u64>,
+    #[serde(rename = "lat")]
+    _lat: Option<String>,
+    #[serde(rename = "lon")]
+    _lon: Option<String>,
+    #[serde(rename = "class")]
+    _class: Option<String>,
+    #[serde(rename = "type")]
+    _type: Option<String>,
+    #[serde(rename = "place_rank")]
+    _place_rank: Option<u32>,
+    #[serde(rename = "importance")]
+    _importance: Option<f64>,
+    #[serde(rename = "addresstype")]
+    _addresstype: Option<String>,
+    #[serde(rename = "name")]
+    _name: Option<String>,
+    display_name: String,
+    address: StrictAddress,
+    #[serde(rename = "boundingbox")]
+    _boundingbox: Option<Vec<String>>,
+    /// Added by maps.co to every response
+    #[serde(rename = "powered_by")]
+    _powered_by: Option<String>,
+}
+
+impl From<StrictGeocodeResponse> for GeocodeResponse {
+    fn from(strict: StrictGeocodeResponse) -> Self {
+        GeocodeResponse {
+            display_name: strict.display_name,
+            address: Address {
+                road: strict.address.road,
+                neighbourhood: strict.address.neighbourhood,
+                suburb: strict.address.suburb,
+                city: strict.address.city,
+                town: strict.address.town,
+                village: strict.address.village,
+                county: strict.address.county,
+                state_district: strict.address.state_district,
+                state: strict.address.state,
+                postcode: strict.address.postcode,
+                country: strict.address.country,
+                country_code: strict.address.country_code,
+            },
+        }
+    }
+}
+
+/// How requests reach the geocoding service.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkOptions {
+    /// Proxy for every request, e.g. "http://proxy.example.com:3128". Without one the HTTPS_PROXY,
+    /// HTTP_PROXY and NO_PROXY environment variables are followed.
+    pub proxy: Option<String>,
+    /// How long a request may take, `DEFAULT_TIMEOUT` when not given
+    pub timeout: Option<Duration>,
+    /// PEM file with certificate authorities to trust on top of the system ones, e.g. for a
+    /// proxy that inspects TLS traffic
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl NetworkOptions {
+    pub(crate) fn client(&self) -> Result<reqwest::Client, GeocodeError> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(self.timeout.unwrap_or(DEFAULT_TIMEOUT));
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy).map_err(|e| format!("invalid proxy {:?}: {}", proxy, e))?);
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let pem = fs::read(ca_bundle).map_err(|e| format!("{}: {}", ca_bundle.display(), e))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| format!("{}: {}", ca_bundle.display(), e))?;
+            if certificates.is_empty() {
+                return Err(format!("{}: no certificates found", ca_bundle.display()).into());
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        Ok(builder.build()?)
+    }
+}
+
+pub struct GeocoderOptions<'a> {
+    pub provider: Provider,
+    pub api_key: Option<String>,
+    /// Preferred language for place names, as an accept-language code such as "en"
+    pub language: String,
+    /// Reject Nominatim responses that don't match the documented schema
+    pub strict: bool,
+    /// Address of a self-hosted Nominatim instance, e.g. "http://localhost:8080"
+    pub url: Option<String>,
+    /// Nominatim zoom level from 3 (country) to 18 (building), for coarser or finer addresses
+    pub zoom: Option<u8>,
+    pub dataset: Option<&'a Path>,
+    /// Recorded responses used by the fixture provider
+    pub fixture: Option<&'a Path>,
+    /// Requests per second to pace the service to, or None to send them as fast as it answers
+    pub rate_limit: Option<f64>,
+    /// Requests that may go out at once before the rate limit kicks in
+    pub burst: u32,
+    pub network: NetworkOptions,
+}
+
+pub fn build_geocoder(options: GeocoderOptions) -> Result<Box<dyn ReverseGeocoder>, GeocodeError> {
+    let GeocoderOptions { provider, api_key, language, strict, url, zoom, dataset, fixture, rate_limit, burst, network } = options;
+    let client = network.client()?;
+    let require_key = || api_key.clone().ok_or_else(|| format!("an API key is required for the {:?} provider", provider));
+
+    let geocoder: Box<dyn ReverseGeocoder> = match provider {
+        Provider::MapsCo => Box::new(Nominatim {
+            client,
+            base_url: url.unwrap_or_else(|| MAPS_CO_URL.to_string()),
+            api_key: api_key.clone(),
+            language,
+            strict,
+            zoom,
+        }),
+        Provider::Nominatim => {
+            let base_url = url.ok_or("the nominatim provider requires --geocoder-url")?;
+            // Self-hosted instances don't need a key, but one behind a proxy might
+            Box::new(Nominatim { client, base_url, api_key: api_key.clone(), language, strict, zoom })
+        }
+        Provider::Opencage => Box::new(OpenCage { client, api_key: require_key()?, language }),
+        Provider::Mapbox => Box::new(Mapbox { client, api_key: require_key()?, language }),
+        Provider::Google => Box::new(Google { client, api_key: require_key()?, language }),
+        Provider::Offline => {
+            let dataset = dataset.ok_or("the offline provider requires --offline-dataset")?;
+            Box::new(OfflineGeocoder::load(dataset)?)
+        }
+        Provider::Fixture => {
+            let fixture = fixture.ok_or("the fixture provider requires --fixture")?;
+            Box::new(FixtureGeocoder::load(fixture)?)
+        }
+    };
+
+    Ok(match rate_limit {
+        Some(rate) if geocoder.rate_limited() => Box::new(Paced::new(geocoder, RateLimiter::new(rate, burst))),
+        _ => geocoder,
+    })
+}
+
+const MAPS_CO_URL: &str = "https://geocode.maps.co";
+
+/// The Nominatim API, as hosted by geocode.maps.co or run yourself.
+pub struct Nominatim {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    language: String,
+    strict: bool,
+    zoom: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct NominatimPlace {
+    // Missing when nothing was found nearby
+    name: Option<String>,
+}
+
+impl Nominatim {
+    fn url(&self, lat: f64, lon: f64, zoom: Option<u8>) -> String {
+        let mut url = format!(
+            "{}/reverse?format=json&addressdetails=1&lat={}&lon={}&accept-language={}",
+            self.base_url.trim_end_matches('/'), lat, lon, self.language
+        );
+        if let Some(zoom) = zoom {
+            url.push_str(&format!("&zoom={}", zoom));
+        }
+        if let Some(api_key) = &self.api_key {
+            url.push_str(&format!("&api_key={}", api_key));
+        }
+        url
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for Nominatim {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        let url = self.url(lat, lon, self.zoom);
+        let body = send(self.client.get(url)).await?.text().await.map_err(request_error)?;
+
+        let response = if self.strict {
+            serde_json::from_str::<StrictGeocodeResponse>(&body)
+                .map_err(|e| format!("geocoder response does not match the expected schema: {}", e))?
+                .into()
+        } else {
+            serde_json::from_str::<GeocodeResponse>(&body)?
+        };
+
+        Ok(response)
+    }
+
+    async fn nearby_poi(&self, lat: f64, lon: f64) -> Result<Option<String>, GeocodeError> {
+        // Only named features such as peaks, viewpoints and museums, rather than the nearest road
+        let url = format!("{}&layer=poi", self.url(lat, lon, Some(18)));
+        let place = send(self.client.get(url)).await?.json::<NominatimPlace>().await.map_err(request_error)?;
+        Ok(place.name.filter(|name| !name.is_empty()))
+    }
+}
+
+pub struct OpenCage {
+    client: reqwest::Client,
+    api_key: String,
+    language: String,
+}
+
+#[derive(Deserialize)]
+struct OpenCageResponse {
+    results: Vec<OpenCageResult>,
+}
+
+#[derive(Deserialize)]
+struct OpenCageResult {
+    formatted: String,
+    // OpenCage uses the same component names as Nominatim
+    components: Address,
+}
+
+#[async_trait]
+impl ReverseGeocoder for OpenCage {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        let url = format!(
+            "https://api.opencagedata.com/geocode/v1/json?q={}+{}&key={}&language={}&no_annotations=1",
+            lat, lon, self.api_key, self.language
+        );
+
+        let response = send(self.client.get(url)).await?.json::<OpenCageResponse>().await.map_err(request_error)?;
+        let result = response.results.into_iter().next().ok_or("no results")?;
+
+        Ok(GeocodeResponse { display_name: result.formatted, address: result.components })
+    }
+}
+
+pub struct Mapbox {
+    client: reqwest::Client,
+    api_key: String,
+    language: String,
+}
+
+// The most queries the batch endpoint takes in one request
+const MAPBOX_BATCH_SIZE: usize = 1000;
+
+// Single lookups and batches both go to the v6 API, which nests the parents of a feature by kind,
+// so a photo is named the same whichever way it was looked up
+#[derive(Deserialize)]
+struct MapboxBatchResponse {
+    batch: Vec<MapboxCollection>,
+}
+
+#[derive(Deserialize)]
+struct MapboxCollection {
+    #[serde(default)]
+    features: Vec<MapboxV6Feature>,
+}
+
+#[derive(Deserialize)]
+struct MapboxV6Feature {
+    properties: MapboxProperties,
+}
+
+#[derive(Deserialize)]
+struct MapboxProperties {
+    name: String,
+    full_address: Option<String>,
+    place_formatted: Option<String>,
+    #[serde(default)]
+    context: MapboxContext,
+}
+
+#[derive(Deserialize, Default)]
+struct MapboxContext {
+    address: Option<MapboxAddressPart>,
+    street: Option<MapboxPart>,
+    neighborhood: Option<MapboxPart>,
+    locality: Option<MapboxPart>,
+    place: Option<MapboxPart>,
+    district: Option<MapboxPart>,
+    region: Option<MapboxPart>,
+    postcode: Option<MapboxPart>,
+    country: Option<MapboxPart>,
+}
+
+#[derive(Deserialize)]
+struct MapboxPart {
+    name: String,
+    country_code: Option<String>,
+}
+
+// Only there when the feature is an address, along with the street it's on
+#[derive(Deserialize)]
+struct MapboxAddressPart {
+    street_name: Option<String>,
+}
+
+impl From<MapboxProperties> for GeocodeResponse {
+    fn from(properties: MapboxProperties) -> GeocodeResponse {
+        let context = properties.context;
+        let name = |part: Option<MapboxPart>| part.map(|part| part.name);
+        let display_name = properties.full_address.unwrap_or_else(|| match properties.place_formatted {
+            Some(place) => format!("{}, {}", properties.name, place),
+            None => properties.name,
+        });
+        let address = Address {
+            road: name(context.street).or(context.address.and_then(|address| address.street_name)),
+            neighbourhood: name(context.neighborhood),
+            village: name(context.locality),
+            city: name(context.place),
+            county: name(context.district),
+            state: name(context.region),
+            postcode: name(context.postcode),
+            country_code: context.country.as_ref().and_then(|country| country.country_code.clone()),
+            country: name(context.country),
+            ..Address::default()
+        };
+        GeocodeResponse { display_name, address }
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for Mapbox {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        let url = format!(
+            "https://api.mapbox.com/search/geocode/v6/reverse?longitude={}&latitude={}&limit=1&access_token={}&language={}",
+            lon, lat, self.api_key, self.language
+        );
+
+        let response = send(self.client.get(url)).await?.json::<MapboxCollection>().await.map_err(request_error)?;
+        let feature = response.features.into_iter().next().ok_or("no results")?;
+        Ok(feature.properties.into())
+    }
+
+    fn batch_size(&self) -> usize {
+        MAPBOX_BATCH_SIZE
+    }
+
+    async fn reverse_batch(&self, positions: &[(f64, f64)]) -> Result<Vec<Result<GeocodeResponse, GeocodeError>>, GeocodeError> {
+        let url = format!("https://api.mapbox.com/search/geocode/v6/batch?access_token={}", self.api_key);
+        let queries = positions.iter()
+            .map(|&(lat, lon)| serde_json::json!({ "longitude": lon, "latitude": lat, "language": self.language, "limit": 1 }))
+            .collect::<Vec<_>>();
+
+        let response = send(self.client.post(url).json(&queries)).await?.json::<MapboxBatchResponse>().await.map_err(request_error)?;
+        if response.batch.len() != positions.len() {
+            return Err(format!("expected {} results from the batch, got {}", positions.len(), response.batch.len()).into());
+        }
+
+        Ok(response.batch.into_iter()
+            .map(|collection| match collection.features.into_iter().next() {
+                Some(feature) => Ok(feature.properties.into()),
+                None => Err("no results".into()),
+            })
+            .collect())
+    }
+}
+
+pub struct Google {
+    client: reqwest::Client,
+    api_key: String,
+    language: String,
+}
+
+#[derive(Deserialize)]
+struct GoogleResponse {
+    status: String,
+    error_message: Option<String>,
+    #[serde(default)]
+    results: Vec<GoogleResult>,
+}
+
+#[derive(Deserialize)]
+struct GoogleResult {
+    formatted_address: String,
+    address_components: Vec<GoogleComponent>,
+}
+
+#[derive(Deserialize)]
+struct GoogleComponent {
+    long_name: String,
+    short_name: String,
+    types: Vec<String>,
+}
+
+#[async_trait]
+impl ReverseGeocoder for Google {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        let url = format!(
+            "https://maps.googleapis.com/maps/api/geocode/json?latlng={},{}&key={}&language={}",
+            lat, lon, self.api_key, self.language
+        );
+
+        let response = send(self.client.get(url)).await?.json::<GoogleResponse>().await.map_err(request_error)?;
+        if response.status != "OK" {
+            // Google reports most failures with a 200 status and a code in the body
+            let kind = match response.status.as_str() {
+                "OVER_QUERY_LIMIT" | "UNKNOWN_ERROR" => FailureKind::Transient,
+                "REQUEST_DENIED" => FailureKind::Unauthorized,
+                _ => FailureKind::Permanent,
+            };
+            return Err(Box::new(ServiceError::new(kind, response.error_message.unwrap_or(response.status))));
+        }
+        let result = response.results.into_iter().next().ok_or("no results")?;
+
+        let component = |kind: &str| result.address_components.iter().find(|c| c.types.iter().any(|t| t == kind));
+        let long_name = |kind: &str| component(kind).map(|c| c.long_name.clone());
+        let address = Address {
+            road: long_name("route"),
+            neighbourhood: long_name("neighborhood"),
+            suburb: long_name("sublocality"),
+            city: long_name("locality"),
+            town: long_name("postal_town"),
+            village: None,
+            county: long_name("administrative_area_level_2"),
+            state_district: None,
+            state: long_name("administrative_area_level_1"),
+            postcode: long_name("postal_code"),
+            country: long_name("country"),
+            country_code: component("country").map(|c| c.short_name.to_lowercase()),
+        };
+
+        Ok(GeocodeResponse { display_name: result.formatted_address, address })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPS_CO_RESPONSE: &str = r#"{"place_id":1,"licence":"Data © OpenStreetMap contributors","powered_by":"Map Maker: https://maps.co",
+        "osm_type":"way","osm_id":2,"lat":"52.37","lon":"4.89","class":"amenity","type":"cafe","place_rank":30,"importance":0.0001,
+        "addresstype":"amenity","name":"Cafe","display_name":"Cafe, Dam, Amsterdam",
+        "address":{"amenity":"Cafe","road":"Dam","city":"Amsterdam","ISO3166-2-lvl4":"NL-NH","country":"Nederland","country_code":"nl"},
+        "boundingbox":["52.37","52.38","4.89","4.90"]}"#;
+
+    #[test]
+    fn strict_schema_accepts_documented_fields() {
+        let response: GeocodeResponse = serde_json::from_str::<StrictGeocodeResponse>(MAPS_CO_RESPONSE).unwrap().into();
+        assert_eq!(response.address.road.as_deref(), Some("Dam"));
+        assert_eq!(response.address.city.as_deref(), Some("Amsterdam"));
+    }
+
+    #[test]
+    fn strict_schema_rejects_unknown_fields() {
+        let renamed = MAPS_CO_RESPONSE.replace("\"road\"", "\"street\"");
+        assert!(serde_json::from_str::<StrictGeocodeResponse>(&renamed).is_err());
+        let added = MAPS_CO_RESPONSE.replace("\"osm_id\":2,", "\"osm_id\":2,\"category\":\"amenity\",");
+        assert!(serde_json::from_str::<StrictGeocodeResponse>(&added).is_err());
+        // The lenient schema takes both
+        assert!(serde_json::from_str::<GeocodeResponse>(&renamed).is_ok());
+        assert!(serde_json::from_str::<GeocodeResponse>(&added).is_ok());
+    }
+
+    #[test]
+    fn strict_schema_rejects_wrong_types() {
+        let wrong = MAPS_CO_RESPONSE.replace("\"city\":\"Amsterdam\"", "\"city\":5");
+        assert!(serde_json::from_str::<StrictGeocodeResponse>(&wrong).is_err());
+    }
+
+    #[test]
+    fn mapbox_features_map_to_the_address() {
+        let collection: MapboxCollection = serde_json::from_str(r#"{"features": [{"properties": {
+            "name": "Dam 1", "full_address": "Dam 1, 1012 JS Amsterdam, Netherlands",
+            "context": {
+                "address": {"name": "Dam 1", "street_name": "Dam"},
+                "postcode": {"name": "1012 JS"},
+                "place": {"name": "Amsterdam"},
+                "region": {"name": "North Holland"},
+                "country": {"name": "Netherlands", "country_code": "NL"}
+            }}}]}"#).unwrap();
+        let response: GeocodeResponse = collection.features.into_iter().next().unwrap().properties.into();
+        assert_eq!(response.display_name, "Dam 1, 1012 JS Amsterdam, Netherlands");
+        assert_eq!(response.address.road.as_deref(), Some("Dam"));
+        assert_eq!(response.address.city.as_deref(), Some("Amsterdam"));
+        assert_eq!(response.address.state.as_deref(), Some("North Holland"));
+        assert_eq!(response.address.country_code.as_deref(), Some("NL"));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::geo::haversine_km;
+use crate::geocoder::{Address, GeocodeResponse};
+use serde::{Deserialize, Serialize};
+
+/// A named place from the config file, e.g. "Grandma's house", that photos taken inside it are
+/// labeled with instead of whatever the geocoder would return. Either a circle around `lat` and
+/// `lon` with a `radius` in meters, or a `polygon` of `[lat, lon]` corners.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Geofence {
+    pub name: String,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// Radius around `lat` and `lon` in meters
+    pub radius: Option<f64>,
+    pub polygon: Option<Vec<[f64; 2]>>,
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+}
+
+impl Geofence {
+    /// Checks that the place describes exactly one shape.
+    pub fn validate(&self) -> Result<(), String> {
+        let circle = (self.lat, self.lon, self.radius);
+        match (circle, &self.polygon) {
+            ((Some(_), Some(_), Some(radius)), None) if radius > 0.0 => Ok(()),
+            ((None, None, None), Some(polygon)) if polygon.len() >= 3 => Ok(()),
+            ((None, None, None), Some(_)) => Err(format!("place {:?} needs at least 3 polygon corners", self.name)),
+            _ => Err(format!("place {:?} needs either lat, lon and a positive radius, or a polygon", self.name)),
+        }
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        if let (Some(center_lat), Some(center_lon), Some(radius)) = (self.lat, self.lon, self.radius) {
+            return haversine_km(center_lat, center_lon, lat, lon) * 1000.0 <= radius;
+        }
+        self.polygon.as_deref().is_some_and(|polygon| in_polygon(polygon, lat, lon))
+    }
+
+    /// The response the geocoder would have given if it knew this place.
+    pub fn response(&self) -> GeocodeResponse {
+        let display_name = match &self.country {
+            Some(country) => format!("{}, {}", self.name, country),
+            None => self.name.clone(),
+        };
+        GeocodeResponse {
+            display_name,
+            address: Address {
+                city: Some(self.name.clone()),
+                country: self.country.clone(),
+                country_code: self.country_code.as_deref().map(str::to_lowercase),
+                ..Address::default()
+            },
+        }
+    }
+}
+
+/// The first of the places that contains the coordinates.
+pub fn find(places: &[Geofence], lat: f64, lon: f64) -> Option<&Geofence> {
+    places.iter().find(|place| place.contains(lat, lon))
+}
+
+// Ray casting, treating latitude and longitude as plane coordinates, which is accurate enough for
+// the neighbourhood-sized areas this is meant for
+fn in_polygon(polygon: &[[f64; 2]], lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut previous = polygon[polygon.len() - 1];
+    for &corner in polygon {
+        let ([lat1, lon1], [lat2, lon2]) = (previous, corner);
+        if (lat1 > lat) != (lat2 > lat) && lon < lon1 + (lat - lat1) / (lat2 - lat1) * (lon2 - lon1) {
+            inside = !inside;
+        }
+        previous = corner;
+    }
+    inside
+}
@@ -0,0 +1,222 @@
+use crate::datetime::parse_iso8601;
+use quick_xml::events::Event;
+use quick_xml::{Reader, XmlVersion};
+use crate::metadata::{PhotoMetadata, PositionSource};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Positions on a GPX track are only interpolated between points this close together in time, in
+// seconds. Across longer gaps (the logger was off, or indoors) only a nearby point is trusted.
+const MAX_INTERPOLATION_GAP: f64 = 30.0 * 60.0;
+
+// How far in time a photo may be from the nearest track point when it can't be interpolated
+const MAX_SNAP_DISTANCE: f64 = 5.0 * 60.0;
+
+#[derive(Debug, Clone, Copy)]
+struct TrackPoint {
+    time: f64,
+    lat: f64,
+    lon: f64,
+}
+
+/// Timestamped positions, from GPX files or from other photos, used to locate photos that have a
+/// capture time but no GPS position.
+#[derive(Debug)]
+pub struct TrackLog {
+    points: Vec<TrackPoint>,
+    /// Seconds added to a capture time before looking it up, to correct for camera clock drift
+    offset: i64,
+    max_gap: f64,
+    max_snap: f64,
+    source: PositionSource,
+}
+
+impl TrackLog {
+    /// Loads the given GPX files, and every `.gpx` file inside any directory among them.
+    pub fn load(paths: &[PathBuf], offset: i64) -> Result<TrackLog, Box<dyn std::error::Error + Send + Sync>> {
+        let mut files = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                let mut entries = fs::read_dir(path)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                entries.retain(|path| is_gpx(path));
+                entries.sort();
+                files.extend(entries);
+            } else {
+                files.push(path.clone());
+            }
+        }
+
+        let mut points = Vec::new();
+        for path in &files {
+            let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let before = points.len();
+            parse_track_points(&contents, &mut points).map_err(|e| format!("{}: {}", path.display(), e))?;
+            if points.len() == before {
+                return Err(format!("{}: no timestamped track points found", path.display()).into());
+            }
+        }
+
+        points.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(TrackLog {
+            points,
+            offset,
+            max_gap: MAX_INTERPOLATION_GAP,
+            max_snap: MAX_SNAP_DISTANCE,
+            source: PositionSource::Track,
+        })
+    }
+
+    /// Builds a track out of the photos that do have an embedded position. Positions are only
+    /// interpolated between two photos at most `max_gap` seconds apart, never extrapolated.
+    pub fn from_neighbors<'a>(metadata: impl IntoIterator<Item = &'a PhotoMetadata>, max_gap: f64) -> TrackLog {
+        let mut points = metadata.into_iter()
+            .filter(|metadata| metadata.position_source == PositionSource::Embedded)
+            .filter_map(|metadata| Some(TrackPoint { time: metadata.timestamp?, lat: metadata.lat, lon: metadata.lon }))
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        TrackLog { points, offset: 0, max_gap, max_snap: 0.0, source: PositionSource::Neighbors }
+    }
+
+    pub fn source(&self) -> PositionSource {
+        self.source
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Position at the given capture time (seconds since the Unix epoch, UTC, before the clock
+    /// offset is applied), or None when the track doesn't cover it.
+    pub fn position_at(&self, capture_time: f64) -> Option<(f64, f64)> {
+        let time = capture_time + self.offset as f64;
+        let next = self.points.partition_point(|point| point.time < time);
+        let before = next.checked_sub(1).and_then(|i| self.points.get(i));
+        let after = self.points.get(next);
+
+        match (before, after) {
+            (Some(before), Some(after)) if after.time - before.time <= self.max_gap => {
+                Some(interpolate(before, after, time))
+            }
+            _ => [before, after].into_iter()
+                .flatten()
+                .filter(|point| (point.time - time).abs() <= self.max_snap)
+                .min_by(|a, b| (a.time - time).abs().total_cmp(&(b.time - time).abs()))
+                .map(|point| (point.lat, point.lon)),
+        }
+    }
+}
+
+fn interpolate(before: &TrackPoint, after: &TrackPoint, time: f64) -> (f64, f64) {
+    let span = after.time - before.time;
+    let t = if span > 0.0 { (time - before.time) / span } else { 0.0 };
+
+    // Go the short way around when the track crosses the antimeridian
+    let mut delta_lon = after.lon - before.lon;
+    if delta_lon > 180.0 {
+        delta_lon -= 360.0;
+    } else if delta_lon < -180.0 {
+        delta_lon += 360.0;
+    }
+
+    let lat = before.lat + (after.lat - before.lat) * t;
+    let mut lon = before.lon + delta_lon * t;
+    if lon > 180.0 {
+        lon -= 360.0;
+    } else if lon < -180.0 {
+        lon += 360.0;
+    }
+    (lat, lon)
+}
+
+// Collects every <trkpt> that has both a position and a <time>
+fn parse_track_points(contents: &str, points: &mut Vec<TrackPoint>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = Reader::from_str(contents);
+    let mut current: Option<(f64, f64)> = None;
+    let mut in_time = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) if element.local_name().as_ref() == "trkpt" => {
+                let attribute = |name: &str| -> Option<f64> {
+                    element.try_get_attribute(name).ok()??
+                        .normalized_value(XmlVersion::Implicit1_0).ok()?
+                        .trim()
+                        .parse()
+                        .ok()
+                };
+                current = attribute("lat").zip(attribute("lon"));
+            }
+            Event::Start(element) if element.local_name().as_ref() == "time" => in_time = current.is_some(),
+            Event::Text(text) if in_time => {
+                if let (Some((lat, lon)), Some(time)) = (current, parse_iso8601(&text.xml10_content())) {
+                    points.push(TrackPoint { time, lat, lon });
+                }
+            }
+            Event::End(element) => match element.local_name().as_ref() {
+                "time" => in_time = false,
+                "trkpt" => current = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a duration such as "90", "-2h", "1h30m", "+45s" or "1y" into seconds. Days, weeks and
+/// years of 365 days are written "d", "w" and "y".
+pub fn parse_duration(value: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid duration \"{}\", expected e.g. \"-2h\", \"1h30m\", \"45s\" or \"30d\"", value);
+    let (sign, rest) = match value.trim() {
+        rest if rest.starts_with('-') => (-1, &rest[1..]),
+        rest => (1, rest.strip_prefix('+').unwrap_or(rest)),
+    };
+
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    // A bare number is taken to be seconds
+    if let Ok(seconds) = rest.parse::<i64>() {
+        return Ok(sign * seconds);
+    }
+
+    let mut total = 0;
+    let mut number = String::new();
+    for c in rest.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'y' => 365 * 86400,
+            'w' => 7 * 86400,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        total += number.parse::<i64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(sign * total)
+}
+
+fn is_gpx(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("gpx"))
+}
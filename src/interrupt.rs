@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Listens for Ctrl-C. The first press asks the run to stop at the next safe point, a second one
+/// quits right away.
+pub fn install() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if REQUESTED.swap(true, Ordering::SeqCst) {
+                warn!("Interrupted again, quitting immediately.");
+                std::process::exit(130);
+            }
+            warn!("Interrupted, stopping after the current file. Press Ctrl-C again to quit immediately.");
+        }
+    });
+}
+
+/// Asks the run to stop at the next safe point, for when Ctrl-C arrives as a key press instead of
+/// a signal, such as while a full-screen view has the terminal.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether the user asked the run to stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
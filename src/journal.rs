@@ -0,0 +1,208 @@
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+pub const JOURNAL_FILE_NAME: &str = ".image-labeler-journal.json";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// SHA-256 of the file at `to` once the run was done with it, when recorded with --checksums
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct JournalRun {
+    pub started_at: u64,
+    pub renames: Vec<JournalEntry>,
+}
+
+/// Record of every rename performed in a directory, grouped per run so they can be undone.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    runs: Vec<JournalRun>,
+}
+
+impl Journal {
+    pub fn load(dir: &Path) -> std::io::Result<Journal> {
+        let path = paths::canonicalize(dir)?.join(JOURNAL_FILE_NAME);
+        let runs = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Journal { path, runs })
+    }
+
+    pub fn begin_run(&mut self) {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.runs.push(JournalRun { started_at, renames: Vec::new() });
+    }
+
+    /// Appends a rename to the current run and writes the journal to disk immediately, so an
+    /// interrupted run can still be undone.
+    pub fn record(&mut self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        if self.runs.is_empty() {
+            self.begin_run();
+        }
+
+        // The journal lives inside the processed directory, which may itself just have been renamed
+        self.relocate(&from, &to);
+
+        if let Some(run) = self.runs.last_mut() {
+            run.renames.push(JournalEntry { from, to, sha256: None });
+        }
+        self.save()
+    }
+
+    /// Whether `path` is where one of the recorded renames put a file, i.e. it was labeled before.
+    pub fn is_rename_target(&self, path: &Path) -> bool {
+        let Ok(path) = paths::canonicalize(path) else {
+            return false;
+        };
+        self.runs.iter().flat_map(|run| &run.renames).any(|entry| entry.to == path)
+    }
+
+    /// Records the checksum of the file the current run renamed to `to`. Saved with the journal.
+    pub fn set_checksum(&mut self, to: &Path, sha256: &str) {
+        let Ok(to) = paths::canonicalize(to) else {
+            return;
+        };
+        let entries = self.runs.last_mut().into_iter().flat_map(|run| run.renames.iter_mut());
+        if let Some(entry) = entries.filter(|entry| entry.to == to).last() {
+            entry.sha256 = Some(sha256.to_string());
+        }
+    }
+
+    /// The latest checksum recorded for every file that hasn't been renamed again since.
+    pub fn checksums(&self) -> Vec<(PathBuf, String)> {
+        let mut renamed_later = HashSet::new();
+        let mut checksums = Vec::new();
+        for entry in self.runs.iter().rev().flat_map(|run| run.renames.iter().rev()) {
+            if let Some(sha256) = entry.sha256.as_ref().filter(|_| !renamed_later.contains(&entry.to)) {
+                checksums.push((entry.to.clone(), sha256.clone()));
+            }
+            renamed_later.insert(entry.from.clone());
+            renamed_later.insert(entry.to.clone());
+        }
+        checksums.reverse();
+        checksums
+    }
+
+    /// Removes and returns the most recent run that still has renames to undo.
+    pub fn pop_run(&mut self) -> Option<JournalRun> {
+        while let Some(run) = self.runs.pop() {
+            if !run.renames.is_empty() {
+                return Some(run);
+            }
+        }
+        None
+    }
+
+    pub fn push_run(&mut self, run: JournalRun) {
+        self.runs.push(run);
+    }
+
+    /// Updates the journal's own location after `from` was renamed to `to`.
+    pub fn relocate(&mut self, from: &Path, to: &Path) {
+        if let Ok(rest) = self.path.strip_prefix(from) {
+            self.path = to.join(rest);
+        }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let runs = self.runs.iter().filter(|r| !r.renames.is_empty()).collect::<Vec<_>>();
+        fs::write(&self.path, serde_json::to_string_pretty(&runs)?)
+    }
+}
+
+/// Reverts the most recent run recorded in the directory's journal. Renames that can't be reverted
+/// stay in the journal so they can be retried.
+pub fn undo(dir: &Path) -> std::io::Result<()> {
+    let mut journal = Journal::load(dir)?;
+    let Some(mut run) = journal.pop_run() else {
+        info!("Nothing to undo.");
+        return Ok(());
+    };
+
+    let mut failed = Vec::new();
+    let mut restored = 0;
+    let mut rename_error = None;
+
+    // Replay in reverse so directory renames are undone before the files inside them
+    while let Some(entry) = run.renames.pop() {
+        if !entry.to.exists() {
+            error!("  Error: {:?} no longer exists, skipping.", entry.to);
+            failed.push(entry);
+            continue;
+        }
+
+        if entry.from.exists() {
+            error!("  Error: {:?} already exists, skipping.", entry.from);
+            failed.push(entry);
+            continue;
+        }
+
+        info!("Restoring: {:?} -> {:?}", entry.to, entry.from);
+        if let Err(e) = fs::rename(&entry.to, &entry.from) {
+            error!("  Error: Couldn't restore {:?}: {}", entry.to, e);
+            failed.push(entry);
+            rename_error.get_or_insert(e);
+            continue;
+        }
+        journal.relocate(&entry.to, &entry.from);
+        restored += 1;
+    }
+
+    info!("{} renames undone", restored);
+
+    if !failed.is_empty() {
+        // Keep whatever couldn't be restored so it can be retried
+        failed.reverse();
+        run.renames = failed;
+        journal.push_run(run);
+    }
+    // Saved before reporting a failed rename, so what was restored isn't tried again
+    journal.save()?;
+
+    match rename_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Checks that every rename recorded in the directory's journal can still be undone, and returns
+/// how many can't.
+pub fn verify(dir: &Path) -> std::io::Result<usize> {
+    let journal = Journal::load(dir)?;
+    let mut checked = 0;
+    let mut problems = 0;
+    // Files renamed again by a later run are only expected at their latest name
+    let mut renamed_later = HashSet::new();
+
+    for entry in journal.runs.iter().rev().flat_map(|run| run.renames.iter().rev()) {
+        checked += 1;
+        if !renamed_later.contains(&entry.to) && !entry.to.exists() {
+            error!("  Error: {:?} no longer exists, it can't be restored to {:?}.", entry.to, entry.from);
+            problems += 1;
+        } else if entry.from.exists() {
+            error!("  Error: {:?} is taken by another file, {:?} can't be restored.", entry.from, entry.to);
+            problems += 1;
+        }
+        renamed_later.insert(entry.from.clone());
+    }
+
+    info!("{} renames checked, {} can't be undone", checked, problems);
+    Ok(problems)
+}
@@ -0,0 +1,84 @@
+use crate::attributes;
+use crate::trash;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Segment payloads are limited by their 16-bit length field, which includes itself
+pub const MAX_SEGMENT_LEN: usize = 0xFFFF - 2;
+
+pub struct Segment {
+    pub marker: u8,
+    // Offset of the 0xFF that starts the segment
+    pub start: usize,
+    pub payload: std::ops::Range<usize>,
+}
+
+/// Lists the marker segments of a JPEG file before its image data starts.
+pub fn segments(data: &[u8]) -> Result<Vec<Segment>, Box<dyn Error + Send + Sync>> {
+    let mut segments = Vec::new();
+    let mut offset = 2;
+
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return Err("malformed JPEG segment".into());
+        }
+        let marker = data[offset + 1];
+        // Start of scan: everything after this is image data
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let len = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        if len < 2 || offset + 2 + len > data.len() {
+            return Err("malformed JPEG segment".into());
+        }
+        segments.push(Segment { marker, start: offset, payload: offset + 4..offset + 2 + len });
+        offset += 2 + len;
+    }
+
+    Ok(segments)
+}
+
+/// Builds an APP1 segment holding `header` followed by `payload`.
+pub fn app1_segment(header: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&((header.len() + payload.len() + 2) as u16).to_be_bytes());
+    segment.extend_from_slice(header);
+    segment.extend_from_slice(payload);
+    segment
+}
+
+/// Returns `data` with the `existing` segment replaced by `segment`, or with `segment` inserted
+/// after the JFIF header (or at the start) when there is nothing to replace.
+pub fn splice(data: &[u8], segments: &[Segment], existing: Option<&Segment>, segment: &[u8]) -> Vec<u8> {
+    let (start, end) = match existing {
+        Some(existing) => (existing.start, existing.payload.end),
+        None => {
+            let after = segments.iter().find(|s| s.marker == 0xE0).map(|s| s.payload.end).unwrap_or(2);
+            (after, after)
+        }
+    };
+
+    let mut output = Vec::with_capacity(data.len() + segment.len());
+    output.extend_from_slice(&data[..start]);
+    output.extend_from_slice(segment);
+    output.extend_from_slice(&data[end..]);
+    output
+}
+
+/// Overwrites a file by writing a temporary copy next to it and renaming that over the original.
+/// The extended attributes of the original carry over to the new contents. With --use-trash the
+/// original goes to the trash instead of being overwritten.
+pub fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{}.image-labeler-tmp", file_name));
+    fs::write(&temporary, contents)?;
+    let replaced = if path.exists() {
+        attributes::copy_xattrs(path, &temporary).and_then(|_| if trash::is_enabled() { trash::put(path) } else { Ok(()) })
+    } else {
+        Ok(())
+    };
+    replaced.and_then(|_| fs::rename(&temporary, path)).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
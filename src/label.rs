@@ -0,0 +1,344 @@
+use crate::calendar::{month_name, weekday_name};
+use crate::datetime::iso_8601;
+use crate::geocoder::{Address, GeocodeResponse};
+use crate::metadata::PhotoMetadata;
+use crate::plan::short_hash;
+use crate::pluscode;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// An address component that can make up the `{location}` part of a name.
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LocationField {
+    Road,
+    Neighbourhood,
+    Suburb,
+    Village,
+    Town,
+    City,
+    County,
+    StateDistrict,
+    State,
+    Postcode,
+    Country,
+}
+
+impl LocationField {
+    pub fn value(self, address: &Address) -> Option<&str> {
+        match self {
+            LocationField::Road => address.road.as_deref(),
+            LocationField::Neighbourhood => address.neighbourhood.as_deref(),
+            LocationField::Suburb => address.suburb.as_deref(),
+            LocationField::Village => address.village.as_deref(),
+            LocationField::Town => address.town.as_deref(),
+            LocationField::City => address.city.as_deref(),
+            LocationField::County => address.county.as_deref(),
+            LocationField::StateDistrict => address.state_district.as_deref(),
+            LocationField::State => address.state.as_deref(),
+            LocationField::Postcode => address.postcode.as_deref(),
+            LocationField::Country => address.country.as_deref(),
+        }
+    }
+}
+
+/// How specific the place in a name is, from a street to a whole country. Sets both the zoom level
+/// addresses are looked up at and which of their fields make up `{location}`.
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Granularity {
+    Road,
+    Suburb,
+    City,
+    Region,
+    Country,
+}
+
+impl Granularity {
+    /// The Nominatim zoom level that returns addresses down to this level.
+    pub fn zoom(self) -> u8 {
+        match self {
+            Granularity::Road => 17,
+            Granularity::Suburb => 14,
+            Granularity::City => 10,
+            Granularity::Region => 5,
+            Granularity::Country => 3,
+        }
+    }
+
+    pub fn location_fields(self) -> Vec<LocationField> {
+        match self {
+            // The town or city followed by the road, as without any fields
+            Granularity::Road => Vec::new(),
+            Granularity::Suburb => vec![LocationField::Suburb, LocationField::Town, LocationField::City, LocationField::Village],
+            Granularity::City => vec![LocationField::Town, LocationField::City, LocationField::Village],
+            Granularity::Region => vec![LocationField::State, LocationField::Country],
+            Granularity::Country => vec![LocationField::Country],
+        }
+    }
+}
+
+pub fn location_label(response: &GeocodeResponse, fields: &[LocationField]) -> String {
+    format!("{}, {}", country_code(response), location_text(response, fields))
+}
+
+pub fn country_code(response: &GeocodeResponse) -> String {
+    response.address.country_code.as_deref().unwrap_or("unknown").to_uppercase()
+}
+
+pub fn town_or_city(response: &GeocodeResponse) -> Option<&str> {
+    response.address.town.as_deref()
+        .or(response.address.city.as_deref())
+        .or(response.address.village.as_deref())
+}
+
+/// The place a response describes, made of the chosen address fields in order. Without any fields,
+/// or when none of them are known, it's the town or city followed by the road.
+pub fn location_text(response: &GeocodeResponse, fields: &[LocationField]) -> String {
+    let mut chosen: Vec<&str> = Vec::new();
+    for value in fields.iter().filter_map(|field| field.value(&response.address)) {
+        // Small places are often their own suburb or county
+        if !chosen.contains(&value) {
+            chosen.push(value);
+        }
+    }
+    if !chosen.is_empty() {
+        return sanitize(&chosen.join(", "));
+    }
+
+    let road = response.address.road.as_deref();
+    let town_or_city = town_or_city(response);
+    let country = response.address.country.as_deref();
+
+    let mut location_parts = Vec::new();
+
+    if let Some(place) = town_or_city {
+        location_parts.push(place.to_string());
+    }
+
+    if let Some(r) = road {
+        location_parts.push(r.to_string());
+    }
+
+    if location_parts.is_empty() && let Some(c) = country {
+        location_parts.push(c.to_string());
+    }
+
+    let location = if location_parts.is_empty() {
+        response.display_name.clone()
+    } else {
+        location_parts.join(", ")
+    };
+    
+    sanitize(&location)
+}
+
+// Sanitize a value for use in a filename
+pub fn sanitize(value: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == ',' { c } else { '_' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How names with accents or non-Latin scripts are spelled in filenames.
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transliteration {
+    /// Spell out German umlauts and ß, e.g. "München" becomes "Muenchen"
+    Umlauts,
+    /// Replace every non-ASCII character with its closest ASCII spelling, e.g. "München" becomes
+    /// "Munchen" and "Київ" becomes "Kyyiv"
+    Ascii,
+}
+
+pub fn transliterate(value: &str, transliteration: Transliteration) -> String {
+    match transliteration {
+        Transliteration::Umlauts => value.chars()
+            .map(|c| match c {
+                'ä' => "ae".to_string(),
+                'ö' => "oe".to_string(),
+                'ü' => "ue".to_string(),
+                'Ä' => "Ae".to_string(),
+                'Ö' => "Oe".to_string(),
+                'Ü' => "Ue".to_string(),
+                'ß' => "ss".to_string(),
+                c => c.to_string(),
+            })
+            .collect(),
+        // Whatever has no ASCII spelling is dropped, so the result is always plain ASCII
+        Transliteration::Ascii => deunicode::deunicode_with_tofu(value, ""),
+    }
+}
+
+/// Transliterates every value filled into the templates.
+pub fn transliterate_values(values: &mut HashMap<&str, String>, transliteration: Transliteration) {
+    for value in values.values_mut() {
+        *value = transliterate(value, transliteration);
+    }
+}
+
+/// How `{seq}` is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqFormat {
+    /// A counter zero-padded to this many digits
+    Counter { width: usize },
+    /// The file's short content hash, as after a collision with `--on-collision hash`, which stays
+    /// the same whatever order files are processed in
+    Hash,
+}
+
+impl Default for SeqFormat {
+    fn default() -> Self {
+        SeqFormat::Counter { width: 1 }
+    }
+}
+
+/// Parses a printf-style format such as "%d" or "%04d", or "hash".
+pub fn parse_seq_format(value: &str) -> Result<SeqFormat, String> {
+    if value.eq_ignore_ascii_case("hash") {
+        return Ok(SeqFormat::Hash);
+    }
+    let width = value.strip_prefix('%')
+        .and_then(|rest| rest.strip_suffix('d'))
+        .and_then(|width| match width {
+            "" => Some(1),
+            width if width.starts_with('0') => width.parse::<usize>().ok().filter(|&width| (1..=10).contains(&width)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("invalid sequence format {:?}, expected e.g. \"%d\", \"%04d\" or \"hash\"", value))?;
+    Ok(SeqFormat::Counter { width })
+}
+
+/// Hands out the `{seq}` numbers, either as one counter for the whole run or one per capture date.
+#[derive(Debug, Default)]
+pub struct Sequence {
+    per_day: bool,
+    format: SeqFormat,
+    start: u32,
+    step: u32,
+    counters: HashMap<String, u32>,
+}
+
+impl Sequence {
+    pub fn new(per_day: bool, format: SeqFormat) -> Sequence {
+        Sequence { per_day, format, start: 1, step: 1, counters: HashMap::new() }
+    }
+
+    /// Counts from `start` up in steps of `step`, e.g. 10, 20, 30, instead of from 1.
+    pub fn starting_at(mut self, start: u32, step: u32) -> Sequence {
+        self.start = start;
+        self.step = step.max(1);
+        self
+    }
+
+    /// Returns the next number for a file captured on `date`, zero-padded to the configured width,
+    /// or the hash of the file at `path`.
+    pub fn next(&mut self, date: &str, path: &Path) -> std::io::Result<String> {
+        let width = match self.format {
+            SeqFormat::Hash => return short_hash(path),
+            SeqFormat::Counter { width } => width,
+        };
+        let key = if self.per_day { date } else { "" };
+        let counter = self.counters.entry(key.to_string()).or_insert(0);
+        *counter += 1;
+        let seq = self.start as u64 + (*counter as u64 - 1) * self.step as u64;
+        Ok(format!("{:0width$}", seq, width = width))
+    }
+
+    /// Carries on after `seq`, the number an earlier run gave a file captured on `date`, so new
+    /// files don't reuse it. Files without a known date can't be counted per day.
+    pub fn continue_after(&mut self, date: Option<&str>, seq: u32) {
+        let key = match date {
+            _ if !self.per_day => "",
+            Some(date) => date,
+            None => return,
+        };
+        if seq < self.start {
+            return;
+        }
+        let counter = self.counters.entry(key.to_string()).or_insert(0);
+        *counter = (*counter).max((seq - self.start) / self.step + 1);
+    }
+
+    /// Returns the number just handed out for `date`, so the next file gets it instead.
+    pub fn give_back(&mut self, date: &str) {
+        let key = if self.per_day { date } else { "" };
+        if let Some(counter) = self.counters.get_mut(key) {
+            *counter = counter.saturating_sub(1);
+        }
+    }
+}
+
+/// Capture time in ISO 8601 without an offset, e.g. "2023-10-24T12:00:00", or just the date when
+/// no time was recorded.
+pub fn iso_date_time(metadata: &PhotoMetadata) -> String {
+    iso_8601(&metadata.date, metadata.time.as_deref())
+}
+
+/// A human readable title such as "Amsterdam, 24 October 2023".
+pub fn suggested_title(metadata: &PhotoMetadata, response: &GeocodeResponse) -> String {
+    let place = town_or_city(response)
+        .or(response.address.country.as_deref())
+        .unwrap_or(&response.display_name);
+    let month = metadata.date[4..6].parse::<usize>().ok().and_then(|month| MONTH_NAMES.get(month.wrapping_sub(1)));
+    let day = metadata.date[6..8].trim_start_matches('0');
+
+    match month {
+        Some(month) => format!("{}, {} {} {}", place, day, month, &metadata.date[..4]),
+        None => place.to_string(),
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+/// The value of every placeholder for a file, with month and weekday names in `language`.
+pub fn template_values<'a>(
+    path: &Path,
+    metadata: &PhotoMetadata,
+    response: &GeocodeResponse,
+    sequence: &str,
+    location_fields: &[LocationField],
+    language: &str,
+) -> HashMap<&'a str, String> {
+    let address = &response.address;
+    let optional = |value: Option<&str>| value.map(sanitize).unwrap_or_default();
+    let orig_name = path.file_stem().and_then(|s| s.to_str());
+    let date_part = |range: std::ops::Range<usize>| metadata.date.get(range).unwrap_or_default().to_string();
+    let month_name = date_part(4..6).parse::<u32>().ok().and_then(|month| month_name(month, language)).unwrap_or_default();
+    let weekday = weekday_name(&metadata.date, language).unwrap_or_default();
+
+    HashMap::from([
+        ("date", metadata.date.clone()),
+        ("year", date_part(0..4)),
+        ("month", date_part(4..6)),
+        ("month_name", month_name.to_string()),
+        ("day", date_part(6..8)),
+        ("weekday", weekday.to_string()),
+        ("time", metadata.time.clone().unwrap_or_default()),
+        ("seq", sequence.to_string()),
+        ("location", location_text(response, location_fields)),
+        ("city", optional(town_or_city(response))),
+        ("road", optional(address.road.as_deref())),
+        ("neighbourhood", optional(address.neighbourhood.as_deref())),
+        ("suburb", optional(address.suburb.as_deref())),
+        ("county", optional(address.county.as_deref())),
+        ("state", optional(address.state.as_deref())),
+        ("postcode", optional(address.postcode.as_deref())),
+        ("country", optional(address.country.as_deref())),
+        ("country_code", country_code(response)),
+        ("camera", optional(metadata.camera.as_deref())),
+        ("make", optional(metadata.make.as_deref())),
+        ("lens", optional(metadata.lens.as_deref())),
+        ("orig_name", optional(orig_name)),
+        ("pluscode", pluscode::encode(metadata.lat, metadata.lon)),
+        ("altitude", metadata.altitude.map(|meters| format!("{}m", meters.round() as i64)).unwrap_or_default()),
+    ])
+}
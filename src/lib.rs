@@ -0,0 +1,65 @@
+//! Renames photos and videos after where and when they were taken, using their embedded GPS
+//! coordinates and a reverse geocoder.
+
+pub mod attributes;
+pub mod cache;
+pub mod calendar;
+pub mod checkpoint;
+pub mod checksum;
+pub mod config;
+pub mod datetime;
+pub mod duplicate;
+pub mod embed;
+pub mod error;
+pub mod event;
+pub mod execute;
+pub mod exif_write;
+pub mod extract;
+pub mod filename;
+pub mod filter;
+pub mod fixture;
+pub mod format;
+pub mod gallery;
+pub mod geo;
+pub mod geofence;
+pub mod geocoder;
+pub mod gpx;
+pub mod interrupt;
+pub mod journal;
+pub mod jpeg;
+pub mod label;
+pub mod logging;
+pub mod map;
+pub mod metadata;
+pub mod metrics;
+pub mod offline;
+pub mod output;
+pub mod overrides;
+pub mod paths;
+pub mod plan;
+pub mod pluscode;
+pub mod quota;
+pub mod rate_limit;
+pub mod resolve;
+pub mod restore;
+pub mod rotate;
+pub mod retry;
+pub mod scan;
+pub mod serve;
+pub mod session;
+pub mod stats;
+pub mod suspicious;
+pub mod tags;
+pub mod template;
+pub mod timezone;
+pub mod trash;
+pub mod tui;
+pub mod video;
+pub mod watch;
+pub mod what3words;
+pub mod xmp;
+
+pub use fixture::{FixtureGeocoder, StaticGeocoder};
+pub use geocoder::{build_geocoder, GeocodeResponse, GeocoderOptions, ReverseGeocoder};
+pub use metadata::{extract_metadata, PhotoMetadata};
+pub use plan::{execute_plan, RenamePlan};
@@ -0,0 +1,167 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+// The bar currently on screen, which log lines have to be written around
+static PROGRESS: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// A log line, and whether it goes to stderr
+type Line = (bool, Vec<u8>);
+
+// Log lines held back while a full-screen view has the terminal
+static HELD: Mutex<Option<Vec<Line>>> = Mutex::new(None);
+
+/// How much to print, from `-q` to `-vv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Warnings and errors only, without progress bars
+    Quiet,
+    /// What happens to each file
+    Normal,
+    /// Also how each file's position and date were found
+    Verbose,
+    /// Everything, including every geocoding request, with timestamps and levels
+    Trace,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: u8) -> Verbosity {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Trace,
+        }
+    }
+}
+
+/// Sets up logging. Messages go to stdout and warnings and errors to stderr, unless stdout is
+/// reserved for machine-readable records, in which case everything goes to stderr.
+pub fn init(verbosity: Verbosity, machine_readable: bool) {
+    QUIET.store(verbosity == Verbosity::Quiet, Ordering::SeqCst);
+    let level = match verbosity {
+        Verbosity::Quiet => Level::WARN,
+        Verbosity::Normal => Level::INFO,
+        Verbosity::Verbose => Level::DEBUG,
+        Verbosity::Trace => Level::TRACE,
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(LogWriters { machine_readable });
+    if verbosity == Verbosity::Trace {
+        builder.with_ansi(std::io::stderr().is_terminal()).init();
+    } else {
+        // The messages carry their own "Error:" and "Warning:" prefixes
+        builder.without_time().with_level(false).with_target(false).with_ansi(false).init();
+    }
+}
+
+/// Holds back log lines, and hides progress bars, until `release` is called. For as long as
+/// something else draws the whole terminal.
+pub fn hold() {
+    HELD.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(Vec::new);
+}
+
+/// Writes out the log lines held back since `hold`, and prints new ones as they come again.
+pub fn release() {
+    let held = HELD.lock().unwrap_or_else(|e| e.into_inner()).take();
+    for (to_stderr, line) in held.into_iter().flatten() {
+        // There is nowhere left to report a failure to write a log line
+        let _ = if to_stderr { std::io::stderr().write_all(&line) } else { std::io::stdout().write_all(&line) };
+    }
+}
+
+/// A progress bar on stderr that log lines are printed around. It's hidden when stderr isn't a
+/// terminal or with `-q`, and removed again when dropped.
+pub struct Progress {
+    bar: ProgressBar,
+}
+
+impl Progress {
+    pub fn new(len: usize, message: &str) -> Progress {
+        let bar = ProgressBar::with_draw_target(Some(len as u64), ProgressDrawTarget::stderr());
+        if QUIET.load(Ordering::SeqCst) || HELD.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        bar.set_style(
+            ProgressStyle::with_template("{msg} [{bar:30}] {pos}/{len} (ETA {eta}) {prefix}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("=> "),
+        );
+        bar.set_message(message.to_string());
+        *PROGRESS.lock().unwrap_or_else(|e| e.into_inner()) = Some(bar.clone());
+        Progress { bar }
+    }
+
+    pub fn bar(&self) -> &ProgressBar {
+        &self.bar
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        PROGRESS.lock().unwrap_or_else(|e| e.into_inner()).take();
+        self.bar.finish_and_clear();
+    }
+}
+
+struct LogWriters {
+    machine_readable: bool,
+}
+
+impl<'a> MakeWriter<'a> for LogWriters {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> LogWriter {
+        LogWriter { to_stderr: self.machine_readable, buffer: Vec::new() }
+    }
+
+    fn make_writer_for(&'a self, metadata: &Metadata<'_>) -> LogWriter {
+        LogWriter { to_stderr: self.machine_readable || *metadata.level() <= Level::WARN, buffer: Vec::new() }
+    }
+}
+
+// Holds on to a log line until it's complete so it can be written in one go, without tearing
+// through a progress bar
+struct LogWriter {
+    to_stderr: bool,
+    buffer: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if let Some(held) = HELD.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            held.push((self.to_stderr, std::mem::take(&mut self.buffer)));
+            return;
+        }
+        let write = || {
+            // There is nowhere left to report a failure to write a log line
+            let _ = if self.to_stderr {
+                std::io::stderr().write_all(&self.buffer)
+            } else {
+                std::io::stdout().write_all(&self.buffer)
+            };
+        };
+        match PROGRESS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(bar) => bar.suspend(write),
+            None => write(),
+        }
+    }
+}
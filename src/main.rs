@@ -1,197 +1,2342 @@
-use clap::Parser;
-use exif::{In, Tag};
-use serde::Deserialize;
+use chrono_tz::Tz;
+use clap::{CommandFactory, Parser, Subcommand};
+use image_labeler::attributes;
+use image_labeler::cache::{self, GeocodeCache};
+use image_labeler::checkpoint::Checkpoint;
+use image_labeler::checksum;
+use image_labeler::config::Config;
+use image_labeler::filename::TargetFs;
+use image_labeler::gallery;
+use image_labeler::filter::{parse_area, parse_date, parse_size, Area, FileSelection, PhotoFilter};
+use image_labeler::geofence::{self, Geofence};
+use image_labeler::geocoder::{GeocodeResponse, GeocoderOptions, NetworkOptions, Provider, ReverseGeocoder};
+use image_labeler::duplicate::{self, DuplicateFinder, OnDuplicate};
+use image_labeler::embed;
+use image_labeler::error::RunError;
+use image_labeler::execute::{execute_chunk, Finishing, Writes};
+use image_labeler::exif_write::write_gps_position;
+use image_labeler::extract::{self, ExtractFormat};
+use image_labeler::datetime::parse_clock_offset;
+use image_labeler::gpx::{parse_duration, TrackLog};
+use image_labeler::interrupt;
+use image_labeler::journal::{self, Journal};
+use image_labeler::event::{detect_events, same_event};
+use image_labeler::what3words::What3Words;
+use image_labeler::label::{iso_date_time, parse_seq_format, location_text, sanitize, suggested_title, template_values, transliterate_values, Granularity, LocationField, SeqFormat, Sequence, Transliteration};
+use image_labeler::metadata::{capture_date, extract_metadata, ClockOffsets, DateFallback, MetadataOptions, MissingMetadata, PhotoMetadata, PositionSource};
+use image_labeler::metrics::{Metrics, Stage};
+use image_labeler::logging::{self, Progress, Verbosity};
+use image_labeler::output::{self, FileRecord, FileStatus, OutputFormat, Report, Summary};
+use image_labeler::overrides::Overrides;
+use image_labeler::paths;
+use image_labeler::plan::{execute_plan, target_path, OnCollision, COLLISION_SUFFIX_LEN, RenamePlan, Transfer};
+use image_labeler::resolve::resolve_locations;
+use image_labeler::restore;
+use image_labeler::rotate;
+use image_labeler::retry::RetryPolicy;
+use image_labeler::scan::{copy_order, is_sidecar, remove_hard_links, scan_directory, FileFilter, FileGroup};
+use image_labeler::serve;
+use image_labeler::session::{Session, SessionOptions};
+use image_labeler::suspicious::suspicious_positions;
+use image_labeler::template::{FolderTemplate, GroupBy, Template};
+use image_labeler::trash;
+use image_labeler::tui::{Outcome, ReviewRow, Tui};
+use image_labeler::tags::{Classifier, TagOptions};
+use image_labeler::timezone::{DateTimezone, TimeNormalizer};
+use image_labeler::watch::DirectoryWatcher;
+use image_labeler::xmp::{self, MetadataTarget, XmpProperties};
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use tokio::time::{sleep, Duration};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+// Enough files to keep the geocoder busy, while the locations and renames held for them stay a few
+// megabytes
+const DEFAULT_CHUNK_SIZE: usize = 1000;
+
+// What --rename-directories names a directory after, rendered like a file stem so it fits the
+// target filesystem
+const DIRECTORY_TEMPLATE: &str = "{country_code}, {location}";
+
+const EXIT_CODES: &str = "Exit codes:
+  0    Every file was handled
+  1    The run couldn't start or had to stop, e.g. an invalid template or an unreadable directory
+  2    Invalid options
+  3    Some files couldn't be geocoded, renamed or written to
+  4    The geocoding service rejected the API key
+  5    No files were processed, with --fail-on-empty
+  6    verify found names to fix, or check found changed files
+  7    Stopped at --max-api-calls or the daily quota, continue with --resume
+  130  Stopped with Ctrl-C";
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(after_help = EXIT_CODES)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: Args,
+
+    /// Only print warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more detail; repeat for every geocoding request with timestamps
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Send files that would be overwritten or replaced, e.g. by --write-metadata or --on-duplicate
+    /// link, to the trash or recycle bin first so they can be restored
+    #[arg(long, global = true)]
+    use_trash: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
 struct Args {
-    /// Path to the directory containing JPEG files
-    #[arg(default_value = ".")]
-    path: PathBuf,
+    /// Directories containing photos (JPEG, HEIC, PNG, TIFF, WebP, RAW) or videos (MP4, MOV), or the files
+    /// themselves [default: .]
+    paths: Vec<PathBuf>,
+
+    /// Also process the files and directories listed in this file, one per line, or "-" to read
+    /// them from stdin, e.g. from `find` or `fd`
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Exit with status 5 when no files were processed
+    #[arg(long)]
+    fail_on_empty: bool,
+
+    /// After processing, rename the directory after the most common location of its files
+    #[arg(long)]
+    rename_directories: bool,
+
+    /// Fail when a maps.co or Nominatim response has fields the Nominatim documentation doesn't list,
+    /// lacks the display name or address, or has fields of the wrong type. Only for the maps-co and
+    /// nominatim providers; locations already in the geocode cache aren't checked again
+    #[arg(long)]
+    strict_schema: bool,
+
+    /// What to do when the new filename is already taken
+    #[arg(long, value_enum, default_value_t = OnCollision::Suffix)]
+    on_collision: OnCollision,
+
+    /// Print the resolved address of each file as JSON without renaming anything
+    #[arg(long)]
+    geocode_only: bool,
+
+    /// Set by the stats subcommand: collect the resolved addresses into a breakdown instead
+    #[arg(skip)]
+    stats: bool,
+
+    /// Print a JSON array with what happened to each file once the run is done; progress
+    /// messages go to stderr
+    #[arg(long, conflicts_with_all = ["ndjson", "geocode_only"])]
+    json: bool,
+
+    /// Print a JSON record per file as soon as it's done; progress messages go to stderr
+    #[arg(long, conflicts_with = "geocode_only")]
+    ndjson: bool,
+
+    /// Write a CSV manifest of old and new names with coordinates and addresses, or JSON when
+    /// the file name ends in .json
+    #[arg(long, value_name = "FILE", conflicts_with = "geocode_only")]
+    manifest: Option<PathBuf>,
+
+    /// Write a GeoJSON map with a point per located file, or KML for Google Earth when the file
+    /// name ends in .kml
+    #[arg(long, value_name = "FILE", conflicts_with = "geocode_only")]
+    map: Option<PathBuf>,
+
+    /// Print how long scanning, reading metadata, geocoding and renaming took, the geocode cache
+    /// hit rate and how many requests went to the geocoder once the run is done
+    #[arg(long)]
+    metrics: bool,
+
+    /// Write the timings and geocoder usage of --metrics to a JSON file
+    #[arg(long, value_name = "FILE")]
+    metrics_json: Option<PathBuf>,
+
+    /// Also process files in subdirectories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Maximum depth of subdirectories to descend into when recursing
+    #[arg(long, requires = "recursive")]
+    max_depth: Option<usize>,
+
+    /// Only process files matching this glob, e.g. "IMG_*.JPG". Can be repeated
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Leave out files or directories matching this glob, e.g. "*-edited.jpg". Can be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Only process files of at least this size, e.g. "500KB" or "2MB"
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Only process files of at most this size, e.g. "50MB"
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only process files last modified at least this long ago, e.g. "1y" or "30d", to migrate a
+    /// large archive in stages
+    #[arg(long, value_name = "DURATION", value_parser = parse_positive_duration)]
+    older_than: Option<i64>,
+
+    /// Only process files last modified less than this long ago, e.g. "7d"
+    #[arg(long, value_name = "DURATION", value_parser = parse_positive_duration)]
+    newer_than: Option<i64>,
+
+    /// Also pick up hidden files, resource forks such as "._IMG_1234.jpg", system files like
+    /// Thumbs.db and unfinished downloads ("*.partial", "*.crdownload"), which are skipped by default
+    #[arg(long)]
+    no_default_ignores: bool,
+
+    /// Pick up the files and directories symbolic links point to, instead of skipping the links.
+    /// The links are renamed, not the files they point to
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Only process photos taken on or after this day, e.g. 2023-06-01
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    after: Option<u32>,
+
+    /// Only process photos taken before this day, e.g. 2023-09-01
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    before: Option<u32>,
+
+    /// Only process photos taken within a radius of a point, e.g. "52.37,4.89,25km"
+    #[arg(long, value_name = "LAT,LON,RADIUS", value_parser = parse_area, allow_hyphen_values = true)]
+    within: Option<Area>,
+
+    /// What to do with files whose content is identical to another one in the directory; without
+    /// it duplicates are labeled like any other file
+    #[arg(long, value_enum, value_name = "ACTION")]
+    on_duplicate: Option<OnDuplicate>,
+
+    /// Directory duplicates are moved into with --on-duplicate move-to
+    #[arg(long, value_name = "DIR", required_if_eq("on_duplicate", "move-to"))]
+    duplicates_dir: Option<PathBuf>,
+
+    /// Also relabel files that look like they were labeled by an earlier run
+    #[arg(long)]
+    relabel: bool,
+
+    /// Show what would be renamed without touching any files
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Filename template, e.g. "{date}_{seq}_{country_code}, {location}". Available placeholders:
+    /// {date}, {year}, {month}, {month_name}, {day}, {weekday}, {time}, {seq}, {location}, {city}, {road}, {neighbourhood},
+    /// {suburb}, {county}, {state}, {postcode}, {country}, {country_code}, {camera}, {make}, {lens}, {orig_name}, {event},
+    /// {pluscode}, {w3w}, {altitude}, {poi}, {tags}, {variant}. {month_name} and {weekday} are in the --language.
+    /// {variant} is where edits such as IMG_1234-edited get their suffix, e.g. "_edited", instead of at the end
+    #[arg(long)]
+    template: Option<String>,
+
+    /// Restart the {seq} counter at 1 for every capture date
+    #[arg(long)]
+    seq_per_day: bool,
+
+    /// Zero-pad {seq} to this many digits, e.g. 3 for "001"
+    #[arg(long, default_value_t = 1)]
+    seq_width: usize,
+
+    /// How {seq} is written: a printf-style number such as "%04d", or "hash" for the short content
+    /// hash --on-collision hash appends, which gives the same names whatever order the files are
+    /// processed in
+    #[arg(long, value_name = "FORMAT", value_parser = parse_seq_format, conflicts_with = "seq_width")]
+    seq_format: Option<SeqFormat>,
+
+    /// Number the first file gets
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    seq_start: u32,
+
+    /// How much {seq} goes up by from one file to the next, e.g. 10 to leave room for files added later
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    seq_step: u32,
+
+    /// Longest break between two photos of the same {event}, e.g. "3h". Events are named after
+    /// the date they started and the place most of their photos were taken, e.g. "2023-10-24 Amsterdam"
+    #[arg(long, value_parser = parse_positive_duration, default_value = "3h")]
+    event_gap: i64,
+
+    /// Organize files into this directory instead of renaming them in place
+    #[arg(long, conflicts_with = "rename_directories")]
+    output_dir: Option<PathBuf>,
+
+    /// Whether to copy, move or hard link files into the output directory [default: copy]
+    #[arg(long, value_enum)]
+    organize: Option<Transfer>,
+
+    /// Layout of the output directory, e.g. "{year}/{month} - {month_name}/{city}". Takes the same
+    /// placeholders as --template
+    #[arg(long)]
+    folder_template: Option<String>,
+
+    /// Sort files into a directory per camera model first, above the --folder-template layout,
+    /// e.g. to keep a phone's photos apart from a DSLR's
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+
+    /// Number of decimals coordinates are rounded to when looking up cached locations
+    #[arg(long, default_value_t = 4)]
+    cache_precision: usize,
+
+    /// Always query the geocoder instead of using cached locations
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Reverse geocoding service to use [default: maps-co]
+    #[arg(long, value_enum)]
+    provider: Option<Provider>,
+
+    /// Address of a self-hosted Nominatim instance to geocode with, e.g. "http://localhost:8080".
+    /// Selects the nominatim provider unless another one is given; no API key or rate limit is
+    /// needed
+    #[arg(long, value_name = "URL")]
+    geocoder_url: Option<String>,
+
+    /// Nominatim zoom level addresses are looked up at, from 3 (country) to 18 (building)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(3..=18))]
+    zoom: Option<u8>,
+
+    /// API key for the selected provider
+    #[arg(long, env = "IMAGE_LABELER_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+
+    /// what3words API key, needed for {w3w}
+    #[arg(long, env = "WHAT3WORDS_API_KEY", hide_env_values = true)]
+    w3w_key: Option<String>,
+
+    /// ONNX image classifier, e.g. MobileNet, that describes what photos show for {tags} and
+    /// their keywords. Runs locally and needs a build with `--features tags`
+    #[arg(long, requires = "tag_labels")]
+    tag_model: Option<PathBuf>,
+
+    /// Class names of the --tag-model, one per line
+    #[arg(long)]
+    tag_labels: Option<PathBuf>,
+
+    /// Most tags a photo gets
+    #[arg(long, default_value_t = 3)]
+    max_tags: usize,
+
+    /// How sure the classifier has to be of a tag, from 0 to 1
+    #[arg(long, value_parser = parse_confidence, default_value_t = 0.2)]
+    tag_confidence: f32,
+
+    /// Config file to use instead of ~/.config/image-labeler/config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Use the settings of a [profile.NAME] table in the config file, e.g. "travel". Options given
+    /// here take precedence over it
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// GeoNames dump (e.g. cities1000.txt) used by the offline provider
+    #[arg(long)]
+    offline_dataset: Option<PathBuf>,
+
+    /// Recorded responses to replay without network access, e.g. an exported geocode cache or
+    /// the output of --geocode-only. Selects the fixture provider unless another one is given
+    #[arg(long, value_name = "FILE")]
+    fixture: Option<PathBuf>,
+
+    /// GPX track (or directory of tracks) to locate photos that have a capture time but no GPS
+    /// position. Can be given more than once
+    #[arg(long)]
+    gpx: Vec<PathBuf>,
+
+    /// Correction added to capture times before matching them against the track, for camera
+    /// clocks that are off or set to local time, e.g. "-2h" or "1m30s"
+    #[arg(long, value_parser = parse_duration, default_value = "0", allow_hyphen_values = true, requires = "gpx")]
+    gpx_offset: i64,
+
+    /// TOML or CSV file with the coordinates or location of individual files, by name or SHA-256,
+    /// for photos whose GPS position is wrong. Takes precedence over both EXIF and the geocoder
+    #[arg(long, value_name = "FILE")]
+    overrides: Option<PathBuf>,
+
+    /// Correction added to every capture time before it's used for names, tracks and ordering, for
+    /// a camera clock that was off, e.g. "+02:00", "-00:01:30" or "1h"
+    #[arg(long, value_name = "OFFSET", value_parser = parse_time_offset, default_value = "0", allow_hyphen_values = true)]
+    time_offset: i64,
+
+    /// Correction for the clock of a single camera model on top of --time-offset, e.g.
+    /// "NIKON D750=-00:01:30". Can be given more than once
+    #[arg(long, value_name = "MODEL=OFFSET", value_parser = parse_camera_offset, allow_hyphen_values = true)]
+    camera_offset: Vec<(String, i64)>,
+
+    /// Where to take the capture date from for files that don't record one
+    #[arg(long, value_enum, default_value_t = DateFallback::Skip)]
+    date_fallback: DateFallback,
+
+    /// Clock the dates and times in filenames are taken from
+    #[arg(long, value_enum, default_value_t = DateTimezone::Camera)]
+    date_timezone: DateTimezone,
+
+    /// Timezone the camera's clock was set to, e.g. "Europe/Amsterdam", for files that don't
+    /// record their UTC offset
+    #[arg(long)]
+    camera_timezone: Option<Tz>,
+
+    /// Place photos without GPS between the photos taken right before and after them, if those are
+    /// at most this far apart in time, e.g. "2m"
+    #[arg(long, value_parser = parse_positive_duration)]
+    interpolate_gps: Option<i64>,
+
+    /// Label files whose GPS position looks wrong, such as 0, 0 or thousands of kilometers from the
+    /// photos taken around them, instead of skipping them for review
+    #[arg(long)]
+    trust_gps: bool,
+
+    /// Write positions found on the GPX track into the photos' EXIF data (JPEG only)
+    #[arg(long, requires = "gpx")]
+    gpx_write: bool,
+
+    /// Also write the resolved city and country into the files' XMP metadata, so photo managers can
+    /// search on them
+    #[arg(long, value_enum)]
+    write_metadata: Option<MetadataTarget>,
+
+    /// Record the SHA-256 of every file in the journal and the manifest, and check that copies and
+    /// moves came out identical to the originals. `image-labeler check` verifies them later
+    #[arg(long, conflicts_with_all = ["geocode_only", "sidecars_only"])]
+    checksums: bool,
+
+    /// Remove the GPS position from the EXIF data of JPEGs once they're labeled, so they can be
+    /// shared without giving away exactly where they were taken
+    #[arg(long, conflicts_with_all = ["geocode_only", "gpx_write"])]
+    strip_gps: bool,
+
+    /// Losslessly rotate JPEGs the way their EXIF orientation says and reset it, so they display
+    /// correctly everywhere. Needs jpegtran
+    #[arg(long, conflicts_with = "geocode_only")]
+    auto_rotate: bool,
+
+    /// Leave files untouched and only write an XMP sidecar next to each one with its location,
+    /// capture date and a suggested title
+    #[arg(long, conflicts_with_all = ["output_dir", "rename_directories", "write_metadata", "geocode_only", "gpx_write", "strip_gps", "auto_rotate"])]
+    sidecars_only: bool,
+
+    /// Language place names are returned in, as a code such as "de" or "pt-BR", which also names
+    /// the months and weekdays [default: en]
+    #[arg(long)]
+    language: Option<String>,
+
+    /// Spell accented and non-Latin place names in filenames with plain letters
+    #[arg(long, value_enum)]
+    transliterate: Option<Transliteration>,
+
+    /// Filesystem the new names have to be valid on; names that are too long lose part of their
+    /// location [default: posix]
+    #[arg(long, value_enum)]
+    target_fs: Option<TargetFs>,
+
+    /// Address components that make up {location}, in order, e.g. "suburb,city,state"
+    #[arg(long, value_enum, value_delimiter = ',')]
+    location_fields: Vec<LocationField>,
+
+    /// How specific {location} is, e.g. "region" for names like "Tuscany, Italy". Sets the zoom
+    /// level and the address components unless --zoom or --location-fields are given
+    #[arg(long, value_enum)]
+    granularity: Option<Granularity>,
+
+    /// Set the modification time of renamed files to when they were taken, so file managers sort
+    /// them in that order
+    #[arg(long, conflicts_with = "sidecars_only")]
+    set_mtime: bool,
+
+    /// Show each proposed rename and accept, skip or correct its location before anything is
+    /// renamed
+    #[arg(long, conflicts_with_all = ["sidecars_only", "geocode_only"])]
+    interactive: bool,
+
+    /// Review the renames in a full-screen table grouped by day, accepting, skipping or renaming
+    /// each before they're carried out, and follow geocoding and renaming as they go
+    #[arg(long, conflicts_with_all = ["interactive", "sidecars_only", "geocode_only"])]
+    tui: bool,
+
+    /// Geocode and rename this many files at a time, so the geocoded locations and planned renames
+    /// held at once don't grow with the folder. The file list and each photo's date and position
+    /// are still read for the whole folder first, since sequence numbers follow the order every
+    /// photo was taken in. Photos of the same {event} always stay together [default: 1000]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    chunk_size: Option<u32>,
+
+    /// Geocode photos within this many meters of each other only once
+    #[arg(long, default_value_t = 0.0)]
+    cluster_radius: f64,
+
+    /// Continue an interrupted run, reusing the locations it already resolved
+    #[arg(long)]
+    resume: bool,
+
+    /// Geocoding requests per second, or 0 to send them as fast as the service answers. Dry runs
+    /// aren't paced [default: 1, or 0 with --geocoder-url]
+    #[arg(long, value_name = "REQ/S")]
+    rate_limit: Option<f64>,
+
+    /// Requests that may go out at once before --rate-limit paces the rest [default: 1]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    burst: Option<u32>,
+
+    /// Proxy for geocoding requests, e.g. "http://proxy.example.com:3128"; without it HTTPS_PROXY
+    /// and NO_PROXY are followed
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// How long a geocoding request may take before it's retried, e.g. "1m" [default: 30s]
+    #[arg(long, value_parser = parse_positive_duration)]
+    timeout: Option<i64>,
+
+    /// PEM file with certificate authorities to trust on top of the system ones, e.g. for a
+    /// corporate proxy
+    #[arg(long, value_name = "FILE")]
+    ca_bundle: Option<PathBuf>,
+
+    /// How often to retry a geocoding request that was rate limited or failed on the network
+    #[arg(long, default_value_t = RetryPolicy::default().max_retries)]
+    max_retries: u32,
+
+    /// Stop before sending more than N geocoding requests, counting retries and batches. What was
+    /// resolved is kept, and the run continues with --resume
+    #[arg(long, value_name = "N")]
+    max_api_calls: Option<usize>,
+
+    /// Stop before the provider's requests today, over every run, go over N. Days start at
+    /// midnight UTC
+    #[arg(long, value_name = "N")]
+    daily_quota: Option<usize>,
 }
 
-#[derive(Deserialize, Debug)]
-struct Address {
-    road: Option<String>,
-    city: Option<String>,
-    town: Option<String>,
-    village: Option<String>,
-    state: Option<String>,
-    country: Option<String>,
-    country_code: Option<String>
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rename photos and videos after where and when they were taken, which is also what happens
+    /// without a subcommand
+    Rename {
+        #[command(flatten)]
+        run: Box<Args>,
+    },
+    /// Restore the filenames changed by the most recent run
+    Undo {
+        /// Directory the run was performed on
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Give files back the name they had before they were first labeled, even without the
+    /// journal `undo` relies on
+    RestoreNames {
+        /// Directory containing the labeled files
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Also restore files in subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Show what would be restored without touching any files
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write GPS coordinates and capture dates from a manifest, or dates from the filenames an
+    /// earlier run gave, into the EXIF data of JPEG files that are missing them
+    Embed {
+        /// Directory containing the files to date with --from-filenames
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// CSV or JSON manifest with the coordinates and dates to write, as written by --manifest
+        #[arg(long, value_name = "FILE", required_unless_present = "from_filenames", conflicts_with = "from_filenames")]
+        manifest: Option<PathBuf>,
+
+        /// Take the capture date from each file's name, e.g. "20231024_3_NL, Amsterdam.jpg"
+        #[arg(long)]
+        from_filenames: bool,
+
+        /// Also process files in subdirectories with --from-filenames
+        #[arg(short, long, requires = "from_filenames")]
+        recursive: bool,
+
+        /// Replace coordinates and dates the files already record
+        #[arg(long)]
+        overwrite: bool,
+
+        /// Show what would be written without touching any files
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check that labeled files still have the name their metadata and location give them, e.g.
+    /// after a geocoder improvement, and that the renames recorded for the directory can be undone
+    Verify {
+        /// Rename the files that don't match
+        #[arg(long)]
+        fix: bool,
+
+        #[command(flatten)]
+        run: Box<Args>,
+    },
+    /// Work out every rename like a dry run and write them to a plan file, to review or edit
+    /// before carrying it out with `apply`
+    Plan {
+        /// File to write the plan to
+        #[arg(short, long, default_value = "image-labeler-plan.json")]
+        output: PathBuf,
+
+        #[command(flatten)]
+        run: Box<Args>,
+    },
+    /// Keep running and label photos and videos as they arrive in the directory, e.g. a folder a
+    /// phone syncs into
+    Watch {
+        /// How long a new file has to stay unchanged before it's processed, so files that are
+        /// still being written are left alone
+        #[arg(long, value_parser = parse_positive_duration, default_value = "5s")]
+        settle: i64,
+
+        #[command(flatten)]
+        run: Box<Args>,
+    },
+    /// Keep running and label the directories and files submitted over HTTP, e.g. by a NAS or
+    /// another tool, one job at a time with the options given here. POST {"paths": [...]} to
+    /// /jobs to submit a job and GET /jobs/{id} to follow it
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        listen: SocketAddr,
+
+        #[command(flatten)]
+        run: Box<Args>,
+    },
+    /// Carry out the renames in a plan file written by `plan`
+    Apply {
+        /// Plan file to carry out
+        plan: PathBuf,
+
+        /// Directory to apply the plan to, if the photos have moved since it was made
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Turn the manifest of a run into a page to review it, e.g. before sharing a trip
+    Report {
+        /// CSV or JSON manifest written by --manifest
+        manifest: PathBuf,
+
+        /// Write a self-contained HTML page with a map of the photos and their thumbnails by day
+        /// and place
+        #[arg(long, value_name = "FILE")]
+        html: PathBuf,
+    },
+    /// Print how many photos were taken per country, city and month, how many have a GPS position
+    /// and the dates they span, without renaming anything
+    Stats {
+        #[command(flatten)]
+        run: Box<Args>,
+    },
+    /// Print the position, capture time and camera of every photo and video as CSV or JSON,
+    /// straight from their metadata. Nothing is geocoded or renamed
+    Extract {
+        /// Directory containing the photos and videos
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Also read files in subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+
+        #[arg(long, value_enum, default_value_t = ExtractFormat::Csv)]
+        format: ExtractFormat,
+    },
+    /// Check that files still have the checksums recorded with --checksums, e.g. after moving an
+    /// archive to another drive
+    Check {
+        /// Manifest written by --manifest, or a directory to check the journal of
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Print a completion script for a shell, e.g.
+    /// `image-labeler completions bash > ~/.local/share/bash-completion/completions/image-labeler`
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page, or write one for every subcommand into a directory for packaging
+    Man {
+        /// Directory to write image-labeler.1 and a page per subcommand to
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+    /// Inspect or clear the cache of resolved locations
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Create or inspect the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
 }
 
-#[derive(Deserialize, Debug)]
-struct GeocodeResponse {
-    display_name: String,
-    address: Address,
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Delete every cached location, for every provider and setting
+    Clear,
+    /// Show where the cache is and how many locations it holds
+    Stats,
+    /// Write every cached location to a JSON file, to share it or keep it safe. The file works
+    /// as a --fixture too, to run fully offline
+    Export {
+        /// File to write
+        file: PathBuf,
+    },
+    /// Add the locations from a file written by `cache export` to the cache
+    Import {
+        /// File written by `cache export`
+        file: PathBuf,
+
+        /// Replace locations that are cached already
+        #[arg(long)]
+        overwrite: bool,
+    },
 }
 
-const API_KEY: &str = match option_env!("API_KEY") {
-    Some(key) => key,
-    None => "REPLACE_ME_AT_BUILD_TIME",
-};
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Write a config file with every setting commented out
+    Init {
+        /// Where to write it instead of ~/.config/image-labeler/config.toml
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the settings read from the config file, without API keys
+    Show {
+        /// Config file to read instead of ~/.config/image-labeler/config.toml
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    if API_KEY == "REPLACE_ME_AT_BUILD_TIME" {
-        eprintln!("Warning: API_KEY was not provided at build time. Reverse geocoding will fail.");
+async fn main() {
+    let cli = Cli::parse();
+    let structured = match &cli.command {
+        Some(Command::Rename { run } | Command::Plan { run, .. } | Command::Watch { run, .. } | Command::Verify { run, .. }) => run.json || run.ndjson,
+        None => cli.run.json || cli.run.ndjson,
+        _ => false,
+    };
+
+    if let Err(e) = run(cli).await {
+        match &e {
+            // What the run got done is kept, so stopping early isn't reported as a failure
+            RunError::OverBudget(_) | RunError::Interrupted(_) => warn!("Stopped: {}.", e),
+            _ => error!("Error: {}", e),
+        }
+        // Wrappers reading the records can find out what went wrong from the same stream
+        if structured {
+            println!("{}", e.to_json());
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), RunError> {
+    let verbosity = Verbosity::from_flags(cli.quiet, cli.verbose);
+    if cli.use_trash {
+        trash::enable();
+    }
+
+    let mut verify = None;
+    let (mut args, plan_output, watch) = match cli.command {
+        Some(Command::Undo { path }) => {
+            logging::init(verbosity, false);
+            journal::undo(&path)?;
+            return Ok(());
+        }
+        Some(Command::RestoreNames { path, recursive, dry_run }) => {
+            logging::init(verbosity, false);
+            restore::restore_names(&path, if recursive { usize::MAX } else { 0 }, dry_run)?;
+            return Ok(());
+        }
+        Some(Command::Embed { path, manifest, from_filenames: _, recursive, overwrite, dry_run }) => {
+            logging::init(verbosity, false);
+            let entries = match manifest {
+                Some(manifest) => embed::read_manifest(&manifest)?,
+                None => embed::from_filenames(&path, if recursive { usize::MAX } else { 0 })?,
+            };
+            embed::embed(&entries, overwrite, dry_run)?;
+            return Ok(());
+        }
+        Some(Command::Report { manifest, html }) => {
+            logging::init(verbosity, false);
+            gallery::write_html(&html, &output::read_manifest(&manifest)?)?;
+            info!("Wrote report: {:?}", html);
+            return Ok(());
+        }
+        Some(Command::Extract { path, recursive, format }) => {
+            // The export goes to stdout so it can be piped into other tools
+            logging::init(verbosity, true);
+            let count = extract::extract(&path, if recursive { usize::MAX } else { 0 }, format, &mut std::io::stdout().lock())?;
+            debug!("Extracted the metadata of {} files", count);
+            return Ok(());
+        }
+        Some(Command::Check { path }) => {
+            logging::init(verbosity, false);
+            return match checksum::check(&path)? {
+                0 => Ok(()),
+                problems => Err(RunError::Mismatch(problems)),
+            };
+        }
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), env!("CARGO_PKG_NAME"), &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Man { out_dir }) => {
+            logging::init(verbosity, false);
+            match out_dir {
+                Some(out_dir) => {
+                    fs::create_dir_all(&out_dir)?;
+                    clap_mangen::generate_to(Cli::command(), &out_dir)?;
+                    info!("Wrote man pages to {:?}", out_dir);
+                }
+                None => clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?,
+            }
+            return Ok(());
+        }
+        Some(Command::Cache(command)) => {
+            logging::init(verbosity, false);
+            return cache_command(command);
+        }
+        Some(Command::Config(command)) => {
+            // The settings go to stdout so they can be redirected into a file
+            logging::init(verbosity, true);
+            return config_command(command);
+        }
+        Some(Command::Apply { plan, dir }) => {
+            logging::init(verbosity, false);
+            interrupt::install();
+            return apply_plan(&plan, dir.as_deref());
+        }
+        Some(Command::Serve { listen, run }) => {
+            logging::init(verbosity, false);
+            interrupt::install();
+            let args = with_profile(*run)?;
+            if args.rename_directories {
+                return Err(RunError::Usage("--rename-directories can't be used with serve, it asks on the terminal before renaming".to_string()));
+            }
+            return serve_jobs(&args, listen).await;
+        }
+        Some(Command::Plan { output, run }) => (*run, Some(output), None),
+        Some(Command::Watch { settle, run }) => (*run, None, Some(Duration::from_secs(settle as u64))),
+        Some(Command::Verify { fix, run }) => {
+            verify = Some(fix);
+            (*run, None, None)
+        }
+        Some(Command::Stats { run }) => {
+            let mut run = *run;
+            if run.json || run.ndjson || run.manifest.is_some() || run.map.is_some() {
+                logging::init(verbosity, false);
+                return Err(RunError::Usage("--json, --ndjson, --manifest and --map can't be combined with stats".to_string()));
+            }
+            run.stats = true;
+            run.geocode_only = true;
+            (run, None, None)
+        }
+        Some(Command::Rename { run }) => (*run, None, None),
+        None => (cli.run, None, None),
+    };
+
+    let format = if args.json {
+        OutputFormat::Json
+    } else if args.ndjson {
+        OutputFormat::Ndjson
+    } else {
+        OutputFormat::Text
+    };
+    // Records on stdout, everything else on stderr
+    logging::init(verbosity, format != OutputFormat::Text || args.geocode_only);
+    args = with_profile(args)?;
+
+    if let Some(fix) = verify {
+        if args.geocode_only || args.sidecars_only || args.output_dir.is_some() {
+            return Err(RunError::Usage("--geocode-only, --sidecars-only and --output-dir can't be verified".to_string()));
+        }
+        args.dry_run = !fix;
     }
 
-    let args = Args::parse();
+    // A hard link shares its modification time with the original, and rewriting a file replaces
+    // it with a new one, which leaves the original behind and the link an independent copy
+    if args.transfer() == Transfer::Link && args.set_mtime {
+        return Err(RunError::Usage("--set-mtime can't be used with --organize link, it would change the originals as well".to_string()));
+    }
+    if args.transfer() == Transfer::Link && (args.strip_gps || args.auto_rotate || args.write_metadata == Some(MetadataTarget::Embedded)) {
+        return Err(RunError::Usage("--strip-gps, --auto-rotate and --write-metadata embedded can't be used with --organize link, they'd turn the links into copies".to_string()));
+    }
 
-    if !args.path.is_dir() {
-        eprintln!("Error: Provided path is not a directory.");
-        std::process::exit(1);
+    if watch.is_some() && args.rename_directories {
+        return Err(RunError::Usage("--rename-directories can't be used with watch, it asks on the terminal before renaming".to_string()));
     }
 
-    let mut sequence = 1;
+    if plan_output.is_some() {
+        // Only renames end up in the plan, anything else would happen right away
+        let acts_on_duplicates = args.on_duplicate.is_some_and(|action| action != OnDuplicate::Skip);
+        if args.geocode_only || args.sidecars_only || args.write_metadata.is_some() || args.strip_gps || args.auto_rotate || args.checksums || args.rename_directories || acts_on_duplicates {
+            return Err(RunError::Usage("--geocode-only, --sidecars-only, --write-metadata, --strip-gps, --auto-rotate, --checksums, --rename-directories and --on-duplicate link or move-to can't be planned ahead".to_string()));
+        }
+        args.dry_run = true;
+    }
 
-    for entry in fs::read_dir(args.path)? {
-        let entry = entry?;
-        let path = entry.path();
+    let inputs = collect_inputs(&args)?;
+    if args.auto_rotate && !args.dry_run && !rotate::is_available() {
+        return Err(RunError::Setup("--auto-rotate needs jpegtran, install libjpeg-turbo or libjpeg".to_string()));
+    }
+    // A plan file and a watcher each cover a single directory
+    if inputs.len() > 1 && (plan_output.is_some() || watch.is_some()) {
+        return Err(RunError::Usage("plan and watch take a single directory".to_string()));
+    }
 
-        if is_jpeg(&path) {
-            println!("Processing: {:?}", path);
-            let metadata = extract_metadata(&path);
-            if let Some((lat, lon, date)) = metadata {
-                println!("  Found coordinates: {}, {}", lat, lon);
-                println!("  Found date: {}", date);
-                // Sleep for 1 second to respect API rate limits
-                sleep(Duration::from_secs(1)).await;
-                match get_location(lat, lon).await {
-                    Ok(location_response) => {
-                        rename_file(&path, &location_response, &date, sequence)?;
-                        sequence += 1;
+    interrupt::install();
+    if let Some(quiet_period) = watch {
+        return watch_directory(&args, &inputs[0].0, format, quiet_period).await;
+    }
+
+    let mut unrestorable = 0;
+    let mut report = Report::new(format, args.manifest.clone()).with_map(args.map.clone()).with_stats(args.stats);
+    let mut processed = 0;
+    let started = Instant::now();
+    let mut session = start_session(&args)?;
+    for (dir, only) in &inputs {
+        if verify.is_some() {
+            unrestorable += journal::verify(dir)?;
+        }
+        processed += label_directory(&args, &mut session, dir, &mut report, plan_output.as_deref(), only.as_ref(), verify.is_some()).await?;
+    }
+    report.finish();
+    print_run_summary(&args, &report, processed);
+
+    report.metrics().total_seconds = started.elapsed().as_secs_f64();
+    if args.metrics {
+        print_metrics(report.metrics());
+    }
+    if let Some(path) = &args.metrics_json {
+        report.metrics().write(path).map_err(|e| RunError::Setup(format!("couldn't write the metrics to {}: {}", path.display(), e)))?;
+    }
+
+    let summary = report.summary();
+    let mismatched = if verify == Some(false) { summary.planned } else { 0 };
+    if let Some(message) = report.rejected_key() {
+        Err(RunError::Unauthorized(message.to_string()))
+    } else if unrestorable + mismatched > 0 {
+        Err(RunError::Mismatch(unrestorable + mismatched))
+    } else if summary.failed + summary.geocode_failures > 0 {
+        Err(RunError::PartialFailure(summary.failed + summary.geocode_failures))
+    } else if processed == 0 && args.fail_on_empty {
+        Err(RunError::NoFiles)
+    } else {
+        Ok(())
+    }
+}
+
+impl Args {
+    // How files get to their new name, which is only ever a rename without an output directory
+    fn transfer(&self) -> Transfer {
+        match &self.output_dir {
+            Some(_) => self.organize.unwrap_or(Transfer::Copy),
+            None => Transfer::Rename,
+        }
+    }
+}
+
+// Fills in what the selected profile sets and the command line doesn't, and checks the options that
+// only make sense together, wherever each of them came from
+fn with_profile(mut args: Args) -> Result<Args, RunError> {
+    if let Some(name) = &args.profile {
+        let config = Config::load(args.config.as_deref())?;
+        let profile = config.profile(name).map_err(RunError::Usage)?.clone();
+        // Fields given on the command line win over the profile's granularity as well
+        if args.location_fields.is_empty() && args.granularity.is_none() {
+            args.location_fields = profile.location_fields.unwrap_or_default();
+        }
+        args.provider = args.provider.or(profile.provider);
+        args.language = args.language.or(profile.language);
+        args.template = args.template.or(profile.template);
+        args.granularity = args.granularity.or(profile.granularity);
+        args.transliterate = args.transliterate.or(profile.transliterate);
+        args.target_fs = args.target_fs.or(profile.target_fs);
+        args.output_dir = args.output_dir.or(profile.output_dir);
+        args.organize = args.organize.or(profile.organize);
+        args.folder_template = args.folder_template.or(profile.folder_template);
+        args.group_by = args.group_by.or(profile.group_by);
+    }
+
+    if args.output_dir.is_none() && (args.organize.is_some() || args.folder_template.is_some() || args.group_by.is_some()) {
+        return Err(RunError::Usage("--organize, --folder-template and --group-by need --output-dir".to_string()));
+    }
+    if args.output_dir.is_some() && args.rename_directories {
+        return Err(RunError::Usage("--rename-directories can't be combined with --output-dir".to_string()));
+    }
+    if args.organize == Some(Transfer::Rename) {
+        return Err(RunError::Usage("organize has to be copy, move or link".to_string()));
+    }
+    Ok(args)
+}
+
+// A directory to label, along with the only files to label in it when files were given rather
+// than the whole directory
+type Input = (PathBuf, Option<HashSet<PathBuf>>);
+
+fn collect_inputs(args: &Args) -> Result<Vec<Input>, String> {
+    let mut paths = args.paths.clone();
+    if let Some(list) = &args.files_from {
+        let contents = if list.as_os_str() == "-" {
+            std::io::read_to_string(std::io::stdin()).map_err(|e| format!("couldn't read stdin: {}", e))?
+        } else {
+            fs::read_to_string(list).map_err(|e| format!("{}: {}", list.display(), e))?
+        };
+        paths.extend(contents.lines().map(str::trim).filter(|line| !line.is_empty()).map(PathBuf::from));
+    } else if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    let mut inputs: Vec<Input> = Vec::new();
+    for path in paths {
+        let (dir, file) = if path.is_dir() {
+            (path, None)
+        } else if path.is_file() {
+            let dir = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+            let file = paths::canonicalize(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            (dir, Some(file))
+        } else {
+            return Err(format!("{} is not a file or directory", path.display()));
+        };
+
+        match inputs.iter_mut().find(|(existing, _)| same_directory(existing, &dir)) {
+            // A whole directory covers any of its files that were listed as well
+            Some((_, only)) => match (only, file) {
+                (Some(files), Some(file)) => {
+                    files.insert(file);
+                }
+                (only, None) => *only = None,
+                (None, Some(_)) => {}
+            },
+            None => inputs.push((dir, file.map(|file| HashSet::from([file])))),
+        }
+    }
+    Ok(inputs)
+}
+
+fn same_directory(a: &Path, b: &Path) -> bool {
+    a == b || paths::canonicalize(a).ok().is_some_and(|a| paths::canonicalize(b).ok() == Some(a))
+}
+
+fn print_run_summary(args: &Args, report: &Report, processed: usize) {
+    if args.geocode_only {
+        info!("{} files processed", processed);
+    } else {
+        let transfer = args.transfer();
+        let verb = if args.sidecars_only { "given a sidecar" } else { transfer_verb(transfer) };
+        print_summary(processed, report.summary(), verb);
+    }
+}
+
+// The geocoding service to use and the server it's reached at. The command line and environment
+// take precedence over the config file
+fn geocoding_provider(args: &Args, config: &Config) -> (Provider, Option<String>) {
+    let geocoder_url = args.geocoder_url.clone().or_else(|| config.geocoder_url.clone());
+    let default_provider = if args.fixture.is_some() {
+        Provider::Fixture
+    } else if geocoder_url.is_some() {
+        Provider::Nominatim
+    } else {
+        Provider::MapsCo
+    };
+    (args.provider.or(config.provider).unwrap_or(default_provider), geocoder_url)
+}
+
+// The language place names are asked for
+fn language(args: &Args, config: &Config) -> String {
+    args.language.clone().or_else(|| config.language.clone()).unwrap_or_else(|| "en".to_string())
+}
+
+fn network_options(args: &Args, config: &Config) -> NetworkOptions {
+    NetworkOptions {
+        proxy: args.proxy.clone().or_else(|| config.proxy.clone()),
+        timeout: args.timeout.map(|seconds| seconds as u64).or(config.timeout).map(Duration::from_secs),
+        ca_bundle: args.ca_bundle.clone().or_else(|| config.ca_bundle.clone()),
+    }
+}
+
+// Sets up the geocoder and the geocode cache for the whole run. Every directory, watched batch and
+// served job shares them, so --max-api-calls and the rate limit hold for the whole run
+fn start_session(args: &Args) -> Result<Session, RunError> {
+    let config = Config::load(args.config.as_deref())?;
+
+    let (provider, geocoder_url) = geocoding_provider(args, &config);
+    let api_key = args.api_key.clone()
+        .or_else(|| config.api_keys.get(provider).map(str::to_string))
+        .or_else(|| config.api_key.clone());
+    if api_key.is_none() && provider == Provider::MapsCo && geocoder_url.is_none() {
+        warn!("Warning: No API key configured, set --api-key or IMAGE_LABELER_API_KEY. Reverse geocoding will fail.");
+    }
+
+    if args.strict_schema && !matches!(provider, Provider::MapsCo | Provider::Nominatim) {
+        return Err(RunError::Usage("--strict-schema only checks maps-co and nominatim responses".to_string()));
+    }
+
+    // A self-hosted instance has no limits to stay within unless one is set
+    let rate_limit = args.rate_limit.or(config.rate_limit).unwrap_or(if geocoder_url.is_some() { 0.0 } else { 1.0 });
+    if rate_limit.is_nan() || rate_limit < 0.0 {
+        return Err(RunError::Usage("the rate limit can't be negative".to_string()));
+    }
+
+    Session::start(SessionOptions {
+        geocoder: GeocoderOptions {
+            provider,
+            api_key,
+            language: language(args, &config),
+            strict: args.strict_schema,
+            url: geocoder_url,
+            zoom: args.zoom.or(args.granularity.map(Granularity::zoom)),
+            dataset: args.offline_dataset.as_deref().or(config.offline_dataset.as_deref()),
+            fixture: args.fixture.as_deref(),
+            // Self-hosted services without limits don't need any pacing, and dry runs aren't paced
+            // either; a service that throttles them gets its Retry-After respected by the retries
+            rate_limit: Some(rate_limit).filter(|&rate| rate > 0.0 && !args.dry_run),
+            burst: args.burst.or(config.burst).unwrap_or(1),
+            network: network_options(args, &config),
+        },
+        max_requests: args.max_api_calls,
+        daily_quota: args.daily_quota.or(config.daily_quota),
+        use_cache: !args.no_cache,
+        cache_precision: args.cache_precision,
+    })
+}
+
+/// Labels the files in `dir`, or only the groups that include one of `only` when given, and
+/// returns how many files were found. With `verify` only files that were labeled already are
+/// looked at, to relabel those whose name no longer matches.
+async fn label_directory(
+    args: &Args,
+    session: &mut Session,
+    dir: &Path,
+    report: &mut Report,
+    plan_output: Option<&Path>,
+    only: Option<&HashSet<PathBuf>>,
+    verify: bool,
+) -> Result<usize, RunError> {
+    let config = Config::load(args.config.as_deref())?;
+    let language = language(args, &config);
+
+    let transliteration = args.transliterate.or(config.transliterate);
+    let location_fields = match args.granularity {
+        Some(granularity) if args.location_fields.is_empty() => granularity.location_fields(),
+        _ => args.location_fields.clone(),
+    };
+    let target_fs = args.target_fs.or(config.target_fs).unwrap_or_default();
+
+    let template = match args.template.as_deref().or(config.template.as_deref()) {
+        Some(template) => Template::parse(template),
+        None => Ok(Template::default()),
+    };
+    let template = template.map_err(|e| RunError::Setup(e.to_string()))?;
+    let directory_template = Template::parse(DIRECTORY_TEMPLATE).expect("directory template is valid");
+
+    let folder_template = match args.folder_template.as_deref().or(config.folder_template.as_deref()) {
+        Some(template) => FolderTemplate::parse(template),
+        None => Ok(FolderTemplate::default()),
+    };
+    let folder_template = folder_template.map_err(|e| RunError::Setup(e.to_string()))?.grouped_by(args.group_by);
+
+    let uses_w3w = template.uses("w3w") || (args.output_dir.is_some() && folder_template.uses("w3w"));
+    let mut what3words = match args.w3w_key.clone().or_else(|| config.api_keys.what3words.clone()) {
+        Some(key) if uses_w3w => Some(What3Words::new(key, language.clone(), &network_options(args, &config))?),
+        None if uses_w3w => {
+            return Err(RunError::Usage("{w3w} needs a what3words API key, set --w3w-key or WHAT3WORDS_API_KEY".to_string()));
+        }
+        _ => None,
+    };
+
+    // Points of interest take a request of their own for every place, so they're only looked up when used
+    let uses_poi = template.uses("poi") || (args.output_dir.is_some() && folder_template.uses("poi"));
+    if uses_poi && !matches!(session.provider, Provider::MapsCo | Provider::Nominatim) {
+        warn!("Warning: {{poi}} is only looked up with the maps-co and nominatim providers and will be left empty.");
+    }
+    let mut pois = HashMap::new();
+
+    let uses_tags = template.uses("tags") || (args.output_dir.is_some() && folder_template.uses("tags"));
+    let classifier = match (&args.tag_model, &args.tag_labels) {
+        (Some(model), Some(labels)) => {
+            let options = TagOptions { max_tags: args.max_tags, min_confidence: args.tag_confidence };
+            Some(Classifier::load(model, labels, options).map_err(|e| RunError::Setup(e.to_string()))?)
+        }
+        _ if uses_tags => return Err(RunError::Usage("{tags} needs an image classifier, set --tag-model and --tag-labels".to_string())),
+        _ => None,
+    };
+
+    let seq_format = args.seq_format.unwrap_or(SeqFormat::Counter { width: args.seq_width });
+    let mut sequence = Sequence::new(args.seq_per_day, seq_format).starting_at(args.seq_start, args.seq_step);
+    let mut processed = 0;
+    let mut locations: HashMap<PathBuf, HashMap<String, usize>> = HashMap::new();
+    let mut writes = Writes::default();
+    // Records of planned files wait until their rename has happened
+    let mut planned: HashMap<PathBuf, FileRecord> = HashMap::new();
+    let mut plan = RenamePlan::new(args.transfer());
+    let mut journal = Journal::load(dir)?;
+    journal.begin_run();
+
+    let started = Instant::now();
+    let max_depth = if args.recursive { args.max_depth.unwrap_or(usize::MAX) } else { 0 };
+    let mut groups = Vec::new();
+    let mut filter = FileFilter::new(dir, &args.include, &args.exclude).map_err(|e| RunError::Setup(e.to_string()))?;
+    if args.no_default_ignores {
+        filter = filter.with_ignores::<&str>(&[]).map_err(|e| RunError::Setup(e.to_string()))?;
+    } else if let Some(ignore) = &config.ignore {
+        filter = filter.with_ignores(ignore).map_err(|e| RunError::Setup(format!("invalid ignore pattern in the config: {}", e)))?;
+    }
+    let selection = FileSelection {
+        min_size: args.min_size,
+        max_size: args.max_size,
+        older_than: args.older_than.map(|seconds| Duration::from_secs(seconds as u64)),
+        newer_than: args.newer_than.map(|seconds| Duration::from_secs(seconds as u64)),
+    };
+    let filter = filter.following_symlinks(args.follow_symlinks).selecting(selection);
+    scan_directory(dir, max_depth, &filter, &mut groups)?;
+    for (link, original) in remove_hard_links(&mut groups) {
+        info!("Skipping {:?}: the same file as {:?}", link, original);
+        report.add(FileRecord::new(link, FileStatus::Skipped).with_reason(format!("the same file as {}", original.display())));
+    }
+    let mut duplicates = args.on_duplicate.map(|_| DuplicateFinder::new());
+    if let Some(only) = only {
+        let (batch, earlier): (Vec<_>, Vec<_>) = groups.into_iter()
+            .partition(|group| group.members.iter().any(|member| paths::canonicalize(member).is_ok_and(|member| only.contains(&member))));
+        // Files from earlier passes can still be the originals of new ones
+        if let Some(finder) = &mut duplicates {
+            for group in &earlier {
+                finder.add(group.primary())?;
+            }
+        }
+        groups = batch;
+    }
+
+    processed += groups.iter().flat_map(|group| &group.members).filter(|member| !is_sidecar(member)).count();
+
+    // Re-running on a folder only picks up the files that arrived since the last run
+    if !verify && !args.relabel && !args.geocode_only && !args.sidecars_only && args.output_dir.is_none() {
+        let (pending, labeled): (Vec<_>, Vec<_>) = groups.into_iter()
+            .partition(|group| !is_labeled(group, &template, &journal));
+        for group in &labeled {
+            info!("Skipping already labeled: {:?}", group.primary());
+            continue_sequence(&mut sequence, &template, group.primary());
+            plan.skip_group(group, "already labeled");
+            if let Some(finder) = &mut duplicates {
+                finder.add(group.primary())?;
+            }
+            report.add(FileRecord::new(group.primary().to_path_buf(), FileStatus::Skipped).with_reason("already labeled"));
+        }
+        groups = pending;
+    } else if verify {
+        let (labeled, pending): (Vec<_>, Vec<_>) = groups.into_iter()
+            .partition(|group| is_labeled(group, &template, &journal));
+        processed -= pending.iter().flat_map(|group| &group.members).filter(|member| !is_sidecar(member)).count();
+        for group in &pending {
+            debug!("Skipping, not labeled yet: {:?}", group.primary());
+        }
+        groups = labeled;
+    }
+
+    // Files organized into the output directory by earlier runs keep their numbers as well
+    if let Some(output_dir) = args.output_dir.as_ref().filter(|output_dir| output_dir.is_dir() && !verify) {
+        let mut organized = Vec::new();
+        let filter = FileFilter::new(output_dir, &[], &[]).map_err(|e| RunError::Setup(e.to_string()))?;
+        scan_directory(output_dir, usize::MAX, &filter, &mut organized)?;
+        for group in &organized {
+            continue_sequence(&mut sequence, &template, group.primary());
+        }
+    }
+
+    report.metrics().add(Stage::Scan, started.elapsed());
+
+    let started = Instant::now();
+    let track = if args.gpx.is_empty() {
+        None
+    } else {
+        let track = TrackLog::load(&args.gpx, args.gpx_offset).map_err(|e| RunError::Setup(e.to_string()))?;
+        info!("Loaded {} track points", track.len());
+        Some(track)
+    };
+
+    let overrides = match &args.overrides {
+        Some(path) => {
+            let overrides = Overrides::load(path).map_err(RunError::Setup)?;
+            info!("Loaded {} overrides", overrides.len());
+            overrides
+        }
+        None => Overrides::default(),
+    };
+    let overridden = if overrides.is_empty() { HashMap::new() } else { overrides.find(dir, &groups) };
+
+    let clock_offsets = ClockOffsets { all: args.time_offset, cameras: args.camera_offset.clone() };
+    let options = MetadataOptions {
+        track: track.as_ref(),
+        date_fallback: args.date_fallback,
+        camera_timezone: args.camera_timezone,
+        clock_offsets: Some(&clock_offsets),
+        position: None,
+    };
+    let position = |group: &FileGroup| overridden.get(group.primary()).and_then(|entry| entry.position());
+
+    // EXIF parsing is independent per file and doesn't touch the network, so it all happens up front
+    let progress = Progress::new(groups.len(), "Reading metadata");
+    let mut metadata = groups.par_iter()
+        .progress_with(progress.bar().clone())
+        .map(|group| group_metadata(group, &MetadataOptions { position: position(group), ..options }))
+        .collect::<Vec<_>>();
+    drop(progress);
+
+    // Bursts where only some photos got a GPS fix are filled in from the ones that did
+    if let Some(max_gap) = args.interpolate_gps {
+        let neighbors = TrackLog::from_neighbors(metadata.iter().flatten(), max_gap as f64);
+        let options = MetadataOptions { track: Some(&neighbors), ..options };
+        metadata = groups.par_iter()
+            .zip(metadata)
+            .map(|(group, metadata)| metadata.or_else(|_| group_metadata(group, &MetadataOptions { position: position(group), ..options })))
+            .collect();
+    }
+
+    if args.date_timezone != DateTimezone::Camera {
+        let normalizer = TimeNormalizer::new(args.date_timezone);
+        let unchanged = metadata.iter_mut().flatten().map(|metadata| normalizer.normalize(metadata)).filter(|normalized| !normalized).count();
+        if unchanged > 0 {
+            warn!("Warning: {} files don't record when they were taken in UTC and keep the camera's date, set --camera-timezone to convert them.", unchanged);
+        }
+    }
+
+    report.metrics().add(Stage::Metadata, started.elapsed());
+
+    let mut files = groups.into_iter().zip(metadata).collect::<Vec<_>>();
+
+    // Photos from outside the trip are left alone before any requests are made for them
+    let photo_filter = PhotoFilter { after: args.after, before: args.before, within: args.within };
+    if !photo_filter.is_empty() {
+        files.retain(|(group, metadata)| {
+            let Some(reason) = metadata.as_ref().ok().and_then(|metadata| photo_filter.rejects(metadata)) else {
+                return true;
+            };
+            info!("Skipping {:?}: {}", group.primary(), reason);
+            plan.skip_group(group, reason);
+            report.add(FileRecord::new(group.primary().to_path_buf(), FileStatus::Skipped).with_reason(reason));
+            false
+        });
+    }
+
+    // Sequence numbers follow the order photos were taken in, then their names; files without
+    // metadata go last
+    files.sort_by_cached_key(|(group, metadata)| {
+        let name = copy_order(group.primary());
+        match metadata {
+            Ok(metadata) => (false, metadata.sort_key(), name),
+            Err(_) => (true, String::new(), name),
+        }
+    });
+
+    // The first copy of a photo is the original, in the same order sequence numbers are handed out
+    if let Some(finder) = &mut duplicates {
+        files.retain(|(group, _)| {
+            let original = match finder.check(group.primary()) {
+                Ok(Some(original)) => original,
+                Ok(None) => return true,
+                Err(e) => {
+                    warn!("Warning: Couldn't compare {:?} with the other files: {}", group.primary(), e);
+                    return true;
+                }
+            };
+            info!("Duplicate: {:?} is identical to {:?}", group.primary(), original);
+            let reason = format!("duplicate of {}", original.display());
+            plan.skip_group(group, &reason);
+            report.add(handle_duplicate(group, &original, args, &mut journal).with_reason(reason));
+            false
+        });
+    }
+    let (groups, metadata): (Vec<_>, Vec<_>) = files.into_iter().unzip();
+    let (metadata, missing): (Vec<_>, Vec<_>) = metadata.into_iter()
+        .map(|metadata| match metadata {
+            Ok(metadata) => (Some(metadata), None),
+            Err(missing) => (None, Some(missing)),
+        })
+        .unzip();
+
+    // A stale or missing fix would confidently name a photo after the wrong place, so those are
+    // left for review before anything is geocoded
+    let suspicious = if args.trust_gps { vec![None; metadata.len()] } else { suspicious_positions(&metadata) };
+    let ((groups, metadata), missing): ((Vec<_>, Vec<_>), Vec<_>) = groups.into_iter()
+        .zip(metadata)
+        .zip(missing)
+        .zip(suspicious)
+        .filter_map(|(((group, metadata), missing), suspicious)| {
+            let Some(reason) = suspicious else {
+                return Some(((group, metadata), missing));
+            };
+            warn!("Warning: Skipping {:?}, {}. Check it, and pass --trust-gps or correct it with --overrides.", group.primary(), reason);
+            let mut record = FileRecord::new(group.primary().to_path_buf(), FileStatus::Skipped);
+            record.lat = metadata.as_ref().map(|metadata| metadata.lat);
+            record.lon = metadata.as_ref().map(|metadata| metadata.lon);
+            record.date = metadata.map(|metadata| metadata.date);
+            plan.skip_group(&group, &reason);
+            report.add(record.with_reason(reason));
+            None
+        })
+        .unzip();
+
+    let mut checkpoint = if args.resume {
+        let checkpoint = Checkpoint::load(dir)?;
+        if checkpoint.is_empty() {
+            info!("No interrupted run to resume, starting from the beginning.");
+        } else {
+            info!("Resuming with {} locations from the previous run", checkpoint.len());
+        }
+        checkpoint
+    } else {
+        if Checkpoint::exists(dir) {
+            warn!("Warning: A previous run was interrupted, pass --resume to pick up where it left off.");
+        }
+        Checkpoint::start(dir)?
+    };
+
+    // Geocoding, renaming and everything after happen a chunk at a time, so the locations, planned
+    // renames and writes held until the renames are carried out don't grow with the folder. The
+    // groups and their metadata above are held for the whole folder, they're needed to sort it
+    let executing = !args.sidecars_only && plan_output.is_none() && !args.dry_run && !args.geocode_only;
+    let uses_event = template.uses("event") || (args.output_dir.is_some() && folder_template.uses("event"));
+    let chunks = chunk_lengths(&metadata, args.chunk_size.map(|size| size as usize).or(config.chunk_size).unwrap_or(DEFAULT_CHUNK_SIZE).max(1), uses_event.then_some(args.event_gap as f64));
+    let chunk_count = chunks.len();
+    let mut left = chunks.iter().sum::<usize>();
+    let mut remaining = groups.into_iter().zip(metadata).zip(missing);
+    let mut accept_all = false;
+    let mut carried_out = false;
+    // The terminal belongs to the review from here on, the log shows up again once it's closed
+    let mut tui = if args.tui { Some(Tui::start().map_err(|e| RunError::Usage(e.to_string()))?) } else { None };
+    let mut review = Vec::new();
+    for (index, length) in chunks.into_iter().enumerate() {
+        let (files, missing): (Vec<_>, Vec<_>) = remaining.by_ref().take(length).unzip();
+        let (groups, metadata): (Vec<_>, Vec<_>) = files.into_iter().unzip();
+        if chunk_count > 1 {
+            info!("Chunk {} of {} ({} files)", index + 1, chunk_count, length);
+        }
+        let title = if chunk_count > 1 { format!("Chunk {} of {}", index + 1, chunk_count) } else { dir.display().to_string() };
+
+        // Photos with a location in the overrides file or inside a place from the config, or resolved
+        // by the interrupted run, aren't geocoded
+        let known = groups.iter()
+            .zip(&metadata)
+            .map(|(group, metadata)| {
+                overridden.get(group.primary()).and_then(|entry| entry.response())
+                    .or_else(|| metadata.as_ref().and_then(|metadata| geofence::find(&config.places, metadata.lat, metadata.lon)).map(Geofence::response))
+                    .or_else(|| checkpoint.get(group.primary()).cloned())
+            })
+            .collect::<Vec<_>>();
+        let unresolved = metadata.iter()
+            .zip(&known)
+            .map(|(metadata, known)| if known.is_some() { None } else { metadata.clone() })
+            .collect::<Vec<_>>();
+
+        let mut primaries: HashMap<(u64, u64), Vec<&Path>> = HashMap::new();
+        for (group, metadata) in groups.iter().zip(&unresolved) {
+            if let Some(metadata) = metadata {
+                primaries.entry((metadata.lat.to_bits(), metadata.lon.to_bits())).or_default().push(group.primary());
+            }
+        }
+        let lookups = primaries.keys().filter(|&&(lat, lon)| session.cache.get(f64::from_bits(lat), f64::from_bits(lon)).is_none()).count();
+        if let Some(tui) = &mut tui {
+            tui.progress(&title, "locations geocoded", 0, lookups)?;
+        }
+        let mut looked_up = 0;
+        let mut record = |lat: f64, lon: f64, response: &GeocodeResponse| {
+            for path in primaries.get(&(lat.to_bits(), lon.to_bits())).into_iter().flatten() {
+                if let Err(e) = checkpoint.record(path, response.clone()) {
+                    warn!("Warning: Couldn't update the checkpoint: {}", e);
+                }
+            }
+            looked_up += 1;
+            if let Some(tui) = &mut tui && let Err(e) = tui.progress(&title, "locations geocoded", looked_up, lookups) {
+                warn!("Warning: Couldn't draw the progress: {}", e);
+            }
+        };
+
+        let hits = unresolved.iter().flatten().filter(|metadata| session.cache.get(metadata.lat, metadata.lon).is_some()).count();
+        report.metrics().add_cache_lookups(hits, unresolved.iter().flatten().count() - hits);
+
+        let started = Instant::now();
+        let retry = RetryPolicy { max_retries: args.max_retries, ..RetryPolicy::default() };
+        let (resolved, rejected) = resolve_locations(session.geocoder.as_ref(), &mut session.cache, &retry, args.cluster_radius, &unresolved, &mut record).await;
+        report.metrics().add(Stage::Geocode, started.elapsed());
+        if let Some(message) = rejected {
+            report.reject_key(message);
+        }
+        let resolved = resolved.into_iter()
+            .zip(known)
+            .zip(&metadata)
+            .map(|((resolved, known), metadata)| match known {
+                Some(response) if metadata.is_some() => Some(Ok(response)),
+                _ => resolved,
+            })
+            .collect::<Vec<_>>();
+        checkpoint.save()?;
+
+        let events = detect_events(&metadata, &resolved, args.event_gap as f64);
+
+        'files: for ((((group, metadata), location), missing), event) in groups.into_iter().zip(metadata).zip(resolved).zip(missing).zip(events) {
+            if interrupt::requested() {
+                let summary = if carried_out { "the files of the earlier chunks were renamed and `image-labeler undo` reverts them" } else { "no files were renamed yet" };
+                return Err(session.stop(&mut checkpoint, report, summary, RunError::Interrupted));
+            }
+            // The files whose lookups were refused are left for the next run, along with the rest
+            if session.budget.is_exhausted() {
+                return Err(session.stop_over_budget(&mut checkpoint, report, left, carried_out));
+            }
+            left -= 1;
+            if let Some(tui) = &mut tui {
+                tui.progress(&title, "files named", length - (left - remaining.len()), length)?;
+            }
+            let path = group.primary().to_path_buf();
+
+            if args.stats {
+                let mut record = FileRecord::new(path, FileStatus::Skipped);
+                if let Some(metadata) = &metadata {
+                    record.lat = Some(metadata.lat);
+                    record.lon = Some(metadata.lon);
+                    record.date = Some(metadata.date.clone());
+                } else if missing == Some(MissingMetadata::Position) {
+                    // Photos without a position still count towards the months they were taken in
+                    record.date = capture_date(&record.path).map(|(date, _)| date);
+                }
+                record.address = location.and_then(Result::ok).map(|response| response.address);
+                record.missing = missing;
+                report.add(record);
+                continue;
+            }
+            if args.geocode_only {
+                print_geocode_record(&path, metadata.as_ref(), location)?;
+                continue;
+            }
+
+            info!("Processing: {:?}", path);
+            for companion in &group.members[1..] {
+                debug!("  Paired with: {:?}", companion);
+            }
+
+            let mut record = FileRecord::new(path.clone(), FileStatus::Skipped);
+            if let (Some(metadata), Some(location)) = (metadata, location) {
+                record.lat = Some(metadata.lat);
+                record.lon = Some(metadata.lon);
+                record.date = Some(metadata.date.clone());
+                match metadata.position_source {
+                    PositionSource::Embedded => debug!("  Found coordinates: {}, {}", metadata.lat, metadata.lon),
+                    PositionSource::Neighbors => debug!("  Interpolated coordinates from neighboring photos: {}, {}", metadata.lat, metadata.lon),
+                    PositionSource::Override => debug!("  Using coordinates from the overrides file: {}, {}", metadata.lat, metadata.lon),
+                    PositionSource::Track => {
+                        debug!("  Found coordinates on GPX track: {}, {}", metadata.lat, metadata.lon);
+                        if args.gpx_write && !args.dry_run {
+                            match write_gps_position(&path, metadata.lat, metadata.lon) {
+                                Ok(()) => debug!("  Wrote coordinates to EXIF"),
+                                Err(e) => error!("  Error writing coordinates: {}", e),
+                            }
+                        }
+                    }
+                }
+                debug!("  Found date: {}", metadata.date);
+                match location {
+                    Ok(mut location_response) => {
+                        let seq = match sequence.next(&metadata.date, &path) {
+                            Ok(seq) => seq,
+                            Err(e) => {
+                                error!("  Error hashing the file for {{seq}}: {}", e);
+                                plan.skip_group(&group, &format!("couldn't hash the file: {}", e));
+                                report.add(record.with_reason(format!("couldn't hash the file: {}", e)));
+                                continue 'files;
+                            }
+                        };
+                        let w3w = match &mut what3words {
+                            Some(what3words) => what3words.words(metadata.lat, metadata.lon).await.unwrap_or_else(|e| {
+                                warn!("  Warning: Couldn't look up the what3words address: {}", e);
+                                String::new()
+                            }),
+                            None => String::new(),
+                        };
+                        let poi = if uses_poi { nearby_poi(session.geocoder.as_ref(), &mut pois, metadata.lat, metadata.lon).await } else { String::new() };
+                        if session.budget.is_exhausted() {
+                            return Err(session.stop_over_budget(&mut checkpoint, report, left + 1, carried_out));
+                        }
+                        let tags = match &classifier {
+                            Some(classifier) => classifier.classify(&path).unwrap_or_else(|e| {
+                                debug!("  Couldn't classify the photo: {}", e);
+                                Vec::new()
+                            }),
+                            None => Vec::new(),
+                        };
+                        if !tags.is_empty() {
+                            debug!("  Found tags: {}", tags.join(", "));
+                        }
+                        // Values that depend on the other files or another service
+                        let extra_values = [
+                            ("event", event.clone().unwrap_or_default()),
+                            ("w3w", w3w),
+                            ("poi", poi),
+                            ("tags", tags.iter().map(|tag| sanitize(tag)).collect::<Vec<_>>().join(", ")),
+                        ];
+                        let mut values = template_values(&path, &metadata, &location_response, &seq, &location_fields, &language);
+                        values.extend(extra_values.clone());
+                        if let Some(transliteration) = transliteration {
+                            transliterate_values(&mut values, transliteration);
+                        }
+                        // Leave room for the longest extension in the group and a collision suffix
+                        let reserved = group.members.iter()
+                            .filter_map(|member| member.extension())
+                            .map(|extension| target_fs.name_len(&extension.to_string_lossy()) + 1)
+                            .max()
+                            .unwrap_or(0) + COLLISION_SUFFIX_LEN;
+                        let mut stem = template.render_for(&values, target_fs, reserved);
+                        let mut dir = args.output_dir.as_ref().map(|output_dir| output_dir.join(folder_template.render(&values, target_fs)));
+                        let mut location = location_text(&location_response, &location_fields);
+
+                        if args.interactive && !accept_all {
+                            loop {
+                                match review_rename(&path, &target_path(&path, dir.as_deref(), &group.member_stem(&path, &stem)), &location_response, &location)? {
+                                    Review::Accept => break,
+                                    Review::AcceptAll => {
+                                        accept_all = true;
+                                        break;
+                                    }
+                                    Review::Skip => {
+                                        sequence.give_back(&metadata.date);
+                                        plan.skip_group(&group, "skipped during review");
+                                        report.add(record.with_reason("skipped during review"));
+                                        continue 'files;
+                                    }
+                                    Review::Edit(text) => {
+                                        // The typed text is the whole location, rather than one part
+                                        // of an address whose other parts were geocoded
+                                        set_location_text(&mut location_response, &text);
+                                        location = sanitize(&text);
+                                        values = template_values(&path, &metadata, &location_response, &seq, &location_fields, &language);
+                                        values.insert("location", location.clone());
+                                        values.extend(extra_values.clone());
+                                        if let Some(transliteration) = transliteration {
+                                            transliterate_values(&mut values, transliteration);
+                                        }
+                                        stem = template.render_for(&values, target_fs, reserved);
+                                        dir = args.output_dir.as_ref().map(|output_dir| output_dir.join(folder_template.render(&values, target_fs)));
+                                    }
+                                }
+                            }
+                        }
+
+                        let label = directory_template.render_for(&values, target_fs, 0);
+                        record.address = Some(location_response.address.clone());
+                        if args.sidecars_only {
+                            let mut properties = XmpProperties::from(&location_response);
+                            properties.tags = tags;
+                            properties.date_created = Some(iso_date_time(&metadata));
+                            properties.title = Some(suggested_title(&metadata, &location_response));
+                            record.new_path = Some(xmp::sidecar_path(&path));
+                            record.status = match write_sidecar(&path, &properties, args.dry_run) {
+                                Ok(()) if args.dry_run => FileStatus::Planned,
+                                Ok(()) => FileStatus::SidecarWritten,
+                                Err(e) => {
+                                    record.reason = Some(e);
+                                    FileStatus::Failed
+                                }
+                            };
+                            report.add(record);
+                        } else {
+                            match plan.add_group(&group, dir.as_deref(), &stem, args.on_collision) {
+                                Ok(Some(target)) => {
+                                    if dir.is_some() {
+                                        info!("  Destination: {:?}", target);
+                                    } else {
+                                        info!("  New name: {:?}", target.file_name().unwrap_or_default());
+                                    }
+                                    writes.metadata.push((group.members.clone(), XmpProperties { tags, ..XmpProperties::from(&location_response) }));
+                                    if let Some(capture_time) = attributes::capture_time(&metadata).filter(|_| args.set_mtime) {
+                                        writes.mtimes.push((group.members.clone(), capture_time));
+                                    }
+                                    if tui.is_some() {
+                                        review.push(ReviewRow::new(group.clone(), target.clone(), &metadata.date, &label));
+                                    }
+                                    record.status = if target == path { FileStatus::Unchanged } else { FileStatus::Planned };
+                                    record.new_path = Some(target);
+                                    planned.insert(path.clone(), record);
+                                }
+                                Ok(None) => report.add(record.with_reason("target filename already exists")),
+                                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                                    report.finish();
+                                    let renamed = if carried_out { "no more files were renamed" } else { "no files were renamed" };
+                                    return Err(RunError::Setup(format!("{}, {}", e, renamed)));
+                                }
+                                Err(e) => return Err(e.into()),
+                            }
+                        }
+                        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                        *locations.entry(dir).or_default().entry(label).or_insert(0) += 1;
+                    }
+                    Err(e) => {
+                        error!("  Error getting location: {}", e);
+                        plan.skip_group(&group, &format!("geocoding failed: {}", e));
+                        record.status = FileStatus::Failed;
+                        report.add(record.with_reason(format!("geocoding failed: {}", e)));
                     }
-                    Err(e) => eprintln!("  Error getting location: {}", e),
                 }
             } else {
-                println!("  Missing GPS or Date metadata.");
+                let missing = missing.unwrap_or(MissingMetadata::Position);
+                info!("  Skipping: {}.", missing);
+                plan.skip_group(&group, &missing.to_string());
+                record.missing = Some(missing);
+                report.add(record.with_reason(missing.to_string()));
+            }
+        }
+
+        session.save()?;
+
+        if let Some(tui) = &mut tui && !review.is_empty() {
+            if tui.review(&title, &mut review)? == Outcome::Cancel {
+                let summary = if carried_out { "the review was cancelled, the files of the earlier chunks were renamed" } else { "the review was cancelled, no files were renamed" };
+                return Err(session.stop(&mut checkpoint, report, summary, RunError::Interrupted));
+            }
+            apply_review(&mut plan, &mut planned, report, review.drain(..), target_fs, args.on_collision);
+        }
+
+        if executing {
+            let started = Instant::now();
+            let finishing = Finishing { checksums: args.checksums, auto_rotate: args.auto_rotate, write_metadata: args.write_metadata, strip_gps: args.strip_gps };
+            let result = execute_chunk(&plan, &mut journal, &mut planned, &writes, finishing, report, &mut |renamed, total| {
+                if let Some(tui) = &mut tui && let Err(e) = tui.progress(&title, "files renamed", renamed, total) {
+                    warn!("Warning: Couldn't draw the progress: {}", e);
+                }
+            });
+            match result {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    let summary = format!("{}, the journal has every completed rename and `image-labeler undo` reverts them", e);
+                    return Err(session.stop(&mut checkpoint, report, &summary, RunError::Interrupted));
+                }
+                Err(e) => {
+                    report.finish();
+                    return Err(e.into());
+                }
             }
+
+            // The next chunk starts a plan of its own, the files renamed so far already claim their names
+            plan = RenamePlan::new(plan.transfer);
+            writes.clear();
+            carried_out = true;
+            report.metrics().add(Stage::Rename, started.elapsed());
+        }
+    }
+    drop(tui);
+
+
+    if args.sidecars_only {
+        // Nothing was planned, the sidecars were written as each file was processed
+    } else if let Some(output) = plan_output {
+        if let Err(e) = plan.save(dir, output) {
+            return Err(RunError::Setup(format!("couldn't write the plan: {}", e)));
+        }
+        info!("");
+        info!("Wrote {} renames to {:?}, run `image-labeler apply {}` to carry them out.", plan.renames.len(), output, output.display());
+        for rename in &plan.renames {
+            if let Some(record) = planned.remove(&rename.from) {
+                report.add(record);
+            }
+        }
+    } else if verify && args.dry_run {
+        print_mismatches(&plan);
+        for rename in &plan.renames {
+            if let Some(record) = planned.remove(&rename.from) {
+                report.add(record);
+            }
+        }
+    } else if args.dry_run && !args.geocode_only {
+        print_dry_run_summary(&plan);
+        for rename in &plan.renames {
+            if let Some(record) = planned.remove(&rename.from) {
+                report.add(record);
+            }
+        }
+    }
+
+    // Everything was planned and carried out, so there is nothing left to resume
+    checkpoint.finish()?;
+    session.save()?;
+    report.metrics().geocoder_requests += session.take_requests();
+
+    if args.rename_directories && !args.geocode_only {
+        // Deepest directories first so renaming a parent doesn't invalidate its children's paths
+        let mut dirs = locations.into_iter().collect::<Vec<_>>();
+        dirs.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+        for (dir, counts) in dirs {
+            rename_directory(&dir, &counts, args.dry_run, &mut journal, report)?;
+        }
+    }
+
+    Ok(processed)
+}
+
+async fn watch_directory(args: &Args, dir: &Path, format: OutputFormat, quiet_period: Duration) -> Result<(), RunError> {
+    // Watch before the first pass so files arriving during it aren't missed
+    let mut watcher = DirectoryWatcher::new(dir, args.recursive, quiet_period).map_err(|e| RunError::Setup(e.to_string()))?;
+    let mut session = start_session(args)?;
+    let mut report = Report::new(format, None);
+    let processed = label_directory(args, &mut session, dir, &mut report, None, None, false).await?;
+    report.finish();
+    print_run_summary(args, &report, processed);
+
+    info!("");
+    info!("Watching {:?} for new files, press Ctrl-C to stop.", dir);
+    while let Some(batch) = watcher.next_batch().map_err(|e| RunError::Other(e.into()))? {
+        // The files a pass renames show up as new ones too
+        let journal = Journal::load(dir)?;
+        let batch = batch.into_iter().filter(|path| !journal.is_rename_target(path)).collect::<HashSet<_>>();
+        if !batch.is_empty() {
+            let mut report = Report::new(format, None);
+            let processed = label_directory(args, &mut session, dir, &mut report, None, Some(&batch), false).await?;
+            report.finish();
+            print_run_summary(args, &report, processed);
         }
     }
 
     Ok(())
 }
 
-fn is_jpeg(path: &Path) -> bool {
-    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
-    ext == "jpg" || ext == "jpeg"
+// Runs the jobs submitted over HTTP in the order they came in, until Ctrl-C
+async fn serve_jobs(args: &Args, listen: SocketAddr) -> Result<(), RunError> {
+    let mut queue = serve::start(listen).await?;
+    let mut session = start_session(args)?;
+    let interrupted = async {
+        while !interrupt::requested() {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    };
+    tokio::pin!(interrupted);
+
+    loop {
+        let job = tokio::select! {
+            job = queue.next() => job,
+            _ = &mut interrupted => None,
+        };
+        let Some(job) = job else {
+            return Ok(());
+        };
+
+        let mut args = args.clone();
+        args.paths = job.request.paths;
+        args.files_from = None;
+        args.dry_run |= job.request.dry_run;
+        info!("Running job {}: {:?}", job.id, args.paths);
+        let outcome = run_job(&args, &mut session, job.progress).await;
+        if let Err(e) = &outcome {
+            error!("Job {} failed: {}", job.id, e);
+        }
+        queue.finish(job.id, outcome.map_err(|e| e.to_string()));
+    }
+}
+
+async fn run_job(args: &Args, session: &mut Session, progress: Arc<AtomicUsize>) -> Result<(Summary, Vec<FileRecord>), RunError> {
+    let mut report = Report::new(OutputFormat::Text, None).keeping_records().with_counter(progress);
+    let mut processed = 0;
+    for (dir, only) in &collect_inputs(args)? {
+        processed += label_directory(args, session, dir, &mut report, None, only.as_ref(), false).await?;
+    }
+    print_run_summary(args, &report, processed);
+    Ok((report.summary(), report.into_records()))
+}
+
+fn apply_plan(path: &Path, dir: Option<&Path>) -> Result<(), RunError> {
+    let (dir, plan) = RenamePlan::load(path, dir)?;
+    let mut journal = Journal::load(&dir)?;
+    journal.begin_run();
+
+    let mut done = 0;
+    let result = execute_plan(&plan, &mut journal, &mut |_| done += 1);
+    info!("");
+    info!("{} files {}.", done, transfer_verb(plan.transfer));
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+            Err(RunError::Interrupted(format!("{}, `image-labeler undo` reverts the completed renames", e)))
+        }
+        result => Ok(result?),
+    }
 }
 
-fn extract_metadata(path: &Path) -> Option<(f64, f64, String)> {
-    let file = fs::File::open(path).ok()?;
-    let mut bufreader = std::io::BufReader::new(&file);
-    let reader = exif::Reader::new();
-    let exif = reader.read_from_container(&mut bufreader).ok()?;
+fn cache_command(command: CacheCommand) -> Result<(), RunError> {
+    let path = GeocodeCache::path().ok_or("no cache directory found")?;
+    match command {
+        CacheCommand::Clear => match cache::clear()? {
+            0 => info!("The cache is empty already."),
+            cleared => info!("Cleared {} cache files in {:?}", cleared, path.parent().unwrap_or(&path)),
+        },
+        CacheCommand::Stats => {
+            let cache = GeocodeCache::load(0);
+            let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+            info!("Cache: {:?}", path);
+            info!("  {} locations, {} KB", cache.len(), size.div_ceil(1024));
+            // Runs with another provider, language, zoom or server each have a cache of their own
+            for other in GeocodeCache::paths().into_iter().filter(|other| *other != path) {
+                let size = fs::metadata(&other).map(|metadata| metadata.len()).unwrap_or(0);
+                info!("Also: {:?}, {} KB", other, size.div_ceil(1024));
+            }
+        }
+        CacheCommand::Export { file } => {
+            let count = GeocodeCache::load(0).export(&file)?;
+            info!("Exported {} locations to {:?}", count, file);
+        }
+        CacheCommand::Import { file, overwrite } => {
+            let mut cache = GeocodeCache::load(0);
+            let count = cache.import(&file, overwrite)?;
+            cache.save()?;
+            info!("Imported {} locations into {:?}", count, path);
+        }
+    }
+    Ok(())
+}
 
-    let lat = exif.get_field(Tag::GPSLatitude, In::PRIMARY)?;
-    let lat_ref = exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY)?;
-    let lon = exif.get_field(Tag::GPSLongitude, In::PRIMARY)?;
-    let lon_ref = exif.get_field(Tag::GPSLongitudeRef, In::PRIMARY)?;
+fn config_command(command: ConfigCommand) -> Result<(), RunError> {
+    match command {
+        ConfigCommand::Init { config, force } => {
+            let path = Config::init(config.as_deref(), force)?;
+            info!("Wrote {:?}", path);
+        }
+        ConfigCommand::Show { config } => {
+            let path = config.clone().or_else(Config::path).ok_or("no configuration directory found, pass --config")?;
+            let settings = Config::load(config.as_deref())?;
+            if path.exists() {
+                info!("# {}", path.display());
+            } else {
+                info!("# {} doesn't exist, using the defaults", path.display());
+            }
+            println!("{}", toml::to_string(&settings).map_err(|e| RunError::Other(e.into()))?);
+        }
+    }
+    Ok(())
+}
 
-    let latitude = to_decimal(lat)?;
-    let longitude = to_decimal(lon)?;
+// Carries out --on-duplicate for a group whose primary file is a copy of `original`
+fn handle_duplicate(group: &FileGroup, original: &Path, args: &Args, journal: &mut Journal) -> FileRecord {
+    let mut record = FileRecord::new(group.primary().to_path_buf(), FileStatus::Duplicate);
+    if args.dry_run {
+        return record;
+    }
+
+    let result = match (args.on_duplicate, &args.duplicates_dir) {
+        (Some(OnDuplicate::Link), _) => duplicate::link(group.primary(), original).map(|()| {
+            debug!("  Replaced with a hard link to {:?}", original);
+        }),
+        (Some(OnDuplicate::MoveTo), Some(dir)) => group.members.iter().try_for_each(|member| {
+            let from = paths::canonicalize(member)?;
+            let to = duplicate::move_to(member, dir)?;
+            info!("  Moved to {:?}", to);
+            if member == group.primary() {
+                record.new_path = Some(to.clone());
+            }
+            journal.record(from, paths::canonicalize(&to)?)
+        }),
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        error!("  Error handling duplicate: {}", e);
+        record.status = FileStatus::Failed;
+    }
+    record
+}
 
-    let lat_final = if lat_ref.display_value().to_string().contains('S') { -latitude } else { latitude };
-    let lon_final = if lon_ref.display_value().to_string().contains('W') { -longitude } else { longitude };
+// The first member with usable metadata, or the reason that got furthest
+fn group_metadata(group: &FileGroup, options: &MetadataOptions) -> Result<PhotoMetadata, MissingMetadata> {
+    let mut missing = MissingMetadata::Date;
+    for member in &group.members {
+        match extract_metadata(member, options) {
+            Ok(metadata) => return Ok(metadata),
+            Err(reason) => missing = missing.max(reason),
+        }
+    }
+    Err(missing)
+}
 
-    // Extract date
-    let date_str = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
-        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?
-        .display_value()
-        .to_string();
+fn write_sidecar(path: &Path, properties: &XmpProperties, dry_run: bool) -> Result<(), String> {
+    if dry_run {
+        info!("  Would write sidecar: {:?}", xmp::sidecar_path(path));
+        return Ok(());
+    }
 
-    // Format yyyy:mm:dd hh:mm:ss to yyyyMMdd
-    // exif display_value is often "2023:10:24 12:00:00"
-    let yyyymmdd = date_str.chars()
-        .filter(|c| c.is_digit(10))
-        .take(8)
-        .collect::<String>();
+    match xmp::write_sidecar(path, properties) {
+        Ok(sidecar) => {
+            info!("  Wrote sidecar: {:?}", sidecar);
+            Ok(())
+        }
+        Err(e) => {
+            error!("  Error writing sidecar: {}", e);
+            Err(e.to_string())
+        }
+    }
+}
 
-    if yyyymmdd.len() == 8 {
-        Some((lat_final, lon_final, yyyymmdd))
+// Either a clock offset like "+02:00" or a duration like "-90s"
+fn parse_time_offset(value: &str) -> Result<i64, String> {
+    if value.contains(':') {
+        parse_clock_offset(value).ok_or_else(|| format!("invalid offset \"{}\", expected e.g. \"+02:00\" or \"-00:01:30\"", value))
     } else {
-        None
+        parse_duration(value)
+    }
+}
+
+fn parse_camera_offset(value: &str) -> Result<(String, i64), String> {
+    let (camera, offset) = value.rsplit_once('=').ok_or_else(|| format!("invalid camera offset \"{}\", expected e.g. \"NIKON D750=-00:01:30\"", value))?;
+    let camera = camera.trim().trim_matches('"');
+    if camera.is_empty() {
+        return Err("the camera model can't be empty".to_string());
     }
+    Ok((camera.to_string(), parse_time_offset(offset)?))
 }
 
-fn to_decimal(field: &exif::Field) -> Option<f64> {
-    if let exif::Value::Rational(ref v) = field.value {
-        if v.len() >= 3 {
-            let degrees = v[0].to_f64();
-            let minutes = v[1].to_f64();
-            let seconds = v[2].to_f64();
-            return Some(degrees + minutes / 60.0 + seconds / 3600.0);
+fn parse_positive_duration(value: &str) -> Result<i64, String> {
+    match parse_duration(value)? {
+        seconds if seconds > 0 => Ok(seconds),
+        _ => Err("the duration must be greater than zero".to_string()),
+    }
+}
+
+fn parse_confidence(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(confidence) if (0.0..=1.0).contains(&confidence) => Ok(confidence),
+        _ => Err(format!("invalid confidence \"{}\", expected a number from 0 to 1", value)),
+    }
+}
+
+// How many of the sorted files go in each chunk. A chunk only ends between two events when the
+// photos of an event have to stay together to be named after it
+fn chunk_lengths(metadata: &[Option<PhotoMetadata>], chunk_size: usize, event_gap: Option<f64>) -> Vec<usize> {
+    let mut lengths = Vec::new();
+    let mut length = 0;
+    for index in 0..metadata.len() {
+        let splits_event = |max_gap: f64| match (index.checked_sub(1).and_then(|previous| metadata[previous].as_ref()), &metadata[index]) {
+            (Some(previous), Some(current)) => same_event(previous, current, max_gap),
+            _ => false,
+        };
+        if length >= chunk_size && !event_gap.is_some_and(splits_event) {
+            lengths.push(length);
+            length = 0;
         }
+        length += 1;
+    }
+    if length > 0 {
+        lengths.push(length);
     }
-    None
+    lengths
 }
 
-async fn get_location(lat: f64, lon: f64) -> Result<GeocodeResponse, Box<dyn std::error::Error>> {
-    let url = format!(
-        "https://geocode.maps.co/reverse?lat={}&lon={}&api_key={}&accept-language={}",
-        lat, lon, API_KEY, "en"
+// A file counts as labeled when an earlier run renamed it, or its name fits the template
+fn is_labeled(group: &FileGroup, template: &Template, journal: &Journal) -> bool {
+    let primary = group.primary();
+    journal.is_rename_target(primary)
+        || primary.file_stem().and_then(|s| s.to_str()).is_some_and(|stem| template.matches(stem))
+}
+
+// Makes {seq} carry on after the number in the name of a file labeled by an earlier run
+fn continue_sequence(sequence: &mut Sequence, template: &Template, path: &Path) {
+    let Some(values) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| template.values_in(stem)) else {
+        return;
+    };
+    if let Some(seq) = values.get("seq").and_then(|seq| seq.parse::<u32>().ok()) {
+        sequence.continue_after(values.get("date").copied(), seq);
+    }
+}
+
+// The point of interest nearest to the position, looked up once for photos taken within a few
+// meters of each other
+async fn nearby_poi(geocoder: &dyn ReverseGeocoder, known: &mut HashMap<(i64, i64), String>, lat: f64, lon: f64) -> String {
+    let key = ((lat * 1e4).round() as i64, (lon * 1e4).round() as i64);
+    if let Some(poi) = known.get(&key) {
+        return poi.clone();
+    }
+    let poi = match geocoder.nearby_poi(lat, lon).await {
+        Ok(poi) => poi.map(|poi| sanitize(&poi)).unwrap_or_default(),
+        Err(e) => {
+            warn!("  Warning: Couldn't look up nearby points of interest: {}", e);
+            String::new()
+        }
+    };
+    known.insert(key, poi.clone());
+    poi
+}
+
+#[derive(Serialize)]
+struct GeocodeRecord<'a> {
+    path: &'a Path,
+    lat: f64,
+    lon: f64,
+    response: GeocodeResponse,
+}
+
+fn print_geocode_record(
+    path: &Path,
+    metadata: Option<&PhotoMetadata>,
+    location: Option<Result<GeocodeResponse, String>>,
+) -> Result<(), RunError> {
+    let (Some(metadata), Some(location)) = (metadata, location) else {
+        warn!("{:?}: Missing GPS or Date metadata.", path);
+        return Ok(());
+    };
+
+    match location {
+        Ok(response) => {
+            let record = GeocodeRecord { path, lat: metadata.lat, lon: metadata.lon, response };
+            println!("{}", serde_json::to_string(&record).map_err(std::io::Error::from)?);
+        }
+        Err(e) => error!("{:?}: Error getting location: {}", path, e),
+    }
+
+    Ok(())
+}
+
+fn print_summary(processed: usize, summary: Summary, verb: &str) {
+    info!("");
+    info!("{} files processed", processed);
+    let lines = [
+        (summary.done, verb.to_string()),
+        (summary.planned, format!("would be {}", verb)),
+        (summary.unchanged, "already had the right name".to_string()),
+        (summary.no_gps, "skipped, no GPS position".to_string()),
+        (summary.no_date, "skipped, no capture date".to_string()),
+        (summary.skipped, "skipped otherwise".to_string()),
+        (summary.duplicates, "duplicates".to_string()),
+        (summary.geocode_failures, "geocoding failures".to_string()),
+        (summary.failed, "failed".to_string()),
+    ];
+    for (count, description) in lines {
+        if count > 0 {
+            info!("  {} {}", count, description);
+        }
+    }
+}
+
+fn print_metrics(metrics: &Metrics) {
+    info!("");
+    info!(
+        "Took {:.2}s: scanning {:.2}s, reading metadata {:.2}s, geocoding {:.2}s, renaming {:.2}s",
+        metrics.total_seconds, metrics.scan_seconds, metrics.metadata_seconds, metrics.geocode_seconds, metrics.rename_seconds
     );
+    match metrics.cache_hit_rate {
+        Some(rate) => info!("  {} of {} locations cached ({:.0}%)", metrics.cache_hits, metrics.cache_hits + metrics.cache_misses, rate * 100.0),
+        None => info!("  No locations looked up"),
+    }
+    info!("  {} geocoder requests", metrics.geocoder_requests);
+}
 
-    let client = reqwest::Client::new();
-    let response = client.get(url)
-        .header("User-Agent", "image-labeler/0.1.0")
-        .send()
-        .await?
-        .json::<GeocodeResponse>()
-        .await?;
+fn transfer_verb(transfer: Transfer) -> &'static str {
+    match transfer {
+        Transfer::Rename => "renamed",
+        Transfer::Copy => "copied",
+        Transfer::Move => "moved",
+        Transfer::Link => "linked",
+    }
+}
 
-    Ok(response)
+fn print_mismatches(plan: &RenamePlan) {
+    let mismatches = plan.renames.iter().filter(|rename| rename.from != rename.to).collect::<Vec<_>>();
+    info!("");
+    if mismatches.is_empty() {
+        info!("Every labeled file matches its metadata.");
+        return;
+    }
+
+    info!("{} files don't match their metadata, run with --fix to rename them:", mismatches.len());
+    for rename in mismatches {
+        info!("  {:?} should be {:?}", rename.from, rename.to.file_name().unwrap_or_default());
+    }
+}
+
+fn print_dry_run_summary(plan: &RenamePlan) {
+    info!("");
+    let verb = transfer_verb(plan.transfer);
+    info!("Dry run, no files were changed. {} files would be {}:", plan.renames.len(), verb);
+    for rename in &plan.renames {
+        if plan.transfer == Transfer::Rename {
+            info!("  {:?} -> {:?}", rename.from, rename.to.file_name().unwrap_or_default());
+        } else {
+            info!("  {:?} -> {:?}", rename.from, rename.to);
+        }
+    }
+
+    if !plan.skipped.is_empty() {
+        info!("{} files would be skipped:", plan.skipped.len());
+        for skipped in &plan.skipped {
+            info!("  {:?}: {}", skipped.path, skipped.reason);
+        }
+    }
 }
 
-fn rename_file(path: &Path, response: &GeocodeResponse, date: &str, sequence: u32) -> std::io::Result<()> {
-    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
-    
-    let road = response.address.road.as_deref();
-    let town_or_city = response.address.town.as_deref()
-        .or(response.address.city.as_deref())
-        .or(response.address.village.as_deref());
-        
-    let country = response.address.country.as_deref();
-    let country_code = response.address.country_code.as_deref().unwrap_or("unknown").to_uppercase();
+fn rename_directory(
+    dir: &Path,
+    locations: &HashMap<String, usize>,
+    dry_run: bool,
+    journal: &mut Journal,
+    report: &mut Report,
+) -> std::io::Result<()> {
+    // Ties are broken alphabetically so the outcome doesn't depend on HashMap order
+    let Some((label, count)) = locations.iter()
+        .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0))) else {
+        info!("No resolved locations, leaving directory name unchanged.");
+        return Ok(());
+    };
 
-    let mut location_parts = Vec::new();
+    let dir = paths::canonicalize(dir)?;
+    let new_path = dir.with_file_name(label);
 
-    if let Some(place) = town_or_city {
-        location_parts.push(place.to_string());
+    if new_path == dir {
+        return Ok(());
     }
 
-    if let Some(r) = road {
-        location_parts.push(r.to_string());
+    // Counted as a failure so the run doesn't end as if every directory was renamed
+    if new_path.exists() {
+        error!("Error: Cannot rename directory, {:?} already exists.", new_path);
+        let mut record = FileRecord::new(dir, FileStatus::Failed).with_reason("directory already exists");
+        record.new_path = Some(new_path);
+        report.add(record);
+        return Ok(());
     }
 
-    if location_parts.is_empty() && let Some(c) = country {
-        location_parts.push(c.to_string());
+    if dry_run {
+        info!("Would rename directory {:?} to {:?} ({} files)", dir, new_path, count);
+        return Ok(());
     }
 
-    let location = if location_parts.is_empty() {
-        response.display_name.clone()
-    } else {
-        location_parts.join(", ")
-    };
-    
-    // Sanitize location for filename
-    let safe_location = location.chars()
-        .map(|c| if c.is_alphanumeric() || c == ' ' || c == ',' { c } else { '_' })
-        .collect::<String>()
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join(" ");
-
-    let new_name = format!("{}_{}_{}, {}.{}", date, sequence, country_code, safe_location, extension);
-    let new_path = path.with_file_name(new_name);
-
-    println!("  Renaming to: {:?}", new_path);
-    fs::rename(path, new_path)?;
+    let prompt = format!("Rename directory {:?} to {:?} ({} files)? [y/N] ", dir, new_path, count);
+    if !confirm(&prompt)? {
+        info!("Leaving directory name unchanged.");
+        return Ok(());
+    }
+
+    info!("Renaming directory to: {:?}", new_path);
+    fs::rename(&dir, &new_path)?;
+    journal.record(dir, new_path)?;
     Ok(())
 }
+
+enum Review {
+    Accept,
+    AcceptAll,
+    Skip,
+    Edit(String),
+}
+
+// Carries the decisions made in the review over to the plan: skipped files come out of it, and
+// files given another name are planned again under that name
+fn apply_review(
+    plan: &mut RenamePlan,
+    planned: &mut HashMap<PathBuf, FileRecord>,
+    report: &mut Report,
+    rows: impl Iterator<Item = ReviewRow>,
+    target_fs: TargetFs,
+    on_collision: OnCollision,
+) {
+    for row in rows {
+        let path = row.group.primary().to_path_buf();
+        let reason = if row.skipped {
+            "skipped during review".to_string()
+        } else if let Some(stem) = row.edited {
+            plan.withdraw_group(&row.group);
+            match plan.add_group(&row.group, row.target.parent(), &target_fs.sanitize(&stem), on_collision) {
+                Ok(Some(target)) => {
+                    if let Some(record) = planned.get_mut(&path) {
+                        record.status = if target == path { FileStatus::Unchanged } else { FileStatus::Planned };
+                        record.new_path = Some(target);
+                    }
+                    continue;
+                }
+                // The plan already has the group skipped
+                Ok(None) => {
+                    if let Some(record) = planned.remove(&path) {
+                        report.add(FileRecord { status: FileStatus::Skipped, new_path: None, ..record }.with_reason("target filename already exists"));
+                    }
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            }
+        } else {
+            continue;
+        };
+        plan.withdraw_group(&row.group);
+        plan.skip_group(&row.group, &reason);
+        if let Some(record) = planned.remove(&path) {
+            report.add(FileRecord { status: FileStatus::Skipped, new_path: None, ..record }.with_reason(reason));
+        }
+    }
+}
+
+// Shows a proposed rename and asks what to do with it. Prompts go to stderr so they don't end up
+// between the records with --json.
+fn review_rename(path: &Path, target: &Path, response: &GeocodeResponse, location: &str) -> std::io::Result<Review> {
+    let mut stderr = std::io::stderr();
+    writeln!(stderr, "  Old name: {:?}", path.file_name().unwrap_or_default())?;
+    writeln!(stderr, "  New name: {:?}", target.file_name().unwrap_or_default())?;
+    writeln!(stderr, "  Address: {}", response.display_name)?;
+
+    loop {
+        let answer = ask("Rename? [y]es, [n]o, [e]dit location, [a]ll remaining: ")?;
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" | "" => return Ok(Review::Accept),
+            "n" | "no" => return Ok(Review::Skip),
+            "a" | "all" => return Ok(Review::AcceptAll),
+            "e" | "edit" => {
+                let text = ask(&format!("Location [{}]: ", location))?;
+                if !text.is_empty() {
+                    return Ok(Review::Edit(text));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn ask(prompt: &str) -> std::io::Result<String> {
+    let mut stderr = std::io::stderr();
+    write!(stderr, "{}", prompt)?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "no more input to answer the prompt"));
+    }
+    Ok(answer.trim().to_string())
+}
+
+// Describes the place in a response with text typed during review. The address keeps its
+// geocoded parts, so {road} or {state} still name the same place
+fn set_location_text(response: &mut GeocodeResponse, text: &str) {
+    response.display_name = match &response.address.country {
+        Some(country) => format!("{}, {}", text, country),
+        None => text.to_string(),
+    };
+}
+
+// Prompts go to stderr, like those of review_rename, so they don't end up in piped output
+fn confirm(prompt: &str) -> std::io::Result<bool> {
+    let mut stderr = std::io::stderr();
+    write!(stderr, "{}", prompt)?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,88 @@
+use crate::geocoder::Address;
+use crate::output::FileRecord;
+use crate::xmp::escape;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// Writes a point for every record with a position, as KML when the path ends in `.kml` and as
+/// GeoJSON otherwise, so a shoot can be dropped onto a map in QGIS or Google Earth. Each point
+/// links to the file under its new name, which map viewers show as a thumbnail.
+pub fn write_map(path: &Path, records: &[FileRecord]) -> std::io::Result<()> {
+    let placed = records.iter()
+        .filter_map(|record| Some((record, record.lat?, record.lon?)))
+        .collect::<Vec<_>>();
+
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("kml")) {
+        fs::write(path, kml(&placed))
+    } else {
+        fs::write(path, serde_json::to_string_pretty(&geojson(&placed))?)
+    }
+}
+
+fn geojson(placed: &[(&FileRecord, f64, f64)]) -> serde_json::Value {
+    let features = placed.iter()
+        .map(|(record, lat, lon)| json!({
+            "type": "Feature",
+            // GeoJSON puts the longitude first
+            "geometry": { "type": "Point", "coordinates": [lon, lat] },
+            "properties": {
+                "path": record.path,
+                "new_path": record.new_path,
+                "date": record.date,
+                "address": record.address.as_ref().map(address_text),
+                "status": record.status,
+                "thumbnail": file_url(record.new_path.as_ref().unwrap_or(&record.path)),
+            },
+        }))
+        .collect::<Vec<_>>();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+fn kml(placed: &[(&FileRecord, f64, f64)]) -> String {
+    let mut kml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n <Document>\n");
+    for (record, lat, lon) in placed {
+        let file = record.new_path.as_ref().unwrap_or(&record.path);
+        let name = file.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let mut description = format!("<img src=\"{}\" width=\"400\"/>", escape(&file_url(file)));
+        if let Some(address) = &record.address {
+            description.push_str(&format!("<br/>{}", escape(&address_text(address))));
+        }
+
+        kml.push_str("  <Placemark>\n");
+        kml.push_str(&format!("   <name>{}</name>\n", escape(&name)));
+        kml.push_str(&format!("   <description>{}</description>\n", escape(&description)));
+        if let Some(date) = record.date.as_deref().filter(|date| date.len() == 8) {
+            kml.push_str(&format!("   <TimeStamp><when>{}-{}-{}</when></TimeStamp>\n", &date[..4], &date[4..6], &date[6..]));
+        }
+        kml.push_str(&format!("   <Point><coordinates>{},{}</coordinates></Point>\n", lon, lat));
+        kml.push_str("  </Placemark>\n");
+    }
+    kml.push_str(" </Document>\n</kml>\n");
+    kml
+}
+
+// The known parts of an address from most to least specific, e.g. "Damrak, Amsterdam, Netherlands"
+fn address_text(address: &Address) -> String {
+    let city = address.city.as_ref().or(address.town.as_ref()).or(address.village.as_ref());
+    [address.road.as_ref(), city, address.state.as_ref(), address.country.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A file:// URL to `file`, absolute so the page or map linking to it can be opened from anywhere.
+pub fn file_url(file: &Path) -> String {
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+    let path = file.to_string_lossy().replace('\\', "/");
+    let path = path.split('/')
+        .map(|segment| segment.bytes().map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        }).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("/");
+    if path.starts_with('/') { format!("file://{}", path) } else { format!("file:///{}", path) }
+}
@@ -0,0 +1,410 @@
+use crate::datetime::{format_unix_time, iso_8601, parse_filename_date, parse_utc_offset, shift_date_time, unix_time};
+use crate::gpx::TrackLog;
+use crate::scan;
+use crate::video;
+use crate::timezone::local_to_utc;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use exif::{In, Tag};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// What a photo or video's metadata says about where and when it was taken.
+#[derive(Debug, Clone)]
+pub struct PhotoMetadata {
+    pub lat: f64,
+    pub lon: f64,
+    pub position_source: PositionSource,
+    /// Meters above sea level, when recorded along with the position
+    pub altitude: Option<f64>,
+    /// Capture time in seconds since the Unix epoch
+    pub timestamp: Option<f64>,
+    /// Whether `timestamp` is known to be UTC, rather than the camera's wall clock read as UTC
+    pub timestamp_is_utc: bool,
+    /// Capture date as yyyyMMdd
+    pub date: String,
+    /// Capture time as HHmmss, when recorded
+    pub time: Option<String>,
+    /// Fractional seconds of the capture time, as the digits after the decimal point
+    pub subsec: Option<String>,
+    /// Camera model, e.g. "iPhone 15 Pro" or "Canon EOS R5"
+    pub camera: Option<String>,
+    /// Camera manufacturer, e.g. "Apple" or "Canon"
+    pub make: Option<String>,
+    pub lens: Option<String>,
+}
+
+/// Where a file's position came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionSource {
+    /// Read from the file itself
+    Embedded,
+    /// Interpolated from a GPX track
+    Track,
+    /// Interpolated between photos taken shortly before and after
+    Neighbors,
+    /// Given for the file in an overrides file
+    Override,
+}
+
+/// Why a file couldn't be labeled. Ordered by how far the file got, so the most useful reason can
+/// be reported for a group of paired files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MissingMetadata {
+    Date,
+    Position,
+}
+
+impl fmt::Display for MissingMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingMetadata::Date => write!(f, "missing capture date"),
+            MissingMetadata::Position => write!(f, "missing GPS position"),
+        }
+    }
+}
+
+impl PhotoMetadata {
+    /// Sortable representation of the capture time, e.g. "20231024120000.050000000".
+    pub fn sort_key(&self) -> String {
+        let subsec = self.subsec.as_deref().unwrap_or("");
+        format!("{}{}.{:0<9}", self.date, self.time.as_deref().unwrap_or(""), subsec)
+    }
+}
+
+/// Where to take a capture date from when the file doesn't record one.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateFallback {
+    /// Skip the file
+    #[default]
+    Skip,
+    /// The file's modification time, in UTC
+    Mtime,
+    /// A date in the file name, e.g. "IMG_20231024_120000" or "Screenshot 2023-10-24 at 12.00.00"
+    Filename,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetadataOptions<'a> {
+    /// Places files without an embedded position
+    pub track: Option<&'a TrackLog>,
+    pub date_fallback: DateFallback,
+    /// Timezone the camera's clock was set to, for files that don't record their UTC offset
+    pub camera_timezone: Option<Tz>,
+    /// Corrections for camera clocks that were set wrong
+    pub clock_offsets: Option<&'a ClockOffsets>,
+    /// Position that replaces whatever the file records, from an overrides file
+    pub position: Option<(f64, f64)>,
+}
+
+/// Corrections added to the capture times cameras recorded, for clocks that were off.
+#[derive(Debug, Clone, Default)]
+pub struct ClockOffsets {
+    /// Seconds added to every capture time
+    pub all: i64,
+    /// Seconds added on top of `all` for a camera model, e.g. ("NIKON D750", -90)
+    pub cameras: Vec<(String, i64)>,
+}
+
+impl ClockOffsets {
+    /// The correction for a file taken with `camera`, matched against the model with or without the
+    /// make in front and ignoring case.
+    pub fn for_camera(&self, make: Option<&str>, camera: Option<&str>) -> i64 {
+        let Some(camera) = camera else {
+            return self.all;
+        };
+        let full_name = make.map(|make| format!("{} {}", make, camera));
+        let matches = |name: &str| name.eq_ignore_ascii_case(camera) || full_name.as_deref().is_some_and(|full| name.eq_ignore_ascii_case(full));
+        self.all + self.cameras.iter().find(|(name, _)| matches(name)).map_or(0, |(_, offset)| *offset)
+    }
+}
+
+/// Reads the position and capture time of a photo or video. Files without an embedded position are
+/// placed on the track, if one is given.
+pub fn extract_metadata(path: &Path, options: &MetadataOptions) -> Result<PhotoMetadata, MissingMetadata> {
+    if scan::is_video(path) {
+        return video::extract_metadata(path, options);
+    }
+
+    // Files without any EXIF data can still be dated by the fallback and placed on a track
+    let exif = fs::File::open(path).ok()
+        .and_then(|file| exif::Reader::new().read_from_container(&mut std::io::BufReader::new(&file)).ok());
+
+    let camera = exif.as_ref().and_then(|exif| ascii_field(exif, Tag::Model));
+    let make = exif.as_ref().and_then(|exif| ascii_field(exif, Tag::Make));
+
+    let (date, time, subsec, recorded_utc) = match exif.as_ref().and_then(exif_date_time) {
+        Some((date, time)) => {
+            let exif = exif.as_ref().ok_or(MissingMetadata::Date)?;
+            let offset = options.clock_offsets.map_or(0, |offsets| offsets.for_camera(make.as_deref(), camera.as_deref()));
+            let (date, time) = correct_clock(date, time, offset);
+            let subsec = ascii_field(exif, Tag::SubSecTimeOriginal)
+                .or_else(|| ascii_field(exif, Tag::SubSecTime))
+                .filter(|s| s.chars().all(|c| c.is_ascii_digit()));
+
+            let recorded_utc = recorded_utc(exif, &date, time.as_deref());
+            (date, time, subsec, recorded_utc)
+        }
+        None => {
+            let (date, time) = fallback_date(path, options.date_fallback).ok_or(MissingMetadata::Date)?;
+            // Modification times are UTC to begin with, dates in file names are wall clock
+            let recorded_utc = match (options.date_fallback, time.as_deref()) {
+                (DateFallback::Mtime, Some(time)) => unix_time(&date, time),
+                _ => None,
+            };
+            (date, time, None, recorded_utc)
+        }
+    };
+
+    let fraction = subsec.as_deref().and_then(|s| format!("0.{}", s).parse::<f64>().ok()).unwrap_or(0.0);
+    let (timestamp, timestamp_is_utc) = capture_timestamp(&date, time.as_deref(), recorded_utc, options);
+    let timestamp = timestamp.map(|seconds| seconds as f64 + fraction);
+
+    let (lat, lon, position_source, altitude) = match (options.position, exif.as_ref().and_then(gps_position)) {
+        (Some((lat, lon)), _) => (lat, lon, PositionSource::Override, None),
+        (None, Some((lat, lon))) => (lat, lon, PositionSource::Embedded, exif.as_ref().and_then(gps_altitude)),
+        (None, None) => {
+            let (track, timestamp) = options.track.zip(timestamp).ok_or(MissingMetadata::Position)?;
+            let (lat, lon) = track.position_at(timestamp).ok_or(MissingMetadata::Position)?;
+            (lat, lon, track.source(), None)
+        }
+    };
+
+    Ok(PhotoMetadata {
+        lat,
+        lon,
+        position_source,
+        altitude,
+        timestamp,
+        timestamp_is_utc,
+        date,
+        time,
+        subsec,
+        camera,
+        make,
+        lens: exif.as_ref().and_then(|exif| ascii_field(exif, Tag::LensModel)),
+    })
+}
+
+/// What a file itself records about where, when and with what it was taken, whether or not that's
+/// enough to label it. Nothing is filled in from a track, the file's name or a clock correction.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RecordedMetadata {
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// Meters above sea level
+    pub altitude: Option<f64>,
+    /// Capture time as the camera's clock showed it, e.g. "2023-10-24T12:00:00"
+    pub taken: Option<String>,
+    /// Capture time in UTC, when the file records its UTC offset or the GPS time
+    pub taken_utc: Option<String>,
+    pub make: Option<String>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+}
+
+/// Reads what a photo or video records about itself, leaving out whatever it doesn't.
+pub fn recorded_metadata(path: &Path) -> RecordedMetadata {
+    if scan::is_video(path) {
+        return video::recorded_metadata(path);
+    }
+    let Some(exif) = fs::File::open(path).ok()
+        .and_then(|file| exif::Reader::new().read_from_container(&mut std::io::BufReader::new(&file)).ok())
+    else {
+        return RecordedMetadata::default();
+    };
+
+    let position = gps_position(&exif);
+    let taken = exif_date_time(&exif);
+    let utc = match &taken {
+        Some((date, time)) => recorded_utc(&exif, date, time.as_deref()),
+        None => gps_timestamp(&exif),
+    };
+    RecordedMetadata {
+        lat: position.map(|(lat, _)| lat),
+        lon: position.map(|(_, lon)| lon),
+        altitude: position.and_then(|_| gps_altitude(&exif)),
+        taken: taken.map(|(date, time)| iso_8601(&date, time.as_deref())),
+        taken_utc: utc.and_then(iso_8601_utc),
+        make: ascii_field(&exif, Tag::Make),
+        camera: ascii_field(&exif, Tag::Model),
+        lens: ascii_field(&exif, Tag::LensModel),
+    }
+}
+
+/// Formats seconds since the Unix epoch as an ISO 8601 time in UTC, e.g. "2023-10-24T10:00:00Z".
+pub fn iso_8601_utc(seconds: i64) -> Option<String> {
+    let (date, time) = format_unix_time(u64::try_from(seconds).ok()?);
+    Some(format!("{}Z", iso_8601(&date, Some(&time))))
+}
+
+/// Seconds since the Unix epoch for a capture time, and whether that is known to be UTC. Without a
+/// recorded UTC time or a camera timezone the wall clock time is read as UTC, which a track's clock
+/// offset can then correct.
+pub fn capture_timestamp(date: &str, time: Option<&str>, recorded_utc: Option<i64>, options: &MetadataOptions) -> (Option<i64>, bool) {
+    if let Some(utc) = recorded_utc {
+        return (Some(utc), true);
+    }
+
+    let Some(time) = time else {
+        return (None, false);
+    };
+
+    match options.camera_timezone {
+        Some(timezone) => match local_to_utc(date, time, timezone) {
+            Some(utc) => (Some(utc), true),
+            None => (unix_time(date, time), false),
+        },
+        None => (unix_time(date, time), false),
+    }
+}
+
+/// Adds a clock correction to a capture date and time. Dates without a time are left alone, it
+/// isn't known whether the correction would move them to another day.
+pub fn correct_clock(date: String, time: Option<String>, offset: i64) -> (String, Option<String>) {
+    match time.as_deref().filter(|_| offset != 0).and_then(|time| shift_date_time(&date, time, offset)) {
+        Some((date, time)) => (date, Some(time)),
+        None => (date, time),
+    }
+}
+
+/// Capture date (yyyyMMdd) and time (HHmmss) a photo records, whether or not it has a position.
+pub fn capture_date(path: &Path) -> Option<(String, Option<String>)> {
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(&file)).ok()?;
+    exif_date_time(&exif)
+}
+
+// EXIF times are local, so the UTC instant is only known from a recorded offset or the GPS
+// receiver's clock, which is right even when the camera's isn't
+fn recorded_utc(exif: &exif::Exif, date: &str, time: Option<&str>) -> Option<i64> {
+    let offset = ascii_field(exif, Tag::OffsetTimeOriginal)
+        .or_else(|| ascii_field(exif, Tag::OffsetTime))
+        .and_then(|offset| parse_utc_offset(&offset));
+    match (offset, time) {
+        (Some(offset), Some(time)) => unix_time(date, time).map(|seconds| seconds - offset),
+        _ => gps_timestamp(exif),
+    }
+}
+
+// The GPS date and time stamps, which are always UTC
+fn gps_timestamp(exif: &exif::Exif) -> Option<i64> {
+    let date = ascii_field(exif, Tag::GPSDateStamp)?.replace([':', '-'], "");
+    let time = match exif.get_field(Tag::GPSTimeStamp, In::PRIMARY)?.value {
+        exif::Value::Rational(ref v) if v.len() >= 3 => {
+            format!("{:02}{:02}{:02}", v[0].to_f64() as u32, v[1].to_f64() as u32, v[2].to_f64() as u32)
+        }
+        _ => return None,
+    };
+    unix_time(&date, &time)
+}
+
+// Capture date (yyyyMMdd) and time (HHmmss) from DateTimeOriginal, falling back to DateTime
+fn exif_date_time(exif: &exif::Exif) -> Option<(String, Option<String>)> {
+    let date_str = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?
+        .display_value()
+        .to_string();
+
+    // Format yyyy:mm:dd hh:mm:ss to yyyyMMdd
+    // exif display_value is often "2023:10:24 12:00:00"
+    let digits = date_str.chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<String>();
+
+    if digits.len() < 8 {
+        return None;
+    }
+
+    Some((digits[..8].to_string(), digits.get(8..14).map(str::to_string)))
+}
+
+/// Capture date (yyyyMMdd) and time (HHmmss) for a file whose metadata has none.
+pub fn fallback_date(path: &Path, fallback: DateFallback) -> Option<(String, Option<String>)> {
+    match fallback {
+        DateFallback::Skip => None,
+        DateFallback::Mtime => {
+            let modified = fs::metadata(path).ok()?.modified().ok()?;
+            let seconds = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+            let (date, time) = format_unix_time(seconds);
+            Some((date, Some(time)))
+        }
+        DateFallback::Filename => parse_filename_date(path.file_stem()?.to_str()?),
+    }
+}
+
+fn gps_position(exif: &exif::Exif) -> Option<(f64, f64)> {
+    let lat = exif.get_field(Tag::GPSLatitude, In::PRIMARY)?;
+    let lat_ref = exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY)?;
+    let lon = exif.get_field(Tag::GPSLongitude, In::PRIMARY)?;
+    let lon_ref = exif.get_field(Tag::GPSLongitudeRef, In::PRIMARY)?;
+
+    let latitude = to_decimal(lat)?;
+    let longitude = to_decimal(lon)?;
+
+    let lat_final = if lat_ref.display_value().to_string().contains('S') { -latitude } else { latitude };
+    let lon_final = if lon_ref.display_value().to_string().contains('W') { -longitude } else { longitude };
+    Some((lat_final, lon_final))
+}
+
+// GPSAltitudeRef 1 means the altitude is below sea level
+fn gps_altitude(exif: &exif::Exif) -> Option<f64> {
+    let altitude = match exif.get_field(Tag::GPSAltitude, In::PRIMARY)?.value {
+        exif::Value::Rational(ref v) if !v.is_empty() && v[0].denom != 0 => v[0].to_f64(),
+        _ => return None,
+    };
+    let below = exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY).and_then(|field| field.value.get_uint(0)) == Some(1);
+    Some(if below { -altitude } else { altitude })
+}
+
+fn ascii_field(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        exif::Value::Ascii(ref v) => {
+            let value = v.first().map(|s| String::from_utf8_lossy(s).trim().to_string())?;
+            if value.is_empty() { None } else { Some(value) }
+        }
+        _ => None,
+    }
+}
+
+fn to_decimal(field: &exif::Field) -> Option<f64> {
+    if let exif::Value::Rational(ref v) = field.value && v.len() >= 3 {
+        let degrees = v[0].to_f64();
+        let minutes = v[1].to_f64();
+        let seconds = v[2].to_f64();
+        return Some(degrees + minutes / 60.0 + seconds / 3600.0);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_offsets_add_to_the_offset_for_all() {
+        let offsets = ClockOffsets { all: 3600, cameras: vec![("NIKON D750".to_string(), -90), ("iPhone 12".to_string(), 30)] };
+        assert_eq!(offsets.for_camera(Some("NIKON CORPORATION"), Some("NIKON D750")), 3510);
+        assert_eq!(offsets.for_camera(Some("Apple"), Some("IPHONE 12")), 3630);
+        assert_eq!(offsets.for_camera(Some("Canon"), Some("EOS R6")), 3600);
+        assert_eq!(offsets.for_camera(None, None), 3600);
+    }
+
+    #[test]
+    fn camera_offsets_match_the_model_with_its_make() {
+        let offsets = ClockOffsets { all: 0, cameras: vec![("Canon EOS R6".to_string(), 120)] };
+        assert_eq!(offsets.for_camera(Some("Canon"), Some("EOS R6")), 120);
+        assert_eq!(offsets.for_camera(None, Some("EOS R6")), 0);
+    }
+
+    #[test]
+    fn corrections_only_move_dates_with_a_time() {
+        let corrected = correct_clock("20231231".to_string(), Some("235930".to_string()), 60);
+        assert_eq!(corrected, ("20240101".to_string(), Some("000030".to_string())));
+        let corrected = correct_clock("20231231".to_string(), None, 86_400);
+        assert_eq!(corrected, ("20231231".to_string(), None));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::geocoder::{GeocodeError, GeocodeResponse, ReverseGeocoder};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The parts of a run that are timed separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Finding the files and skipping those labeled already
+    Scan,
+    /// Reading positions and capture times, and placing files on tracks
+    Metadata,
+    /// Looking up the locations that weren't cached
+    Geocode,
+    /// Carrying out the renames, and whatever else happens to the files afterwards
+    Rename,
+}
+
+/// Where a run spent its time and how much it asked of the geocoder, over every directory it
+/// labeled. Times are in seconds.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Metrics {
+    pub scan_seconds: f64,
+    pub metadata_seconds: f64,
+    pub geocode_seconds: f64,
+    pub rename_seconds: f64,
+    pub total_seconds: f64,
+    /// Files whose location was in the geocode cache
+    pub cache_hits: usize,
+    /// Files whose location had to be looked up
+    pub cache_misses: usize,
+    /// Share of the files with a position that were answered from the cache, from 0 to 1
+    pub cache_hit_rate: Option<f64>,
+    /// Requests sent to the geocoder, counting retries, batches and points of interest
+    pub geocoder_requests: usize,
+}
+
+impl Metrics {
+    pub fn add(&mut self, stage: Stage, elapsed: Duration) {
+        let seconds = match stage {
+            Stage::Scan => &mut self.scan_seconds,
+            Stage::Metadata => &mut self.metadata_seconds,
+            Stage::Geocode => &mut self.geocode_seconds,
+            Stage::Rename => &mut self.rename_seconds,
+        };
+        *seconds += elapsed.as_secs_f64();
+    }
+
+    pub fn add_cache_lookups(&mut self, hits: usize, misses: usize) {
+        self.cache_hits += hits;
+        self.cache_misses += misses;
+        let lookups = self.cache_hits + self.cache_misses;
+        self.cache_hit_rate = (lookups > 0).then(|| self.cache_hits as f64 / lookups as f64);
+    }
+
+    /// Writes the metrics as JSON.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+}
+
+/// A geocoder that counts the requests going through it, whether or not they succeed.
+pub struct Counted {
+    geocoder: Box<dyn ReverseGeocoder>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Counted {
+    pub fn new(geocoder: Box<dyn ReverseGeocoder>, requests: Arc<AtomicUsize>) -> Counted {
+        Counted { geocoder, requests }
+    }
+
+    fn count(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for Counted {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        self.count();
+        self.geocoder.reverse(lat, lon).await
+    }
+
+    fn rate_limited(&self) -> bool {
+        self.geocoder.rate_limited()
+    }
+
+    async fn nearby_poi(&self, lat: f64, lon: f64) -> Result<Option<String>, GeocodeError> {
+        self.count();
+        self.geocoder.nearby_poi(lat, lon).await
+    }
+
+    fn batch_size(&self) -> usize {
+        self.geocoder.batch_size()
+    }
+
+    async fn reverse_batch(&self, positions: &[(f64, f64)]) -> Result<Vec<Result<GeocodeResponse, GeocodeError>>, GeocodeError> {
+        self.count();
+        self.geocoder.reverse_batch(positions).await
+    }
+}
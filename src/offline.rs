@@ -0,0 +1,153 @@
+use crate::geo::haversine_km;
+use crate::geocoder::{Address, GeocodeError, GeocodeResponse, ReverseGeocoder};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Size of the grid cells places are bucketed into, in degrees
+const CELL_SIZE: f64 = 1.0;
+
+// Stop widening the search once this many rings of cells have been inspected
+const MAX_SEARCH_RINGS: i32 = 10;
+
+struct Place {
+    name: String,
+    lat: f64,
+    lon: f64,
+    country_code: String,
+}
+
+/// Resolves coordinates to the nearest populated place in a GeoNames dump (e.g. `cities1000.txt`)
+/// without any network access. If GeoNames' `countryInfo.txt` sits next to the dump it is used to
+/// fill in country names.
+pub struct OfflineGeocoder {
+    places: Vec<Place>,
+    grid: HashMap<(i32, i32), Vec<usize>>,
+    countries: HashMap<String, String>,
+}
+
+impl OfflineGeocoder {
+    pub fn load(dataset: &Path) -> Result<OfflineGeocoder, GeocodeError> {
+        let contents = fs::read_to_string(dataset)
+            .map_err(|e| format!("failed to read offline dataset {}: {}", dataset.display(), e))?;
+
+        let mut places = Vec::new();
+        let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+        for line in contents.lines() {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 9 {
+                continue;
+            }
+
+            // Only populated places (cities, towns, villages) are useful as labels
+            if fields[6] != "P" {
+                continue;
+            }
+
+            let (Ok(lat), Ok(lon)) = (fields[4].parse::<f64>(), fields[5].parse::<f64>()) else {
+                continue;
+            };
+
+            grid.entry(cell(lat, lon)).or_default().push(places.len());
+            places.push(Place {
+                name: fields[1].to_string(),
+                lat,
+                lon,
+                country_code: fields[8].to_string(),
+            });
+        }
+
+        if places.is_empty() {
+            return Err(format!("offline dataset {} contains no populated places", dataset.display()).into());
+        }
+
+        let countries = dataset.parent()
+            .map(|dir| dir.join("countryInfo.txt"))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| parse_country_info(&contents))
+            .unwrap_or_default();
+
+        Ok(OfflineGeocoder { places, grid, countries })
+    }
+
+    fn nearest(&self, lat: f64, lon: f64) -> Option<&Place> {
+        let (row, col) = cell(lat, lon);
+        let mut best: Option<(f64, usize)> = None;
+        let mut found_in_ring = None;
+
+        for ring in 0..=MAX_SEARCH_RINGS {
+            // A match near the edge of a cell can still be beaten by one in the next ring, but
+            // anything beyond that is further away
+            if found_in_ring.is_some_and(|found| ring > found + 1) {
+                break;
+            }
+
+            for r in row - ring..=row + ring {
+                for c in col - ring..=col + ring {
+                    // Only visit the outer edge of the ring, the inside was covered already
+                    if (r - row).abs() != ring && (c - col).abs() != ring {
+                        continue;
+                    }
+
+                    for &index in self.grid.get(&(r, c)).into_iter().flatten() {
+                        let place = &self.places[index];
+                        let distance = haversine_km(lat, lon, place.lat, place.lon);
+                        if best.is_none_or(|(d, _)| distance < d) {
+                            best = Some((distance, index));
+                        }
+                    }
+                }
+            }
+
+            if best.is_some() && found_in_ring.is_none() {
+                found_in_ring = Some(ring);
+            }
+        }
+
+        best.map(|(_, index)| &self.places[index])
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for OfflineGeocoder {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        let place = self.nearest(lat, lon).ok_or("no place found nearby in the offline dataset")?;
+        let country = self.countries.get(&place.country_code).cloned();
+
+        let display_name = match &country {
+            Some(country) => format!("{}, {}", place.name, country),
+            None => place.name.clone(),
+        };
+
+        Ok(GeocodeResponse {
+            display_name,
+            address: Address {
+                city: Some(place.name.clone()),
+                country,
+                country_code: Some(place.country_code.to_lowercase()),
+                ..Address::default()
+            },
+        })
+    }
+
+    fn rate_limited(&self) -> bool {
+        false
+    }
+}
+
+fn cell(lat: f64, lon: f64) -> (i32, i32) {
+    ((lat / CELL_SIZE).floor() as i32, (lon / CELL_SIZE).floor() as i32)
+}
+
+// countryInfo.txt is tab separated with the ISO code in the first and the name in the fifth column
+fn parse_country_info(contents: &str) -> HashMap<String, String> {
+    contents.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            Some((fields.first()?.to_string(), fields.get(4)?.to_string()))
+        })
+        .collect()
+}
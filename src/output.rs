@@ -0,0 +1,430 @@
+use crate::geocoder::Address;
+use crate::map::write_map;
+use crate::metadata::MissingMetadata;
+use crate::metrics::Metrics;
+use crate::stats::Stats;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Progress messages for people reading along
+    #[default]
+    Text,
+    /// A single JSON array with a record per file, written once the run is done
+    Json,
+    /// A JSON record per line, written as soon as each file is done
+    Ndjson,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    /// Part of a dry run, or not carried out yet
+    #[default]
+    Planned,
+    Renamed,
+    Copied,
+    Moved,
+    Linked,
+    /// Already had the name it would get
+    Unchanged,
+    SidecarWritten,
+    Skipped,
+    /// Identical to another file, and handled as --on-duplicate says
+    Duplicate,
+    Failed,
+}
+
+/// What happened to a single file (or group of paired files, by their primary file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub new_path: Option<PathBuf>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub date: Option<String>,
+    pub address: Option<Address>,
+    #[serde(default)]
+    pub status: FileStatus,
+    /// Why the file was skipped or failed
+    pub reason: Option<String>,
+    /// SHA-256 of the file under its new name, with --checksums
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip)]
+    pub missing: Option<MissingMetadata>,
+}
+
+impl FileRecord {
+    pub fn new(path: PathBuf, status: FileStatus) -> FileRecord {
+        FileRecord {
+            path,
+            new_path: None,
+            lat: None,
+            lon: None,
+            date: None,
+            address: None,
+            status,
+            reason: None,
+            sha256: None,
+            missing: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> FileRecord {
+        self.reason = Some(reason.into());
+        self
+    }
+}
+
+/// Tally of what happened to the files in a run.
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct Summary {
+    /// Renamed, copied, moved or given a sidecar
+    pub done: usize,
+    /// Would have been done, in a dry run
+    pub planned: usize,
+    pub unchanged: usize,
+    pub no_gps: usize,
+    pub no_date: usize,
+    pub geocode_failures: usize,
+    /// Skipped for any other reason, e.g. because they were labeled already
+    pub skipped: usize,
+    pub duplicates: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    fn add(&mut self, record: &FileRecord) {
+        match (record.status, record.missing) {
+            (FileStatus::Renamed | FileStatus::Copied | FileStatus::Moved | FileStatus::Linked | FileStatus::SidecarWritten, _) => self.done += 1,
+            (FileStatus::Planned, _) => self.planned += 1,
+            (FileStatus::Unchanged, _) => self.unchanged += 1,
+            (FileStatus::Skipped, Some(MissingMetadata::Position)) => self.no_gps += 1,
+            (FileStatus::Skipped, Some(MissingMetadata::Date)) => self.no_date += 1,
+            (FileStatus::Skipped, None) => self.skipped += 1,
+            (FileStatus::Duplicate, _) => self.duplicates += 1,
+            // Geocoding failures are the only failures recorded before anything is planned
+            (FileStatus::Failed, _) if record.new_path.is_none() => self.geocode_failures += 1,
+            (FileStatus::Failed, _) => self.failed += 1,
+        }
+    }
+}
+
+/// Writes file records to stdout in the chosen format, and to the manifest and map files if they
+/// were asked for. Text output has no records, everything it has to say is in the progress messages.
+#[derive(Debug, Default)]
+pub struct Report {
+    format: OutputFormat,
+    manifest: Option<PathBuf>,
+    map: Option<PathBuf>,
+    stats: bool,
+    records: Vec<FileRecord>,
+    summary: Summary,
+    rejected_key: Option<String>,
+    keep_records: bool,
+    counter: Option<Arc<AtomicUsize>>,
+    metrics: Metrics,
+}
+
+impl Report {
+    pub fn new(format: OutputFormat, manifest: Option<PathBuf>) -> Report {
+        Report { format, manifest, map: None, stats: false, records: Vec::new(), summary: Summary::default(), rejected_key: None, keep_records: false, counter: None, metrics: Metrics::default() }
+    }
+
+    /// Also writes the located files to a GeoJSON or KML map once the run is done.
+    pub fn with_map(mut self, map: Option<PathBuf>) -> Report {
+        self.map = map;
+        self
+    }
+
+    /// Also prints a breakdown of the files by country, city and month once the run is done.
+    pub fn with_stats(mut self, stats: bool) -> Report {
+        self.stats = stats;
+        self
+    }
+
+    /// Holds on to every record, to hand them over with `into_records` instead of printing them.
+    pub fn keeping_records(mut self) -> Report {
+        self.keep_records = true;
+        self
+    }
+
+    /// Counts the records as they come in, so the progress of the run can be followed from
+    /// another thread.
+    pub fn with_counter(mut self, counter: Arc<AtomicUsize>) -> Report {
+        self.counter = Some(counter);
+        self
+    }
+
+    pub fn into_records(self) -> Vec<FileRecord> {
+        self.records
+    }
+
+    pub fn summary(&self) -> Summary {
+        self.summary
+    }
+
+    /// Notes that the geocoding service rejected the API key, which ends the run with its own exit code.
+    pub fn reject_key(&mut self, message: String) {
+        self.rejected_key.get_or_insert(message);
+    }
+
+    pub fn rejected_key(&self) -> Option<&str> {
+        self.rejected_key.as_deref()
+    }
+
+    /// Timings and geocoder usage, added to by every directory the run labels.
+    pub fn metrics(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+
+    pub fn add(&mut self, record: FileRecord) {
+        self.summary.add(&record);
+        if let Some(counter) = &self.counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.format == OutputFormat::Ndjson {
+            match serde_json::to_string(&record) {
+                Ok(line) => println!("{}", line),
+                Err(e) => error!("Error writing record for {:?}: {}", record.path, e),
+            }
+        }
+        if self.format == OutputFormat::Json || self.manifest.is_some() || self.map.is_some() || self.stats || self.keep_records {
+            self.records.push(record);
+        }
+    }
+
+    /// Writes the records that were held back until the end of the run.
+    pub fn finish(&mut self) {
+        if self.format == OutputFormat::Json {
+            match serde_json::to_string_pretty(&self.records) {
+                Ok(records) => println!("{}", records),
+                Err(e) => error!("Error writing records: {}", e),
+            }
+        }
+
+        if let Some(manifest) = self.manifest.take() {
+            match write_manifest(&manifest, &self.records) {
+                Ok(()) => info!("Wrote manifest: {:?}", manifest),
+                Err(e) => error!("Error writing manifest {:?}: {}", manifest, e),
+            }
+        }
+
+        if let Some(map) = self.map.take() {
+            match write_map(&map, &self.records) {
+                Ok(()) => info!("Wrote map: {:?}", map),
+                Err(e) => error!("Error writing map {:?}: {}", map, e),
+            }
+        }
+        if self.stats {
+            print!("{}", Stats::new(&self.records));
+        }
+        self.records.clear();
+    }
+}
+
+/// Writes the records as CSV, or as JSON when the path ends in `.json`.
+pub fn write_manifest(path: &Path, records: &[FileRecord]) -> std::io::Result<()> {
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json")) {
+        return fs::write(path, serde_json::to_string_pretty(records)?);
+    }
+
+    let mut csv = String::from("path,new_path,status,lat,lon,date,road,city,country,country_code,reason,sha256\n");
+    for record in records {
+        let address = record.address.as_ref();
+        let city = address.and_then(|a| a.city.as_ref().or(a.town.as_ref()).or(a.village.as_ref()));
+        let fields = [
+            Some(record.path.display().to_string()),
+            record.new_path.as_ref().map(|path| path.display().to_string()),
+            serde_json::to_value(record.status).ok().and_then(|status| status.as_str().map(str::to_string)),
+            record.lat.map(|lat| lat.to_string()),
+            record.lon.map(|lon| lon.to_string()),
+            record.date.clone(),
+            address.and_then(|a| a.road.clone()),
+            city.cloned(),
+            address.and_then(|a| a.country.clone()),
+            address.and_then(|a| a.country_code.clone()),
+            record.reason.clone(),
+            record.sha256.clone(),
+        ];
+        let row = fields.iter().map(|field| csv_field(field.as_deref().unwrap_or(""))).collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    fs::write(path, csv)
+}
+
+/// Reads the records of a manifest written by `write_manifest`. Only the path is required, so
+/// hand-made manifests with just a few of the columns can be read too.
+pub fn read_manifest(path: &Path) -> std::io::Result<Vec<FileRecord>> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+    let contents = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json")) {
+        return serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()));
+    }
+
+    let mut rows = parse_csv(&contents).into_iter();
+    let header = rows.next().unwrap_or_default();
+    rows.enumerate()
+        .map(|(index, row)| {
+            let field = |name: &str| header.iter().position(|column| column == name)
+                .and_then(|column| row.get(column))
+                .filter(|value| !value.is_empty())
+                .cloned();
+            let coordinate = |name: &str| field(name)
+                .map(|value| value.parse::<f64>().map_err(|_| invalid(format!("invalid {} {:?} on line {}", name, value, index + 2))))
+                .transpose();
+
+            let path = field("path").ok_or_else(|| invalid(format!("no path on line {}", index + 2)))?;
+            let mut record = FileRecord::new(PathBuf::from(path), FileStatus::default());
+            if let Some(status) = field("status") {
+                record.status = serde_json::from_value(serde_json::Value::String(status.clone()))
+                    .map_err(|_| invalid(format!("invalid status {:?} on line {}", status, index + 2)))?;
+            }
+            record.new_path = field("new_path").map(PathBuf::from);
+            record.lat = coordinate("lat")?;
+            record.lon = coordinate("lon")?;
+            record.date = field("date");
+            record.reason = field("reason");
+            record.sha256 = field("sha256");
+            if ["road", "city", "country", "country_code"].iter().any(|name| field(name).is_some()) {
+                record.address = Some(Address {
+                    road: field("road"),
+                    city: field("city"),
+                    country: field("country"),
+                    country_code: field("country_code"),
+                    ..Address::default()
+                });
+            }
+            Ok(record)
+        })
+        .collect()
+}
+
+/// Splits CSV into rows of fields, undoing the quoting `csv_field` applies.
+pub fn parse_csv(contents: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Quotes a field when it contains anything CSV treats specially.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("image-labeler-{}-{}", std::process::id(), name))
+    }
+
+    fn renamed_record() -> FileRecord {
+        let mut record = FileRecord::new(PathBuf::from("photos/IMG_1234.jpg"), FileStatus::Renamed);
+        record.new_path = Some(PathBuf::from("photos/20231024_1_NL, \"Dam\", Amsterdam.jpg"));
+        record.lat = Some(52.373);
+        record.lon = Some(4.8924);
+        record.date = Some("20231024".to_string());
+        record.address = Some(Address {
+            road: Some("Dam".to_string()),
+            city: Some("Amsterdam".to_string()),
+            country: Some("Nederland".to_string()),
+            country_code: Some("nl".to_string()),
+            ..Address::default()
+        });
+        record.sha256 = Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string());
+        record
+    }
+
+    fn assert_round_trips(name: &str) {
+        let path = manifest_path(name);
+        let failed = FileRecord::new(PathBuf::from("photos/IMG_1235.jpg"), FileStatus::Failed).with_reason("couldn't write,\nthe disk is full");
+        write_manifest(&path, &[renamed_record(), failed]).unwrap();
+        let records = read_manifest(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        let expected = renamed_record();
+        assert_eq!(records[0].path, expected.path);
+        assert_eq!(records[0].new_path, expected.new_path);
+        assert_eq!(records[0].status, FileStatus::Renamed);
+        assert_eq!((records[0].lat, records[0].lon), (expected.lat, expected.lon));
+        assert_eq!(records[0].date, expected.date);
+        assert_eq!(records[0].sha256, expected.sha256);
+        let address = records[0].address.as_ref().unwrap();
+        assert_eq!(address.road.as_deref(), Some("Dam"));
+        assert_eq!(address.city.as_deref(), Some("Amsterdam"));
+        assert_eq!(address.country_code.as_deref(), Some("nl"));
+        assert_eq!(records[1].status, FileStatus::Failed);
+        assert_eq!(records[1].reason.as_deref(), Some("couldn't write,\nthe disk is full"));
+        assert!(records[1].address.is_none());
+    }
+
+    #[test]
+    fn csv_manifests_round_trip() {
+        assert_round_trips("manifest.csv");
+    }
+
+    #[test]
+    fn json_manifests_round_trip() {
+        assert_round_trips("manifest.json");
+    }
+
+    #[test]
+    fn hand_made_manifests_only_need_a_path() {
+        let path = manifest_path("hand-made.csv");
+        fs::write(&path, "status,path\r\nrenamed,a.jpg\r\n,b.jpg\r\n").unwrap();
+        let records = read_manifest(&path);
+        fs::write(&path, "path,lat\na.jpg,north\n").unwrap();
+        let invalid = read_manifest(&path);
+        fs::remove_file(&path).unwrap();
+
+        let records = records.unwrap();
+        assert_eq!(records.iter().map(|record| record.path.as_path()).collect::<Vec<_>>(), [Path::new("a.jpg"), Path::new("b.jpg")]);
+        assert_eq!(records[0].status, FileStatus::Renamed);
+        assert_eq!(records[1].status, FileStatus::default());
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn quoted_fields_are_unquoted() {
+        let rows = parse_csv(&format!("{},{}\n", csv_field("a, \"b\""), csv_field("c")));
+        assert_eq!(rows, [["a, \"b\"", "c"]]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::checksum::sha256;
+use crate::geocoder::{Address, GeocodeResponse};
+use crate::output::parse_csv;
+use crate::scan::{is_sidecar, FileGroup};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// A correction for a single file, such as a photo taken indoors or underground that recorded a
+/// stale GPS fix. Either its coordinates, which are geocoded as usual, or the location to name it
+/// after, or both.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    /// File name, or path relative to the directory being labeled
+    pub file: Option<String>,
+    /// SHA-256 of the file's contents, which still matches after it was renamed
+    pub sha256: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// Location to name the file after instead of geocoding, e.g. "Kitchen, Amsterdam". Files that
+    /// record no position need lat and lon as well
+    pub location: Option<String>,
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+}
+
+impl Override {
+    fn validate(&self) -> Result<(), String> {
+        if self.file.is_none() && self.sha256.is_none() {
+            return Err("needs a file or sha256".to_string());
+        }
+        match (self.lat, self.lon) {
+            (Some(lat), Some(lon)) if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) => {
+                Err(format!("coordinates {}, {} are out of range", lat, lon))
+            }
+            (Some(_), Some(_)) => Ok(()),
+            (None, None) if self.location.is_some() => Ok(()),
+            (None, None) => Err("needs lat and lon, a location, or both".to_string()),
+            _ => Err("needs both lat and lon".to_string()),
+        }
+    }
+
+    /// Coordinates that replace whatever the file records.
+    pub fn position(&self) -> Option<(f64, f64)> {
+        self.lat.zip(self.lon)
+    }
+
+    /// The response the geocoder is bypassed with, when the override names a location.
+    pub fn response(&self) -> Option<GeocodeResponse> {
+        let location = self.location.as_ref()?;
+        let display_name = match &self.country {
+            Some(country) => format!("{}, {}", location, country),
+            None => location.clone(),
+        };
+        Some(GeocodeResponse {
+            display_name,
+            address: Address {
+                city: Some(location.clone()),
+                country: self.country.clone(),
+                country_code: self.country_code.as_deref().map(str::to_lowercase),
+                ..Address::default()
+            },
+        })
+    }
+
+    fn names(&self, relative: &str, name: &str) -> bool {
+        self.file.as_deref().is_some_and(|file| {
+            let file = file.replace('\\', "/");
+            file == relative || file == name
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideFile {
+    #[serde(default, rename = "override")]
+    overrides: Vec<Override>,
+}
+
+/// Corrections read from an overrides file, which take precedence over both the files' metadata
+/// and the geocoder.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    entries: Vec<Override>,
+}
+
+impl Overrides {
+    /// Reads `[[override]]` tables from a TOML file, or rows with a `file` or `sha256` column from
+    /// a CSV file, along with `lat`, `lon`, `location`, `country` and `country_code` columns.
+    pub fn load(path: &Path) -> Result<Overrides, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let entries = if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv")) {
+            parse_rows(&contents).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            toml::from_str::<OverrideFile>(&contents).map_err(|e| format!("{}: {}", path.display(), e))?.overrides
+        };
+
+        for (index, entry) in entries.iter().enumerate() {
+            entry.validate().map_err(|e| format!("{}: override {} {}", path.display(), index + 1, e))?;
+        }
+        Ok(Overrides { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The override for each group, keyed by its primary file. A group matches when any of its
+    /// files has the name, relative path or checksum of an entry; the first entry that matches
+    /// wins. Checksums are only computed when an entry has one.
+    pub fn find(&self, dir: &Path, groups: &[FileGroup]) -> HashMap<PathBuf, &Override> {
+        let by_checksum = self.entries.iter().any(|entry| entry.sha256.is_some());
+        groups.par_iter()
+            .filter_map(|group| {
+                let members = group.members.iter().filter(|member| !is_sidecar(member)).collect::<Vec<_>>();
+                let checksums = if by_checksum {
+                    members.iter()
+                        .filter_map(|member| sha256(member).map_err(|e| warn!("Warning: Couldn't read {:?} to match it against the overrides: {}", member, e)).ok())
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                let entry = self.entries.iter().find(|entry| {
+                    let named = members.iter().any(|member| {
+                        let relative = member.strip_prefix(dir).unwrap_or(member).to_string_lossy().replace('\\', "/");
+                        let name = member.file_name().unwrap_or_default().to_string_lossy();
+                        entry.names(&relative, &name)
+                    });
+                    named || entry.sha256.as_ref().is_some_and(|expected| checksums.iter().any(|checksum| checksum.eq_ignore_ascii_case(expected)))
+                })?;
+                Some((group.primary().to_path_buf(), entry))
+            })
+            .collect()
+    }
+}
+
+fn parse_rows(contents: &str) -> Result<Vec<Override>, String> {
+    let mut rows = parse_csv(contents).into_iter();
+    let header = rows.next().unwrap_or_default();
+    if let Some(unknown) = header.iter().find(|column| !["file", "sha256", "lat", "lon", "location", "country", "country_code"].contains(&column.as_str())) {
+        return Err(format!("unknown column {:?}", unknown));
+    }
+
+    rows.enumerate()
+        .map(|(index, row)| {
+            let field = |name: &str| header.iter().position(|column| column == name)
+                .and_then(|column| row.get(column))
+                .filter(|value| !value.is_empty())
+                .cloned();
+            let coordinate = |name: &str| field(name)
+                .map(|value| value.parse::<f64>().map_err(|_| format!("invalid {} {:?} on line {}", name, value, index + 2)))
+                .transpose();
+
+            Ok(Override {
+                file: field("file"),
+                sha256: field("sha256"),
+                lat: coordinate("lat")?,
+                lon: coordinate("lon")?,
+                location: field("location"),
+                country: field("country"),
+                country_code: field("country_code"),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,142 @@
+use crate::filename::is_reserved_name;
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Longest path Windows accepts without the verbatim prefix, including the terminating null
+const MAX_PATH: usize = 260;
+
+/// Canonicalizes a path like `fs::canonicalize`, but in the form users know it by. On Windows that
+/// returns verbatim paths such as `\\?\C:\Photos` or `\\?\UNC\nas\photos`, which don't compare
+/// equal to the `C:\Photos` or `\\nas\photos` given on the command line and look odd in messages.
+/// Paths that only work verbatim, such as those longer than MAX_PATH, stay verbatim; the standard
+/// library adds the prefix to long paths it's given by itself.
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    fs::canonicalize(path).map(|path| simplify(&path))
+}
+
+/// The usual form of a verbatim Windows path, or the path as it is everywhere else.
+pub fn simplify(path: &Path) -> PathBuf {
+    if cfg!(windows) && let Some(usual) = path.to_str().and_then(without_verbatim_prefix) {
+        return PathBuf::from(usual);
+    }
+    path.to_path_buf()
+}
+
+/// Turns a verbatim path into the usual form, e.g. `C:\Photos` for `\\?\C:\Photos` and
+/// `\\nas\photos` for `\\?\UNC\nas\photos`. None when the path isn't verbatim, or only refers to
+/// the same file in verbatim form: Windows limits other paths to MAX_PATH, trims trailing dots and
+/// spaces off names and reads names like CON as devices.
+pub fn without_verbatim_prefix(path: &str) -> Option<String> {
+    let usual = if let Some(share) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", share)
+    } else {
+        let local = path.strip_prefix(r"\\?\")?;
+        // Only drive letters, not volume GUIDs or devices
+        let mut chars = local.chars();
+        if !(chars.next()?.is_ascii_alphabetic() && chars.next()? == ':' && matches!(chars.next(), None | Some('\\'))) {
+            return None;
+        }
+        local.to_string()
+    };
+
+    let names_survive = usual.split('\\')
+        .filter(|name| !name.is_empty())
+        .all(|name| !name.ends_with(['.', ' ']) && !is_reserved_name(name) && !name.contains('/'));
+    (usual.encode_utf16().count() < MAX_PATH && names_survive).then_some(usual)
+}
+
+/// The path in a form Windows opens past MAX_PATH, for renaming into deeply nested folders: long
+/// paths get the verbatim prefix, shorter ones and paths everywhere else are returned as they are.
+pub fn extended(path: &Path) -> Cow<'_, Path> {
+    if cfg!(windows)
+        && let Ok(absolute) = std::path::absolute(path)
+        && let Some(verbatim) = absolute.to_str().and_then(with_verbatim_prefix)
+    {
+        return Cow::Owned(PathBuf::from(verbatim));
+    }
+    Cow::Borrowed(path)
+}
+
+/// Turns an absolute Windows path of MAX_PATH or longer into its verbatim form, e.g.
+/// `\\?\C:\Photos\...` for `C:\Photos\...` and `\\?\UNC\nas\photos\...` for `\\nas\photos\...`.
+/// None for shorter paths and those that are verbatim or relative already, or that still hold `.`
+/// or `..`: Windows doesn't resolve those in verbatim paths.
+pub fn with_verbatim_prefix(path: &str) -> Option<String> {
+    if path.encode_utf16().count() < MAX_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    let path = path.replace('/', r"\");
+    if path.split('\\').any(|name| name == "." || name == "..") {
+        return None;
+    }
+
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{}", share));
+    }
+    let mut chars = path.chars();
+    (chars.next()?.is_ascii_alphabetic() && chars.next()? == ':' && chars.next()? == '\\').then(|| format!(r"\\?\{}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drive_paths_lose_the_verbatim_prefix() {
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\Photos\a.jpg").as_deref(), Some(r"C:\Photos\a.jpg"));
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\").as_deref(), Some(r"C:\"));
+        assert_eq!(without_verbatim_prefix(r"\\?\d:").as_deref(), Some("d:"));
+    }
+
+    #[test]
+    fn unc_paths_lose_the_verbatim_prefix() {
+        assert_eq!(without_verbatim_prefix(r"\\?\UNC\server\share").as_deref(), Some(r"\\server\share"));
+        assert_eq!(without_verbatim_prefix(r"\\?\UNC\nas\photos\2023").as_deref(), Some(r"\\nas\photos\2023"));
+    }
+
+    #[test]
+    fn usual_paths_stay_as_they_are() {
+        assert_eq!(without_verbatim_prefix(r"\\server\share"), None);
+        assert_eq!(without_verbatim_prefix(r"C:\Photos"), None);
+        assert_eq!(without_verbatim_prefix("photos/a.jpg"), None);
+        assert_eq!(without_verbatim_prefix("/home/user/photos"), None);
+    }
+
+    #[test]
+    fn paths_that_only_work_verbatim_keep_the_prefix() {
+        assert_eq!(without_verbatim_prefix(r"\\?\Volume{0b0f35a2-0000-0000-0000-100000000000}\Photos"), None);
+        assert_eq!(without_verbatim_prefix(r"\\?\GLOBALROOT\Device\HarddiskVolume1"), None);
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\Photos\CON"), None);
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\Photos\trip."), None);
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\Photos\trip "), None);
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\Photos\a/b.jpg"), None);
+        let long = format!(r"\\?\C:\{}", "a".repeat(MAX_PATH));
+        assert_eq!(without_verbatim_prefix(&long), None);
+    }
+
+    #[test]
+    fn simplify_only_changes_paths_on_windows() {
+        let simplified = simplify(Path::new(r"\\?\C:\Photos"));
+        let expected = if cfg!(windows) { r"C:\Photos" } else { r"\\?\C:\Photos" };
+        assert_eq!(simplified, PathBuf::from(expected));
+        assert_eq!(simplify(Path::new("/home/user/photos")), PathBuf::from("/home/user/photos"));
+    }
+
+    #[test]
+    fn long_paths_get_the_verbatim_prefix() {
+        let name = "a".repeat(MAX_PATH);
+        assert_eq!(with_verbatim_prefix(&format!(r"C:\Photos\{}", name)), Some(format!(r"\\?\C:\Photos\{}", name)));
+        assert_eq!(with_verbatim_prefix(&format!(r"\\nas\photos\{}", name)), Some(format!(r"\\?\UNC\nas\photos\{}", name)));
+        assert_eq!(with_verbatim_prefix(&format!("C:/Photos/{}", name)), Some(format!(r"\\?\C:\Photos\{}", name)));
+    }
+
+    #[test]
+    fn short_relative_and_verbatim_paths_get_no_prefix() {
+        let name = "a".repeat(MAX_PATH);
+        assert_eq!(with_verbatim_prefix(r"C:\Photos\a.jpg"), None);
+        assert_eq!(with_verbatim_prefix(&format!(r"Photos\{}", name)), None);
+        assert_eq!(with_verbatim_prefix(&format!(r"\\?\C:\Photos\{}", name)), None);
+        assert_eq!(with_verbatim_prefix(&format!(r"C:\Photos\..\{}", name)), None);
+    }
+}
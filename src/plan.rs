@@ -0,0 +1,500 @@
+use crate::attributes;
+use crate::checksum::sha256;
+use crate::format::{self, ImageFormat};
+use crate::interrupt;
+use crate::journal::Journal;
+use crate::paths;
+use crate::scan::{same_file, FileGroup};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+/// Room a collision suffix such as "_2" or "_3fa9c1" takes up at most in the new name.
+pub const COLLISION_SUFFIX_LEN: usize = 7;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OnCollision {
+    /// Append a counter, e.g. "_2"
+    Suffix,
+    /// Append a short hash of the file's content
+    Hash,
+    /// Stop before renaming anything
+    Abort,
+}
+
+/// How files get to their new name.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transfer {
+    /// Rename in place
+    #[default]
+    #[value(skip)]
+    Rename,
+    /// Copy into the destination, leaving the originals untouched
+    Copy,
+    /// Move into the destination
+    Move,
+    /// Hard link into the destination, so the files show up in both trees without taking up
+    /// space twice. The destination has to be on the same filesystem, and the files can't be
+    /// rewritten with --strip-gps, --auto-rotate or --write-metadata embedded
+    Link,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlannedRename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Every rename a run intends to make, worked out before anything on disk is touched.
+#[derive(Debug, Default)]
+pub struct RenamePlan {
+    pub transfer: Transfer,
+    pub renames: Vec<PlannedRename>,
+    pub skipped: Vec<SkippedFile>,
+    // Targets in lowercase, since names that only differ in case are the same file on macOS and
+    // Windows volumes
+    claimed: HashSet<String>,
+}
+
+impl RenamePlan {
+    pub fn new(transfer: Transfer) -> RenamePlan {
+        RenamePlan { transfer, ..RenamePlan::default() }
+    }
+
+    /// Plans renaming every file in the group to `stem` with its own extension, inside `dir` or
+    /// next to the original when no directory is given. Returns the path of the primary file after
+    /// resolving collisions, or None when the group was skipped. Fails with `AlreadyExists` when
+    /// the name is taken and `on_collision` is `Abort`.
+    pub fn add_group(
+        &mut self,
+        group: &FileGroup,
+        dir: Option<&Path>,
+        stem: &str,
+        on_collision: OnCollision,
+    ) -> std::io::Result<Option<PathBuf>> {
+        let Some(stem) = self.resolve_stem(group, dir, stem, on_collision)? else {
+            self.skip_group(group, "target filename already exists");
+            return Ok(None);
+        };
+
+        for member in &group.members {
+            let to = target_path(member, dir, &group.member_stem(member, &stem));
+            self.claimed.insert(claim_key(&to));
+            self.renames.push(PlannedRename { from: member.clone(), to });
+        }
+
+        Ok(Some(target_path(group.primary(), dir, &group.member_stem(group.primary(), &stem))))
+    }
+
+    /// Takes the group's renames out of the plan again, which frees the names they claimed.
+    pub fn withdraw_group(&mut self, group: &FileGroup) {
+        let claimed = &mut self.claimed;
+        self.renames.retain(|rename| {
+            let member = group.members.contains(&rename.from);
+            if member {
+                claimed.remove(&claim_key(&rename.to));
+            }
+            !member
+        });
+    }
+
+    pub fn skip_group(&mut self, group: &FileGroup, reason: &str) {
+        for member in &group.members {
+            self.skipped.push(SkippedFile { path: member.clone(), reason: reason.to_string() });
+        }
+    }
+
+    // Picks the stem shared by every file in the group, or None when the group must be skipped
+    // because its target names are taken
+    fn resolve_stem(
+        &self,
+        group: &FileGroup,
+        dir: Option<&Path>,
+        stem: &str,
+        on_collision: OnCollision,
+    ) -> std::io::Result<Option<String>> {
+        let is_taken = |stem: &str| group.members.iter().any(|member| {
+            let target = target_path(member, dir, &group.member_stem(member, stem));
+            !same_file(&target, member) && (target.exists() || self.claimed.contains(&claim_key(&target)))
+        });
+
+        if !is_taken(stem) {
+            return Ok(Some(stem.to_string()));
+        }
+
+        match on_collision {
+            OnCollision::Suffix => {
+                let suffixed = (2..).map(|n| format!("{}_{}", stem, n)).find(|stem| !is_taken(stem));
+                Ok(suffixed)
+            }
+            OnCollision::Abort => Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{:?} is already taken", target_path(group.primary(), dir, &group.member_stem(group.primary(), stem))),
+            )),
+            OnCollision::Hash => {
+                // Hash the primary file only so paired files keep sharing a name
+                let hashed = format!("{}_{}", stem, short_hash(group.primary())?);
+                if is_taken(&hashed) {
+                    error!("  Error: {:?} already exists, skipping.", target_path(group.primary(), dir, &group.member_stem(group.primary(), &hashed)));
+                    return Ok(None);
+                }
+                Ok(Some(hashed))
+            }
+        }
+    }
+}
+
+/// A plan as written by `image-labeler plan`, with paths relative to the directory it was made
+/// for so it can be applied on another machine. Targets outside that directory stay absolute.
+#[derive(Serialize, Deserialize, Debug)]
+struct PlanFile {
+    directory: PathBuf,
+    transfer: Transfer,
+    renames: Vec<PlannedRename>,
+}
+
+impl RenamePlan {
+    /// Writes the renames of a plan made for `dir` to `path` as JSON.
+    pub fn save(&self, dir: &Path, path: &Path) -> std::io::Result<()> {
+        let dir = paths::canonicalize(dir)?;
+        let relative = |path: &Path| -> std::io::Result<PathBuf> {
+            let path = std::path::absolute(path)?;
+            Ok(path.strip_prefix(&dir).map(Path::to_path_buf).unwrap_or(path))
+        };
+
+        let renames = self.renames.iter()
+            .filter(|rename| rename.from != rename.to)
+            .map(|rename| Ok(PlannedRename { from: relative(&rename.from)?, to: relative(&rename.to)? }))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let file = PlanFile { directory: dir.clone(), transfer: self.transfer, renames };
+        fs::write(path, serde_json::to_string_pretty(&file)?)
+    }
+
+    /// Reads a plan written by `save`, applied to `dir` or else the directory it was made for.
+    /// Returns that directory along with the plan. Since the file may have been edited by hand it
+    /// fails when a file to rename is missing or two files are given the same name.
+    pub fn load(path: &Path, dir: Option<&Path>) -> std::io::Result<(PathBuf, RenamePlan)> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+        let file: PlanFile = serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?;
+        let dir = dir.map(Path::to_path_buf).unwrap_or(file.directory);
+
+        let mut plan = RenamePlan::new(file.transfer);
+        for rename in file.renames {
+            let rename = PlannedRename { from: dir.join(rename.from), to: dir.join(rename.to) };
+            if !rename.from.exists() {
+                return Err(invalid(format!("{:?} doesn't exist", rename.from)));
+            }
+            if !plan.claimed.insert(claim_key(&rename.to)) {
+                return Err(invalid(format!("more than one file would be named {:?}", rename.to)));
+            }
+            plan.renames.push(rename);
+        }
+
+        Ok((dir, plan))
+    }
+}
+
+fn claim_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+pub fn target_path(path: &Path, dir: Option<&Path>, stem: &str) -> PathBuf {
+    // Files picked up by their contents alone get the extension that goes with them
+    let extension = path.extension()
+        .and_then(|s| s.to_str())
+        .or_else(|| format::detect(path).map(ImageFormat::extension));
+    let file_name = match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    };
+    match dir {
+        Some(dir) => dir.join(file_name),
+        None => path.with_file_name(file_name),
+    }
+}
+
+/// Length of `short_hash`, in hex characters.
+pub const SHORT_HASH_LEN: usize = 6;
+
+/// The start of the file's blake3 digest, which names files apart by their contents, both after a
+/// collision and in place of `{seq}`.
+pub fn short_hash(path: &Path) -> std::io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path)?)?;
+    Ok(hasher.finalize().to_hex()[..SHORT_HASH_LEN].to_string())
+}
+
+/// Performs every rename in the plan, recording each one in the journal as it happens. Copies and
+/// links aren't journaled since the originals stay where they were. Never replaces an existing file: if
+/// a target appeared since the plan was made, execution stops there. When the user presses Ctrl-C
+/// the rename in progress is finished and execution stops with `Interrupted`. `on_done` is called
+/// after each file has been renamed, copied or moved.
+pub fn execute_plan(
+    plan: &RenamePlan,
+    journal: &mut Journal,
+    on_done: &mut dyn FnMut(&PlannedRename),
+) -> std::io::Result<()> {
+    let total = plan.renames.iter().filter(|rename| rename.from != rename.to).count();
+    let mut done = 0;
+
+    for rename in &plan.renames {
+        if rename.from == rename.to {
+            continue;
+        }
+
+        if interrupt::requested() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("interrupted after {} of {} files", done, total),
+            ));
+        }
+
+        // Targets in deeply nested folders can be longer than Windows allows without the verbatim prefix
+        let (source, target) = (paths::extended(&rename.from), paths::extended(&rename.to));
+
+        // Changing only the case of a name renames a file onto itself on case-insensitive volumes
+        if target.exists() && !same_file(&source, &target) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{:?} already exists, refusing to overwrite it", rename.to),
+            ));
+        }
+
+        if plan.transfer == Transfer::Rename {
+            info!("Renaming: {:?} -> {:?}", rename.from, rename.to.file_name().unwrap_or_default());
+        } else {
+            let verb = match plan.transfer {
+                Transfer::Copy => "Copying",
+                Transfer::Link => "Linking",
+                _ => "Moving",
+            };
+            info!("{}: {:?} -> {:?}", verb, rename.from, rename.to);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let from = paths::canonicalize(&rename.from)?;
+        let original_name = rename.from.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        match plan.transfer {
+            Transfer::Copy => {
+                copy_file(&source, &target)?;
+                attributes::record_original_name(&target, original_name)?;
+                on_done(rename);
+                done += 1;
+                continue;
+            }
+            Transfer::Link => {
+                // The original name isn't recorded, it would end up on the original as well
+                link_file(&source, &target)?;
+                on_done(rename);
+                done += 1;
+                continue;
+            }
+            Transfer::Move => move_file(&source, &target)?,
+            Transfer::Rename => {
+                let before = fs::metadata(&source)?;
+                fs::rename(&source, &target)?;
+                attributes::restore_times(&target, &before)?;
+            }
+        }
+        attributes::record_original_name(&target, original_name)?;
+        journal.record(from, paths::canonicalize(&rename.to)?)?;
+        on_done(rename);
+        done += 1;
+    }
+
+    Ok(())
+}
+
+// The destination may be on another filesystem, such as another drive or a NAS mount, where a
+// plain rename isn't possible. The original is only removed once the copy is on disk and reads back
+// the same
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_file(from, to)?;
+            let verified = verify_copy(from, to).and_then(|_| sync_dir(to.parent().unwrap_or(Path::new("."))));
+            if let Err(e) = verified {
+                let _ = fs::remove_file(to);
+                return Err(e);
+            }
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+fn verify_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    let same = fs::metadata(from)?.len() == fs::metadata(to)?.len() && sha256(from)? == sha256(to)?;
+    if !same {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{:?} doesn't match {:?} after copying it, the original was kept", to, from),
+        ));
+    }
+    Ok(())
+}
+
+fn link_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::hard_link(from, to).map_err(|e| match e.kind() {
+        std::io::ErrorKind::CrossesDevices => std::io::Error::new(
+            e.kind(),
+            format!("{:?} is on another filesystem than {:?}, hard links can't cross filesystems", to, from),
+        ),
+        _ => e,
+    })
+}
+
+// Copies under a temporary name first so a copy cut short never shows up as the target
+fn copy_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    let file_name = to.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let partial = to.with_file_name(format!(".{}.part", file_name));
+    let copied = fs::copy(from, &partial)
+        .and_then(|_| attributes::copy_attributes(from, &partial))
+        .and_then(|_| sync_file(&partial))
+        .and_then(|_| fs::rename(&partial, to));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Waits until the file's contents are on disk, so a crash can't leave the target half written.
+// Windows only flushes handles that were opened for writing
+fn sync_file(path: &Path) -> std::io::Result<()> {
+    let file = if cfg!(windows) { fs::File::options().write(true).open(path)? } else { fs::File::open(path)? };
+    file.sync_all()
+}
+
+// Renames and new files only survive a crash once their directory is synced as well on Unix
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory with the given empty files in it
+    fn directory(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("image-labeler-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        paths::canonicalize(&dir).unwrap()
+    }
+
+    fn group(dir: &Path, names: &[&str]) -> FileGroup {
+        FileGroup { members: names.iter().map(|name| dir.join(name)).collect() }
+    }
+
+    fn targets(plan: &RenamePlan) -> Vec<PathBuf> {
+        plan.renames.iter().map(|rename| rename.to.clone()).collect()
+    }
+
+    #[test]
+    fn taken_names_get_a_counter() {
+        let dir = directory("suffix", &["a.jpg", "a.cr2", "b.jpg", "c.jpg", "x.cr2"]);
+        let mut plan = RenamePlan::new(Transfer::Rename);
+        let primary = plan.add_group(&group(&dir, &["a.jpg", "a.cr2"]), None, "x", OnCollision::Suffix).unwrap();
+        plan.add_group(&group(&dir, &["b.jpg"]), None, "x", OnCollision::Suffix).unwrap();
+        plan.add_group(&group(&dir, &["c.jpg"]), None, "X_2", OnCollision::Suffix).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The RAW file's name was taken on disk, so its JPEG moves along to keep the pair together
+        assert_eq!(primary, Some(dir.join("x_2.jpg")));
+        assert_eq!(targets(&plan), [dir.join("x_2.jpg"), dir.join("x_2.cr2"), dir.join("x.jpg"), dir.join("X_2_2.jpg")]);
+    }
+
+    #[test]
+    fn taken_names_get_a_hash_or_stop_the_run() {
+        let dir = directory("hash", &["a.jpg", "x.jpg"]);
+        let mut plan = RenamePlan::new(Transfer::Rename);
+        let aborted = plan.add_group(&group(&dir, &["a.jpg"]), None, "x", OnCollision::Abort);
+        let hashed = plan.add_group(&group(&dir, &["a.jpg"]), None, "x", OnCollision::Hash).unwrap();
+        let hash = short_hash(&dir.join("a.jpg")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(aborted.unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(hashed, Some(dir.join(format!("x_{}.jpg", hash))));
+        assert_eq!(hash.len(), SHORT_HASH_LEN);
+    }
+
+    #[test]
+    fn withdrawn_groups_free_their_names() {
+        let dir = directory("withdraw", &["a.jpg", "b.jpg"]);
+        let mut plan = RenamePlan::new(Transfer::Rename);
+        plan.add_group(&group(&dir, &["a.jpg"]), None, "x", OnCollision::Suffix).unwrap();
+        plan.withdraw_group(&group(&dir, &["a.jpg"]));
+        let primary = plan.add_group(&group(&dir, &["b.jpg"]), None, "x", OnCollision::Suffix).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(primary, Some(dir.join("x.jpg")));
+        assert_eq!(targets(&plan), [dir.join("x.jpg")]);
+    }
+
+    #[test]
+    fn saved_plans_load_relative_to_their_directory() {
+        let dir = directory("save", &["a.jpg", "b.jpg", "c.jpg"]);
+        let other = directory("save-elsewhere", &["a.jpg", "b.jpg", "c.jpg"]);
+        let mut plan = RenamePlan::new(Transfer::Copy);
+        plan.add_group(&group(&dir, &["a.jpg"]), None, "x", OnCollision::Suffix).unwrap();
+        plan.add_group(&group(&dir, &["b.jpg"]), Some(&dir.join("2023")), "x", OnCollision::Suffix).unwrap();
+        plan.add_group(&group(&dir, &["c.jpg"]), None, "c", OnCollision::Suffix).unwrap();
+        let path = dir.join("plan.json");
+        plan.save(&dir, &path).unwrap();
+
+        let (loaded_dir, loaded) = RenamePlan::load(&path, None).unwrap();
+        let (other_dir, elsewhere) = RenamePlan::load(&path, Some(&other)).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&other).unwrap();
+
+        assert!(saved.contains(r#""from": "a.jpg""#));
+        assert_eq!(loaded_dir, dir);
+        assert_eq!(loaded.transfer, Transfer::Copy);
+        // Files that keep their name are left out
+        assert_eq!(targets(&loaded), [dir.join("x.jpg"), dir.join("2023").join("x.jpg")]);
+        assert_eq!(other_dir, other);
+        assert_eq!(elsewhere.renames[0].from, other.join("a.jpg"));
+        assert_eq!(targets(&elsewhere), [other.join("x.jpg"), other.join("2023").join("x.jpg")]);
+    }
+
+    #[test]
+    fn edited_plans_are_checked_when_loaded() {
+        let dir = directory("load", &["a.jpg", "b.jpg"]);
+        let plan = |renames: &str| {
+            let path = dir.join("plan.json");
+            fs::write(&path, format!(r#"{{"directory": {:?}, "transfer": "copy", "renames": [{}]}}"#, dir, renames)).unwrap();
+            RenamePlan::load(&path, None).map(|(_, plan)| plan.renames.len())
+        };
+        let valid = plan(r#"{"from": "a.jpg", "to": "x.jpg"}, {"from": "b.jpg", "to": "y.jpg"}"#);
+        let missing = plan(r#"{"from": "c.jpg", "to": "x.jpg"}"#);
+        let duplicate = plan(r#"{"from": "a.jpg", "to": "x.jpg"}, {"from": "b.jpg", "to": "X.jpg"}"#);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(valid.unwrap(), 2);
+        assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(duplicate.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,50 @@
+// Open Location Code, see https://github.com/google/open-location-code/blob/main/docs/specification.md
+const ALPHABET: &[u8; 20] = b"23456789CFGHJMPQRVWX";
+const SEPARATOR_POSITION: usize = 8;
+const PAIR_CODE_LENGTH: usize = 10;
+
+// Each pair of digits divides the cell before it by 20 in both directions, starting from 20
+// degrees, so after five pairs a degree is split into 20^5 / 20 = 8000 steps
+const PRECISION: f64 = 8000.0;
+const LAT_STEPS: i64 = 180 * 8000;
+const LNG_STEPS: i64 = 360 * 8000;
+
+/// The plus code of a position with ten digits, e.g. "9F469VCF+XV", which pins it down to an area
+/// of about 14 by 14 meters. Worked out locally, no service is involved.
+pub fn encode(lat: f64, lon: f64) -> String {
+    // The north pole would otherwise fall just outside the last cell
+    let lat_value = (((lat.clamp(-90.0, 90.0) + 90.0) * PRECISION).floor() as i64).min(LAT_STEPS - 1);
+    let lng_value = (((lon + 180.0) * PRECISION).floor() as i64).rem_euclid(LNG_STEPS);
+
+    let mut digits = Vec::with_capacity(PAIR_CODE_LENGTH + 1);
+    let (mut lat_value, mut lng_value) = (lat_value, lng_value);
+    for _ in 0..PAIR_CODE_LENGTH / 2 {
+        digits.push(ALPHABET[(lng_value % 20) as usize]);
+        digits.push(ALPHABET[(lat_value % 20) as usize]);
+        lat_value /= 20;
+        lng_value /= 20;
+    }
+    digits.reverse();
+    digits.insert(SEPARATOR_POSITION, b'+');
+    String::from_utf8(digits).expect("plus codes are ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_encode_to_ten_digits() {
+        assert_eq!(encode(20.375, 2.775), "7FG49QGG+22");
+        assert_eq!(encode(47.0000625, 8.0000625), "8FVC2222+22");
+        assert_eq!(encode(-41.2730625, 174.7859375), "4VCPPQGP+Q9");
+    }
+
+    #[test]
+    fn positions_at_the_edges_stay_on_the_grid() {
+        assert_eq!(encode(90.0, 1.0), encode(89.99999, 1.0));
+        assert_eq!(encode(95.0, 1.0), encode(90.0, 1.0));
+        assert_eq!(encode(10.0, 180.0), encode(10.0, -180.0));
+        assert_eq!(encode(10.0, 190.0), encode(10.0, -170.0));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::datetime::format_unix_time;
+use crate::geocoder::{FailureKind, GeocodeError, GeocodeResponse, ReverseGeocoder, ServiceError};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Requests sent to each geocoding provider today, kept next to the geocode cache so a daily
+/// quota holds across runs. Days start at midnight UTC.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct DailyUsage {
+    /// The day the counts are for, as yyyyMMdd
+    date: String,
+    requests: BTreeMap<String, usize>,
+}
+
+impl DailyUsage {
+    pub fn path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("image-labeler").join("daily-usage.json"))
+    }
+
+    /// Today's usage, which starts from nothing on a new day.
+    pub fn load() -> DailyUsage {
+        let today = today();
+        let usage = DailyUsage::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| match serde_json::from_str::<DailyUsage>(&contents) {
+                Ok(usage) => Some(usage),
+                Err(e) => {
+                    warn!("Warning: Ignoring unreadable daily usage: {}", e);
+                    None
+                }
+            })
+            .filter(|usage| usage.date == today);
+        usage.unwrap_or(DailyUsage { date: today, requests: BTreeMap::new() })
+    }
+
+    pub fn used(&self, provider: &str) -> usize {
+        self.requests.get(provider).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, provider: &str, requests: usize) {
+        // A run that goes on past midnight counts towards the new day from then on
+        let today = today();
+        if self.date != today {
+            *self = DailyUsage { date: today, requests: BTreeMap::new() };
+        }
+        *self.requests.entry(provider.to_string()).or_insert(0) += requests;
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(path) = DailyUsage::path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+}
+
+fn today() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    format_unix_time(now).0
+}
+
+/// How many requests a run may send to the geocoder: what's left of `--max-api-calls` or of the
+/// day's quota, whichever runs out first. Requests are counted as they go out, and what the run
+/// sent is added to the daily usage when it's saved.
+#[derive(Debug)]
+pub struct Budget {
+    limit: usize,
+    /// What ran out once the limit is reached
+    used_up: String,
+    sent: AtomicUsize,
+    refused: AtomicBool,
+    daily: Option<(String, Mutex<DailyUsage>)>,
+    saved: AtomicUsize,
+}
+
+impl Budget {
+    /// A budget of `limit` requests for this run, or as many as it needs.
+    pub fn new(limit: Option<usize>) -> Budget {
+        Budget {
+            limit: limit.unwrap_or(usize::MAX),
+            used_up: format!("the limit of {} geocoding requests is reached", limit.unwrap_or(usize::MAX)),
+            sent: AtomicUsize::new(0),
+            refused: AtomicBool::new(false),
+            daily: None,
+            saved: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts the requests towards today's usage of `provider`, and keeps to `quota` requests a
+    /// day including those earlier runs sent.
+    pub fn tracking_daily(mut self, provider: &str, quota: Option<usize>) -> Budget {
+        let usage = DailyUsage::load();
+        if let Some(quota) = quota {
+            let left = quota.saturating_sub(usage.used(provider));
+            if left < self.limit {
+                self.limit = left;
+                self.used_up = format!("the daily quota of {} {} requests is used up", quota, provider);
+            }
+        }
+        self.daily = Some((provider.to_string(), Mutex::new(usage)));
+        self
+    }
+
+    /// Requests the run may send, if there's a limit.
+    pub fn limit(&self) -> Option<usize> {
+        (self.limit < usize::MAX).then_some(self.limit)
+    }
+
+    /// Whether a request was turned away because the budget ran out.
+    pub fn is_exhausted(&self) -> bool {
+        self.refused.load(Ordering::Relaxed)
+    }
+
+    /// What ran out, for telling the user why the run stopped.
+    pub fn used_up(&self) -> &str {
+        &self.used_up
+    }
+
+    fn take(&self) -> Result<(), GeocodeError> {
+        let taken = self.sent.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sent| (sent < self.limit).then_some(sent + 1));
+        if taken.is_err() {
+            self.refused.store(true, Ordering::Relaxed);
+            return Err(Box::new(ServiceError::new(FailureKind::OverBudget, self.used_up.clone())));
+        }
+        Ok(())
+    }
+
+    /// Adds the requests sent since the last save to the daily usage.
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some((provider, usage)) = &self.daily else {
+            return Ok(());
+        };
+        let sent = self.sent.load(Ordering::Relaxed);
+        let unsaved = sent - self.saved.swap(sent, Ordering::Relaxed);
+        let mut usage = usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage.add(provider, unsaved);
+        usage.save()
+    }
+}
+
+/// A geocoder that stops sending requests once the budget is used up, failing them instead.
+pub struct Budgeted {
+    geocoder: Box<dyn ReverseGeocoder>,
+    budget: Arc<Budget>,
+}
+
+impl Budgeted {
+    pub fn new(geocoder: Box<dyn ReverseGeocoder>, budget: Arc<Budget>) -> Budgeted {
+        Budgeted { geocoder, budget }
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for Budgeted {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        self.budget.take()?;
+        self.geocoder.reverse(lat, lon).await
+    }
+
+    fn rate_limited(&self) -> bool {
+        self.geocoder.rate_limited()
+    }
+
+    async fn nearby_poi(&self, lat: f64, lon: f64) -> Result<Option<String>, GeocodeError> {
+        self.budget.take()?;
+        self.geocoder.nearby_poi(lat, lon).await
+    }
+
+    fn batch_size(&self) -> usize {
+        self.geocoder.batch_size()
+    }
+
+    async fn reverse_batch(&self, positions: &[(f64, f64)]) -> Result<Vec<Result<GeocodeResponse, GeocodeError>>, GeocodeError> {
+        self.budget.take()?;
+        self.geocoder.reverse_batch(positions).await
+    }
+}
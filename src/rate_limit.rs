@@ -0,0 +1,92 @@
+use crate::geocoder::{GeocodeError, GeocodeResponse, ReverseGeocoder};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Token bucket shared by concurrent geocoding requests. Tokens refill at `rate` per second up
+/// to `burst`, and every request takes one.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> RateLimiter {
+        let burst = burst.max(1) as f64;
+        RateLimiter {
+            rate,
+            burst,
+            state: Mutex::new(Bucket { tokens: burst, refilled_at: Instant::now() }),
+        }
+    }
+
+    /// Waits until a request is allowed to go out.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+                bucket.refilled_at = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+
+            sleep(wait).await;
+        }
+    }
+}
+
+/// A geocoder whose requests wait for the rate limiter, so callers can send them as fast as they
+/// like. Only requests that actually reach the service are paced, cache hits never get here.
+pub struct Paced {
+    geocoder: Box<dyn ReverseGeocoder>,
+    limiter: RateLimiter,
+}
+
+impl Paced {
+    pub fn new(geocoder: Box<dyn ReverseGeocoder>, limiter: RateLimiter) -> Paced {
+        Paced { geocoder, limiter }
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for Paced {
+    async fn reverse(&self, lat: f64, lon: f64) -> Result<GeocodeResponse, GeocodeError> {
+        self.limiter.acquire().await;
+        self.geocoder.reverse(lat, lon).await
+    }
+
+    fn rate_limited(&self) -> bool {
+        self.geocoder.rate_limited()
+    }
+
+    async fn nearby_poi(&self, lat: f64, lon: f64) -> Result<Option<String>, GeocodeError> {
+        self.limiter.acquire().await;
+        self.geocoder.nearby_poi(lat, lon).await
+    }
+
+    fn batch_size(&self) -> usize {
+        self.geocoder.batch_size()
+    }
+
+    // A batch counts as a single request towards the limit
+    async fn reverse_batch(&self, positions: &[(f64, f64)]) -> Result<Vec<Result<GeocodeResponse, GeocodeError>>, GeocodeError> {
+        self.limiter.acquire().await;
+        self.geocoder.reverse_batch(positions).await
+    }
+}
@@ -0,0 +1,177 @@
+use crate::cache::GeocodeCache;
+use crate::geo;
+use crate::geocoder::{FailureKind, GeocodeError, GeocodeResponse, ReverseGeocoder, ServiceError};
+use crate::interrupt;
+use crate::logging::Progress;
+use crate::metadata::PhotoMetadata;
+use crate::retry::RetryPolicy;
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info, trace, warn};
+
+// Upper bound on geocoding requests in flight at once; the geocoder paces how fast they start
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+// Resolves the location of every file with metadata, in the same order. Cache misses are
+// geocoded concurrently, paced by the geocoder and batched when the service supports it, and
+// with a cluster radius only one location is looked up per group of nearby photos. Transient failures are retried according to the
+// policy; once the service rejects the API key the remaining requests aren't sent at all.
+// `on_resolved` is called as soon as each requested location comes in. Also returns why the
+// API key was rejected, if it was.
+pub async fn resolve_locations(
+    geocoder: &dyn ReverseGeocoder,
+    cache: &mut GeocodeCache,
+    retry: &RetryPolicy,
+    cluster_radius: f64,
+    metadata: &[Option<PhotoMetadata>],
+    on_resolved: &mut dyn FnMut(f64, f64, &GeocodeResponse),
+) -> (Vec<Option<Result<GeocodeResponse, String>>>, Option<String>) {
+    let key = |lat: f64, lon: f64| (lat.to_bits(), lon.to_bits());
+
+    // Photos taken at exactly the same spot only need a single request
+    let mut seen = HashSet::new();
+    let pending = metadata.iter()
+        .flatten()
+        .filter(|m| cache.get(m.lat, m.lon).is_none() && seen.insert(key(m.lat, m.lon)))
+        .map(|m| (m.lat, m.lon))
+        .collect::<Vec<_>>();
+
+    let leaders = if cluster_radius > 0.0 {
+        geo::cluster(&pending, cluster_radius)
+    } else {
+        (0..pending.len()).collect()
+    };
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (index, &leader) in leaders.iter().enumerate() {
+        members.entry(leader).or_default().push(index);
+    }
+
+    // Services with a batch endpoint get many cluster leaders per request
+    let requests = (0..pending.len()).filter(|&index| leaders[index] == index).collect::<Vec<_>>();
+    let batches = requests.chunks(geocoder.batch_size().max(1)).map(<[usize]>::to_vec).collect::<Vec<_>>();
+    if batches.len() < members.len() {
+        info!("Resolving {} locations in {} requests...", members.len(), batches.len());
+    } else if !members.is_empty() {
+        info!("Resolving {} locations...", members.len());
+    }
+
+    let progress = Progress::new(members.len(), "Geocoding");
+    let rejected = Mutex::new(None);
+    let latency = Mutex::new(Latency::default());
+    let mut results = stream::iter(batches)
+        .map(|batch| {
+            let request = Request { geocoder, retry, rejected: &rejected, latency: &latency };
+            let positions = batch.iter().map(|&leader| pending[leader]).collect::<Vec<_>>();
+            async move { (batch, request.reverse_with_retry(&positions).await) }
+        })
+        .buffer_unordered(MAX_CONCURRENT_REQUESTS);
+
+    // Every member of a cluster shares its leader's result
+    let mut fetched = HashMap::new();
+    while let Some((batch, batch_results)) = results.next().await {
+        progress.bar().inc(batch.len() as u64);
+        if let Some(average) = latency.lock().unwrap_or_else(|e| e.into_inner()).average() {
+            progress.bar().set_prefix(format!("{} ms per request", average.as_millis()));
+        }
+
+        for (leader, result) in batch.into_iter().zip(batch_results) {
+            for &index in &members[&leader] {
+                let (lat, lon) = pending[index];
+                if let Ok(response) = &result {
+                    cache.insert(lat, lon, response.clone());
+                    on_resolved(lat, lon, response);
+                }
+                fetched.insert(key(lat, lon), result.clone());
+            }
+        }
+    }
+
+    let resolved = metadata.iter()
+        .map(|m| m.as_ref().map(|m| match fetched.get(&key(m.lat, m.lon)) {
+            Some(result) => result.clone(),
+            None => cache.get(m.lat, m.lon).cloned().ok_or_else(|| "location was not resolved".to_string()),
+        }))
+        .collect();
+    drop(results);
+    (resolved, rejected.into_inner().unwrap_or_else(|e| e.into_inner()))
+}
+
+// Running average of how long the service takes to answer
+#[derive(Debug, Default)]
+struct Latency {
+    total: Duration,
+    count: u32,
+}
+
+impl Latency {
+    fn average(&self) -> Option<Duration> {
+        if self.count == 0 { None } else { Some(self.total / self.count) }
+    }
+}
+
+// Everything a single lookup shares with the others in flight
+#[derive(Clone, Copy)]
+struct Request<'a> {
+    geocoder: &'a dyn ReverseGeocoder,
+    retry: &'a RetryPolicy,
+    rejected: &'a Mutex<Option<String>>,
+    latency: &'a Mutex<Latency>,
+}
+
+impl Request<'_> {
+    // Looks up the positions, in a single batch request when there are several, with a result
+    // for each of them
+    async fn reverse_with_retry(self, positions: &[(f64, f64)]) -> Vec<Result<GeocodeResponse, String>> {
+        let failed = |message: String| vec![Err(message); positions.len()];
+        let described = match positions {
+            [(lat, lon)] => format!("{}, {}", lat, lon),
+            _ => format!("{} locations", positions.len()),
+        };
+        let mut attempt = 0;
+        loop {
+            if let Some(message) = self.rejected.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+                return failed(message);
+            }
+            if interrupt::requested() {
+                return failed("interrupted".to_string());
+            }
+
+            let started = Instant::now();
+            let result = match positions {
+                [(lat, lon)] => self.geocoder.reverse(*lat, *lon).await.map(|response| vec![Ok(response)]),
+                _ => self.geocoder.reverse_batch(positions).await,
+            };
+            let elapsed = started.elapsed();
+            {
+                let mut latency = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+                latency.total += elapsed;
+                latency.count += 1;
+            }
+            trace!("Geocoded {} in {} ms: {}", described, elapsed.as_millis(), if result.is_ok() { "ok" } else { "failed" });
+
+            let error: GeocodeError = match result {
+                Ok(responses) => return responses.into_iter().map(|result| result.map_err(|e| e.to_string())).collect(),
+                Err(e) => e,
+            };
+
+            if ServiceError::kind_of(&error) == FailureKind::Unauthorized {
+                let mut rejected = self.rejected.lock().unwrap_or_else(|e| e.into_inner());
+                if rejected.is_none() {
+                    error!("Error: {}. Skipping the remaining requests.", error);
+                    *rejected = Some(error.to_string());
+                }
+                return failed(error.to_string());
+            }
+
+            // Nobody is waiting for the retry once the run is being stopped
+            let Some(delay) = self.retry.delay(attempt, &error).filter(|_| !interrupt::requested()) else {
+                return failed(error.to_string());
+            };
+            warn!("  Geocoding {} failed ({}), retrying in {:.1}s", described, error, delay.as_secs_f64());
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
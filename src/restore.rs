@@ -0,0 +1,77 @@
+use crate::attributes;
+use crate::journal::Journal;
+use crate::paths;
+use crate::scan::{is_sidecar, scan_directory, FileFilter};
+use crate::xmp;
+use std::fs;
+use std::path::Path;
+use tracing::{error, info};
+
+/// Renames files back to the name they had before they were first labeled, as recorded in their
+/// extended attributes or their XMP, so the originals can be recovered even without the journal.
+/// Sidecars follow the file they belong to. The renames are journaled like any other run, so
+/// `undo` labels the files again.
+pub fn restore_names(dir: &Path, max_depth: usize, dry_run: bool) -> std::io::Result<()> {
+    let filter = FileFilter::new(dir, &[], &[]).map_err(std::io::Error::other)?;
+    let mut groups = Vec::new();
+    scan_directory(dir, max_depth, &filter, &mut groups)?;
+
+    let mut journal = Journal::load(dir)?;
+    journal.begin_run();
+    let mut restored = 0;
+    let mut failed = 0;
+
+    for group in &groups {
+        for member in group.members.iter().filter(|member| !is_sidecar(member)) {
+            let Some(original) = attributes::original_name(member).or_else(|| xmp::preserved_file_name(member)) else {
+                continue;
+            };
+
+            // The name comes from the file itself, so it must not lead anywhere else
+            if Path::new(&original).file_name().is_none_or(|name| name != original.as_str()) {
+                error!("  Error: {:?} has an invalid original name {:?}, skipping.", member, original);
+                failed += 1;
+                continue;
+            }
+
+            let target = member.with_file_name(&original);
+            if target == *member {
+                continue;
+            }
+            if target.exists() {
+                error!("  Error: {:?} already exists, skipping {:?}.", target, member);
+                failed += 1;
+                continue;
+            }
+
+            info!("Restoring: {:?} -> {:?}", member, original);
+            restored += 1;
+            if dry_run {
+                continue;
+            }
+
+            rename(member, &target, &mut journal)?;
+            let sidecar = xmp::sidecar_path(member);
+            let sidecar_target = xmp::sidecar_path(&target);
+            if group.members.contains(&sidecar) && sidecar.exists() && !sidecar_target.exists() {
+                rename(&sidecar, &sidecar_target, &mut journal)?;
+            }
+        }
+    }
+
+    if dry_run {
+        info!("Dry run, {} files would get their original name back", restored);
+    } else {
+        info!("{} files got their original name back", restored);
+    }
+    if failed > 0 {
+        info!("{} files couldn't be restored", failed);
+    }
+    Ok(())
+}
+
+fn rename(from: &Path, to: &Path, journal: &mut Journal) -> std::io::Result<()> {
+    let canonical = paths::canonicalize(from)?;
+    fs::rename(from, to)?;
+    journal.record(canonical, paths::canonicalize(to)?)
+}
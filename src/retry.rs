@@ -0,0 +1,49 @@
+use crate::geocoder::{FailureKind, GeocodeError, ServiceError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often and how patiently to retry geocoding requests that failed for a transient reason.
+/// Delays double with every attempt, up to `max_delay`, and are jittered so concurrent requests
+/// don't retry in lockstep.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retrying after the given failed attempt (starting at 0), or None
+    /// when the error isn't worth retrying or the retries are used up.
+    pub fn delay(&self, attempt: u32, error: &GeocodeError) -> Option<Duration> {
+        if attempt >= self.max_retries || ServiceError::kind_of(error) != FailureKind::Transient {
+            return None;
+        }
+
+        // A delay requested by the service beats our own guess
+        let retry_after = error.downcast_ref::<ServiceError>().and_then(|e| e.retry_after);
+        if let Some(retry_after) = retry_after {
+            return Some(retry_after.min(self.max_delay));
+        }
+
+        let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        // Waits somewhere between half and the full backoff
+        Some(backoff.mul_f64(0.5 + jitter() * 0.5))
+    }
+}
+
+// A number in [0, 1). Retries only need to spread out, not be unpredictable, so the clock will do.
+fn jitter() -> f64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let mixed = (nanos as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 11;
+    mixed as f64 / (1u64 << 53) as f64
+}
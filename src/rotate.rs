@@ -0,0 +1,63 @@
+use crate::exif_write::reset_orientation;
+use crate::jpeg::replace_file;
+use exif::{In, Tag};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Rotation is left to jpegtran, which turns the compressed blocks around without decoding them
+const JPEGTRAN: &str = "jpegtran";
+
+/// Whether jpegtran, which `auto_rotate` relies on, can be run.
+pub fn is_available() -> bool {
+    Command::new(JPEGTRAN).arg("-version").output().is_ok()
+}
+
+/// The EXIF orientation of a photo, from 1 (stored upright) to 8.
+pub fn orientation(path: &Path) -> Option<u32> {
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(&file)).ok()?;
+    exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// Turns a JPEG the way its EXIF orientation says it should be shown and resets the orientation,
+/// so it displays correctly even where the tag is ignored. The rotation is lossless; images whose
+/// size doesn't allow that are left alone with an error. Returns whether the image was rotated.
+pub fn auto_rotate(path: &Path) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let orientation = orientation(path).unwrap_or(1);
+    let transform: &[&str] = match orientation {
+        2 => &["-flip", "horizontal"],
+        3 => &["-rotate", "180"],
+        4 => &["-flip", "vertical"],
+        5 => &["-transpose"],
+        6 => &["-rotate", "90"],
+        7 => &["-transverse"],
+        8 => &["-rotate", "270"],
+        _ => return Ok(false),
+    };
+
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+    let rotated = path.with_file_name(format!(".{}.image-labeler-rotated", file_name));
+    let output = Command::new(JPEGTRAN)
+        .args(["-copy", "all", "-perfect"])
+        .args(transform)
+        .arg("-outfile")
+        .arg(&rotated)
+        .arg(path)
+        .output();
+    let contents = match output {
+        Ok(output) if output.status.success() => fs::read(&rotated),
+        Ok(output) => {
+            let _ = fs::remove_file(&rotated);
+            return Err(format!("can't be rotated losslessly: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+        }
+        Err(e) => return Err(format!("couldn't run {}: {}", JPEGTRAN, e).into()),
+    };
+    let _ = fs::remove_file(&rotated);
+
+    replace_file(path, &contents?)?;
+    // Orientations 5 to 8 turn the image a quarter
+    reset_orientation(path, orientation >= 5)?;
+    Ok(true)
+}
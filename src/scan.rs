@@ -0,0 +1,456 @@
+use crate::filter::FileSelection;
+use crate::format;
+use crate::metadata::capture_date;
+use crate::paths;
+use crate::template::VARIANT_SLOT;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Files in one directory that share a base name, e.g. a RAW file and the JPEG the camera wrote
+/// alongside it, or the photo and video of an iPhone Live Photo. All members are renamed together
+/// so the pair stays linked.
+#[derive(Debug, Clone)]
+pub struct FileGroup {
+    /// Members ordered by how reliably their metadata can be read, best first
+    pub members: Vec<PathBuf>,
+}
+
+impl FileGroup {
+    pub fn primary(&self) -> &Path {
+        &self.members[0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The stem `member` gets when the group is renamed to `stem`. Edits that joined the group of
+    /// their original keep a suffix so they don't take the original's name, in place of {variant}
+    /// when the template has it and at the end otherwise.
+    pub fn member_stem(&self, member: &Path, stem: &str) -> String {
+        let own = lowercase_stem(member);
+        let suffix = match variant_base(&own) {
+            Some((_, suffix)) if own != lowercase_stem(self.primary()) => suffix,
+            _ => String::new(),
+        };
+        if stem.contains(VARIANT_SLOT) {
+            stem.replace(VARIANT_SLOT, &suffix)
+        } else {
+            format!("{}{}", stem, suffix)
+        }
+    }
+}
+
+// HEIF containers (as produced by iPhones), PNG, WebP and TIFF-based RAW formats are parsed by
+// the exif crate just like JPEGs
+pub fn is_photo(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "jpg" | "jpeg" | "heic" | "heif" | "png" | "tif" | "tiff" | "webp")
+        || is_raw(path)
+}
+
+pub fn is_video(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "mp4" | "mov" | "m4v")
+}
+
+pub fn is_raw(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "cr2" | "nef" | "arw" | "dng")
+}
+
+/// Whether `path` is a photo or video to label. Files with an extension that isn't recognized are
+/// picked up too when their contents are an image format that carries EXIF.
+pub fn is_media(path: &Path) -> bool {
+    is_photo(path) || is_video(path) || (!is_sidecar(path) && format::detect(path).is_some())
+}
+
+// XMP sidecars and the AAE files iPhones keep edits in aren't processed themselves but follow the
+// file they describe when it's renamed
+pub fn is_sidecar(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "xmp" | "aae")
+}
+
+pub fn is_xmp(path: &Path) -> bool {
+    extension(path) == "xmp"
+}
+
+/// Files and directories skipped unless `--no-default-ignores` is given: hidden files, the
+/// thumbnail caches and metadata operating systems and NAS software leave behind, AppleDouble
+/// resource forks such as `._IMG_1234.jpg`, and downloads or syncs that haven't finished.
+pub const DEFAULT_IGNORES: &[&str] = &[
+    ".*",
+    "._*",
+    ".DS_Store",
+    "Thumbs.db",
+    "desktop.ini",
+    "@eaDir",
+    "$RECYCLE.BIN",
+    "System Volume Information",
+    "*.partial",
+    "*.crdownload",
+    "*.part",
+    "*.tmp",
+    "~$*",
+];
+
+/// Which files to pick up while scanning, from `--include` and `--exclude` glob patterns. Patterns
+/// match case-insensitively against the file name or the path relative to the scanned directory,
+/// so `*-edited.jpg` and `exports/**` both work. Excluding a directory skips everything in it.
+/// The `DEFAULT_IGNORES` are excluded as well, and so are symbolic links unless they're followed.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    ignore: GlobSet,
+    follow_symlinks: bool,
+    selection: FileSelection,
+    now: SystemTime,
+}
+
+impl FileFilter {
+    pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<FileFilter, globset::Error> {
+        let include = if include.is_empty() { None } else { Some(glob_set(include)?) };
+        let ignore = glob_set(DEFAULT_IGNORES)?;
+        Ok(FileFilter {
+            root: root.to_path_buf(),
+            include,
+            exclude: glob_set(exclude)?,
+            ignore,
+            follow_symlinks: false,
+            selection: FileSelection::default(),
+            now: SystemTime::now(),
+        })
+    }
+
+    /// Picks up the files symbolic links point to and scans the directories they point to. The
+    /// links themselves are what gets renamed.
+    pub fn following_symlinks(mut self, follow: bool) -> FileFilter {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Only picks up files of the size and age the selection asks for.
+    pub fn selecting(mut self, selection: FileSelection) -> FileFilter {
+        self.selection = selection;
+        self
+    }
+
+    /// Skips these patterns instead of the `DEFAULT_IGNORES`, none at all when empty.
+    pub fn with_ignores<S: AsRef<str>>(mut self, patterns: &[S]) -> Result<FileFilter, globset::Error> {
+        self.ignore = glob_set(patterns)?;
+        Ok(self)
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.matches(&self.exclude, path) || self.matches(&self.ignore, path)
+    }
+
+    pub fn is_included(&self, path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|include| self.matches(include, path)) && !self.is_excluded(path) && self.is_selected(path)
+    }
+
+    // Only reads the file's metadata when a size or age was asked for
+    fn is_selected(&self, path: &Path) -> bool {
+        self.selection.is_empty() || fs::metadata(path).is_ok_and(|metadata| self.selection.selects(&metadata, self.now))
+    }
+
+    fn matches(&self, set: &GlobSet, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        path.file_name().is_some_and(|name| set.is_match(name)) || set.is_match(relative)
+    }
+}
+
+fn glob_set(patterns: &[impl AsRef<str>]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern.as_ref()).case_insensitive(true).literal_separator(true).build()?);
+    }
+    builder.build()
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase()
+}
+
+pub fn scan_directory(
+    dir: &Path,
+    depth_remaining: usize,
+    filter: &FileFilter,
+    groups: &mut Vec<FileGroup>,
+) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    let mut sidecars = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_symlink() && (!filter.follow_symlinks || is_loop(&path)) {
+            continue;
+        }
+
+        if path.is_dir() {
+            if depth_remaining > 0 && !filter.is_excluded(&path) {
+                scan_directory(&path, depth_remaining - 1, filter, groups)?;
+            }
+        } else if is_media(&path) {
+            // Sidecars aren't filtered, they only come along with a file that was picked up
+            if filter.is_included(&path) {
+                files.push(path);
+            }
+        } else if is_sidecar(&path) {
+            sidecars.push(path);
+        }
+    }
+
+    groups.extend(pair_files(files, sidecars));
+    Ok(())
+}
+
+// A link to a directory that contains it would be scanned forever, as would a broken one that
+// can't be resolved at all
+fn is_loop(link: &Path) -> bool {
+    let (Ok(target), Some(Ok(parent))) = (paths::canonicalize(link), link.parent().map(paths::canonicalize)) else {
+        return true;
+    };
+    target.is_dir() && parent.starts_with(&target)
+}
+
+/// Removes every file that is a hard link to another file in `groups`, or a followed symbolic
+/// link to one, since renaming one of them names the photo under both. The first file that isn't
+/// a symbolic link is kept. Returns the removed files along with the one they're linked to.
+pub fn remove_hard_links(groups: &mut Vec<FileGroup>) -> Vec<(PathBuf, PathBuf)> {
+    let ids = groups.iter().map(|group| file_id(group.primary())).collect::<Vec<_>>();
+    let mut kept: HashMap<(u64, u64), usize> = HashMap::new();
+    for (index, id) in ids.iter().enumerate() {
+        let Some(id) = id else { continue };
+        // A file takes the place of a symbolic link to it that came first
+        let replaces = |other: usize| groups[other].primary().is_symlink() && !groups[index].primary().is_symlink();
+        if kept.get(id).is_none_or(|&other| replaces(other)) {
+            kept.insert(*id, index);
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut remaining = Vec::with_capacity(groups.len());
+    for (index, group) in groups.drain(..).enumerate() {
+        match ids[index].map(|id| kept[&id]) {
+            Some(original) if original != index => removed.push((group.primary().to_path_buf(), original)),
+            _ => remaining.push((index, group)),
+        }
+    }
+    let path_of = |original: usize| remaining.iter().find(|(index, _)| *index == original).map(|(_, group)| group.primary().to_path_buf());
+    let removed = removed.into_iter().filter_map(|(path, original)| Some((path, path_of(original)?))).collect();
+    groups.extend(remaining.into_iter().map(|(_, group)| group));
+    removed
+}
+
+// The device and inode a file's contents are stored under
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// The volume and file index a file's contents are stored under. The standard library has no
+// stable way to read them yet
+#[cfg(windows)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS};
+
+    // Reading the information takes no access rights, and folders only open with backup semantics
+    let file = fs::File::options().access_mode(0).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path).ok()?;
+    // SAFETY: the handle stays open for the call and the information is plain data
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+        return None;
+    }
+    let index = (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow);
+    Some((u64::from(info.dwVolumeSerialNumber), index))
+}
+
+// The standard library has no stable way to identify a file on other platforms
+#[cfg(not(any(unix, windows)))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Whether two paths lead to the same file, as names that only differ in case do on macOS and
+/// Windows volumes.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    match (file_id(a), file_id(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => paths::canonicalize(a).ok().zip(paths::canonicalize(b).ok()).is_some_and(|(a, b)| a == b),
+    }
+}
+
+/// Sorts files by name, with the copies phones and browsers save, such as "IMG_1234 (1).jpg",
+/// right after the file they're a copy of. Breaks ties between photos taken at the same time, so
+/// which of them gets a collision suffix doesn't depend on the order the directory lists them in.
+pub fn copy_order(path: &Path) -> (PathBuf, String, u32, PathBuf) {
+    let stem = lowercase_stem(path);
+    let copy = stem.strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" ("))
+        .and_then(|(base, number)| Some((base.to_string(), number.parse::<u32>().ok()?)));
+    let (base, number) = copy.unwrap_or((stem, 0));
+    (path.parent().map(Path::to_path_buf).unwrap_or_default(), base, number, path.to_path_buf())
+}
+
+fn lowercase_stem(path: &Path) -> String {
+    path.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_lowercase()
+}
+
+// Edits that phones and photo apps save next to the original: iPhones save IMG_E1234 next to
+// IMG_1234 and, when exported, the adjustments of the original as IMG_O1234.AAE, Google Photos
+// saves IMG_1234-edited and Pixels number their edits PXL_20231024_120000123~2. Returns the base
+// name such a file belongs to and the suffix it keeps after renaming.
+fn variant_base(stem: &str) -> Option<(String, String)> {
+    if let Some(base) = stem.strip_suffix("-edited").filter(|base| !base.is_empty()) {
+        return Some((base.to_string(), "_edited".to_string()));
+    }
+    if let Some((base, version)) = stem.rsplit_once('~').filter(|(base, version)| !base.is_empty() && is_number(version)) {
+        return Some((base.to_string(), format!("_v{}", version)));
+    }
+
+    let (prefix, rest) = stem.split_once('_')?;
+    let mut chars = rest.chars();
+    let suffix = match chars.next()? {
+        'e' => "_edited",
+        'o' => "_original",
+        _ => return None,
+    };
+    let number = chars.as_str();
+    if !is_number(number) {
+        return None;
+    }
+    Some((format!("{}_{}", prefix, number), suffix.to_string()))
+}
+
+fn is_number(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+// An app that saves an edit keeps the capture time of the original, so a file that records
+// another one only has a name like an edit. Files without a capture time are given the benefit
+// of the doubt, since exports often leave out the EXIF data.
+fn is_edit_of(variants: &[PathBuf], originals: &[PathBuf]) -> bool {
+    let captured = |paths: &[PathBuf]| paths.iter().filter(|path| !is_video(path)).find_map(|path| capture_date(path));
+    match (captured(variants), captured(originals)) {
+        (Some(variant), Some(original)) => variant == original,
+        _ => true,
+    }
+}
+
+/// Groups files from a single directory by their case-insensitive base name, with iPhone edits
+/// joining the group of their original. Sidecars only join a group, they never form one of their
+/// own.
+fn pair_files(files: Vec<PathBuf>, sidecars: Vec<PathBuf>) -> Vec<FileGroup> {
+    let mut order = Vec::new();
+    let mut by_stem: HashMap<String, Vec<PathBuf>> = HashMap::new();
+
+    for path in files {
+        let stem = lowercase_stem(&path);
+        let members = by_stem.entry(stem.clone()).or_default();
+        if members.is_empty() {
+            order.push(stem);
+        }
+        members.push(path);
+    }
+
+    // Edits without their original in the directory stay a group of their own
+    order.retain(|stem| {
+        let Some((base, _)) = variant_base(stem).filter(|(base, _)| by_stem.get(base).is_some_and(|originals| is_edit_of(&by_stem[stem], originals))) else {
+            return true;
+        };
+        let variants = by_stem.remove(stem).unwrap_or_default();
+        by_stem.entry(base).or_default().extend(variants);
+        false
+    });
+
+    for sidecar in sidecars {
+        let stem = lowercase_stem(&sidecar);
+        // The sidecar of an edit goes where the edit went
+        let base = variant_base(&stem).map(|(base, _)| base).filter(|base| !by_stem.contains_key(&stem) && by_stem.contains_key(base));
+        if let Some(members) = by_stem.get_mut(base.as_ref().unwrap_or(&stem)) {
+            members.push(sidecar);
+        }
+    }
+
+    order.into_iter()
+        .filter_map(|stem| by_stem.remove(&stem))
+        .map(|mut members| {
+            // Camera JPEGs carry the same EXIF as the RAW file and are cheaper to parse. The original
+            // comes before its edits so the group is named after it.
+            let base = members.iter().map(|path| lowercase_stem(path)).min_by_key(|stem| variant_base(stem).is_some());
+            members.sort_by_key(|path| (is_sidecar(path), Some(lowercase_stem(path)) != base, is_video(path), is_raw(path)));
+            FileGroup { members }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| PathBuf::from("photos").join(name)).collect()
+    }
+
+    fn groups(files: &[&str], sidecars: &[&str]) -> Vec<Vec<PathBuf>> {
+        pair_files(paths(files), paths(sidecars)).into_iter().map(|group| group.members).collect()
+    }
+
+    #[test]
+    fn raw_files_and_live_photos_pair_by_name() {
+        let groups = groups(&["IMG_1234.CR2", "img_1234.jpg", "IMG_1235.MOV", "IMG_1235.HEIC"], &["IMG_1234.xmp", "IMG_9999.xmp"]);
+        assert_eq!(groups, [paths(&["img_1234.jpg", "IMG_1234.CR2", "IMG_1234.xmp"]), paths(&["IMG_1235.HEIC", "IMG_1235.MOV"])]);
+    }
+
+    #[test]
+    fn edits_join_their_original() {
+        let groups = groups(
+            &["IMG_E1234.jpg", "IMG_1234.jpg", "IMG_5678-edited.jpg", "IMG_5678.jpg", "PXL_20231024_120000123~2.jpg", "PXL_20231024_120000123.jpg"],
+            &["IMG_O1234.aae", "IMG_E1234.aae"],
+        );
+        assert_eq!(groups, [
+            paths(&["IMG_1234.jpg", "IMG_E1234.jpg", "IMG_O1234.aae", "IMG_E1234.aae"]),
+            paths(&["IMG_5678.jpg", "IMG_5678-edited.jpg"]),
+            paths(&["PXL_20231024_120000123.jpg", "PXL_20231024_120000123~2.jpg"]),
+        ]);
+    }
+
+    #[test]
+    fn edits_without_their_original_stay_on_their_own() {
+        assert_eq!(groups(&["IMG_E1234.jpg", "IMG_E1234.mov"], &[]), [paths(&["IMG_E1234.jpg", "IMG_E1234.mov"])]);
+        assert_eq!(groups(&["IMG_Edit.jpg", "IMG_1234.jpg"], &[]), [paths(&["IMG_Edit.jpg"]), paths(&["IMG_1234.jpg"])]);
+    }
+
+    #[test]
+    fn edits_keep_a_suffix_after_renaming() {
+        let group = pair_files(paths(&["IMG_1234.jpg", "IMG_E1234.jpg", "IMG_1234~3.jpg"]), Vec::new()).remove(0);
+        assert_eq!(group.member_stem(&group.members[0], "20231024_1"), "20231024_1");
+        assert_eq!(group.member_stem(&group.members[1], "20231024_1"), "20231024_1_edited");
+        assert_eq!(group.member_stem(&group.members[2], "20231024_1"), "20231024_1_v3");
+        assert_eq!(group.member_stem(&group.members[1], &format!("20231024{}_1", VARIANT_SLOT)), "20231024_edited_1");
+        assert_eq!(group.member_stem(&group.members[0], &format!("20231024{}_1", VARIANT_SLOT)), "20231024_1");
+    }
+
+    #[test]
+    fn copies_sort_right_after_their_original() {
+        let mut files = paths(&["IMG_1234 (2).jpg", "IMG_1235.jpg", "IMG_1234 (1).jpg", "IMG_1234.jpg"]);
+        files.sort_by_key(|path| copy_order(path));
+        assert_eq!(files, paths(&["IMG_1234.jpg", "IMG_1234 (1).jpg", "IMG_1234 (2).jpg", "IMG_1235.jpg"]));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::output::{FileRecord, Summary};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+/// What a client asks to be labeled with `POST /jobs`, e.g. `{"paths": ["/photos/import"]}`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct JobRequest {
+    /// Directories or single files, as on the command line
+    pub paths: Vec<PathBuf>,
+    /// Only work out the new names, on top of the server's own --dry-run
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A submitted job as `GET /jobs/{id}` reports it.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobStatus {
+    pub id: u64,
+    pub paths: Vec<PathBuf>,
+    pub dry_run: bool,
+    pub state: JobState,
+    /// Files handled so far
+    pub files_done: usize,
+    pub summary: Option<Summary>,
+    pub error: Option<String>,
+    /// What happened to each file, once the job is over
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<FileRecord>,
+}
+
+struct Job {
+    status: JobStatus,
+    progress: Arc<AtomicUsize>,
+}
+
+#[derive(Clone)]
+struct Jobs {
+    jobs: Arc<Mutex<Vec<Job>>>,
+    queue: mpsc::UnboundedSender<u64>,
+}
+
+impl Jobs {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn status(job: &Job) -> JobStatus {
+        JobStatus { files_done: job.progress.load(Ordering::Relaxed), ..job.status.clone() }
+    }
+}
+
+/// The jobs clients submitted, to be run one at a time by whoever holds the queue. Running them in
+/// turn keeps every job within the same rate limit and leaves the cache and journals to one writer.
+pub struct JobQueue {
+    jobs: Jobs,
+    pending: mpsc::UnboundedReceiver<u64>,
+}
+
+/// A job taken off the queue. Its progress counter goes to the run's report.
+pub struct NextJob {
+    pub id: u64,
+    pub request: JobRequest,
+    pub progress: Arc<AtomicUsize>,
+}
+
+impl JobQueue {
+    /// Waits for the next job and marks it as running.
+    pub async fn next(&mut self) -> Option<NextJob> {
+        let id = self.pending.recv().await?;
+        let mut jobs = self.jobs.lock();
+        let job = jobs.iter_mut().find(|job| job.status.id == id)?;
+        job.status.state = JobState::Running;
+        let request = JobRequest { paths: job.status.paths.clone(), dry_run: job.status.dry_run };
+        Some(NextJob { id, request, progress: job.progress.clone() })
+    }
+
+    /// Records how a job ended.
+    pub fn finish(&self, id: u64, outcome: Result<(Summary, Vec<FileRecord>), String>) {
+        let mut jobs = self.jobs.lock();
+        let Some(job) = jobs.iter_mut().find(|job| job.status.id == id) else {
+            return;
+        };
+        match outcome {
+            Ok((summary, records)) => {
+                job.status.state = JobState::Done;
+                job.status.summary = Some(summary);
+                job.status.records = records;
+            }
+            Err(error) => {
+                job.status.state = JobState::Failed;
+                job.status.error = Some(error);
+            }
+        }
+    }
+}
+
+/// Starts answering HTTP requests on `listen` in the background and returns the queue the
+/// submitted jobs arrive on. The API:
+///
+/// - `POST /jobs` with a `JobRequest` queues a job and answers `{"id": 1}`
+/// - `GET /jobs` lists every job, without the records of each file
+/// - `GET /jobs/{id}` reports a single job, with the records once it's done
+/// - `GET /health` answers `{"status": "ok"}`
+pub async fn start(listen: SocketAddr) -> std::io::Result<JobQueue> {
+    let (queue, pending) = mpsc::unbounded_channel();
+    let jobs = Jobs { jobs: Arc::new(Mutex::new(Vec::new())), queue };
+
+    let app = Router::new()
+        .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/{id}", get(job_status))
+        .with_state(jobs.clone());
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Error: the server stopped: {}", e);
+        }
+    });
+
+    Ok(JobQueue { jobs, pending })
+}
+
+async fn submit_job(State(jobs): State<Jobs>, Json(request): Json<JobRequest>) -> Response {
+    if request.paths.is_empty() {
+        return error(StatusCode::BAD_REQUEST, "paths can't be empty");
+    }
+    if let Some(missing) = request.paths.iter().find(|path| !path.exists()) {
+        return error(StatusCode::BAD_REQUEST, &format!("{} is not a file or directory", missing.display()));
+    }
+
+    let id = {
+        let mut list = jobs.lock();
+        let id = list.last().map_or(1, |job| job.status.id + 1);
+        list.push(Job {
+            status: JobStatus {
+                id,
+                paths: request.paths,
+                dry_run: request.dry_run,
+                state: JobState::Queued,
+                files_done: 0,
+                summary: None,
+                error: None,
+                records: Vec::new(),
+            },
+            progress: Arc::new(AtomicUsize::new(0)),
+        });
+        id
+    };
+    debug!("Queued job {}", id);
+    if jobs.queue.send(id).is_err() {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "the server is shutting down");
+    }
+    (StatusCode::ACCEPTED, Json(json!({ "id": id }))).into_response()
+}
+
+async fn list_jobs(State(jobs): State<Jobs>) -> Json<Vec<JobStatus>> {
+    let list = jobs.lock();
+    Json(list.iter().map(|job| JobStatus { records: Vec::new(), ..Jobs::status(job) }).collect())
+}
+
+async fn job_status(State(jobs): State<Jobs>, Path(id): Path<u64>) -> Response {
+    let list = jobs.lock();
+    match list.iter().find(|job| job.status.id == id) {
+        Some(job) => Json(Jobs::status(job)).into_response(),
+        None => error(StatusCode::NOT_FOUND, &format!("no job {}", id)),
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,98 @@
+use crate::cache::{CacheScope, GeocodeCache};
+use crate::checkpoint::Checkpoint;
+use crate::error::RunError;
+use crate::geocoder::{build_geocoder, GeocoderOptions, Provider, ReverseGeocoder};
+use crate::metrics::Counted;
+use crate::output::Report;
+use crate::quota::{Budget, Budgeted};
+use crate::tui;
+use clap::ValueEnum;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{error, info};
+
+pub struct SessionOptions<'a> {
+    pub geocoder: GeocoderOptions<'a>,
+    /// Requests the whole run may send, on top of the daily quota
+    pub max_requests: Option<usize>,
+    /// Requests a day the provider allows, including those earlier runs sent
+    pub daily_quota: Option<usize>,
+    /// Look locations up in the geocode cache before asking the geocoder
+    pub use_cache: bool,
+    /// Number of decimals coordinates are rounded to when looking up cached locations
+    pub cache_precision: usize,
+}
+
+/// What every directory, watched batch and served job of a run shares: the geocoder, paced to its
+/// rate limit and held to the run's budget, and the geocode cache. Setting them up once keeps the
+/// limits across all of them and reads the cache a single time.
+pub struct Session {
+    pub geocoder: Box<dyn ReverseGeocoder>,
+    pub budget: Arc<Budget>,
+    pub cache: GeocodeCache,
+    pub provider: Provider,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Session {
+    pub fn start(options: SessionOptions) -> Result<Session, RunError> {
+        let SessionOptions { geocoder, max_requests, daily_quota, use_cache, cache_precision } = options;
+        let provider = geocoder.provider;
+        let provider_name = provider.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        let scope = CacheScope { provider: provider_name.clone(), url: geocoder.url.clone(), language: geocoder.language.clone(), zoom: geocoder.zoom };
+        // Local lookups don't count towards any plan, and are as cheap as the cache so they
+        // shouldn't end up mixed into it
+        let local = matches!(provider, Provider::Offline | Provider::Fixture);
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let geocoder: Box<dyn ReverseGeocoder> = Box::new(Counted::new(build_geocoder(geocoder)?, requests.clone()));
+
+        let mut budget = Budget::new(max_requests);
+        if !local {
+            budget = budget.tracking_daily(&provider_name, daily_quota);
+        }
+        if let Some(limit) = budget.limit() {
+            info!("Sending at most {} geocoding requests", limit);
+        }
+        let budget = Arc::new(budget);
+        let geocoder = Box::new(Budgeted::new(geocoder, budget.clone()));
+
+        let cache = if use_cache && !local { GeocodeCache::load_scoped(&scope, cache_precision) } else { GeocodeCache::disabled() };
+        Ok(Session { geocoder, budget, cache, provider, requests })
+    }
+
+    /// The requests sent to the geocoder since the last call, for the metrics of each directory.
+    pub fn take_requests(&self) -> usize {
+        self.requests.swap(0, Ordering::Relaxed)
+    }
+
+    /// Writes the new cache entries and the requests sent to the daily usage.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        self.cache.save()?;
+        self.budget.save()
+    }
+
+    /// Saves what the run has resolved so far so it can be picked up again with --resume, and
+    /// returns the error the run stops with.
+    pub fn stop(&mut self, checkpoint: &mut Checkpoint, report: &mut Report, summary: &str, stopped: fn(String) -> RunError) -> RunError {
+        tui::restore();
+        if let Err(e) = self.cache.save() {
+            error!("Error saving the geocode cache: {}", e);
+        }
+        if let Err(e) = checkpoint.save() {
+            error!("Error saving the checkpoint: {}", e);
+        }
+        if let Err(e) = self.budget.save() {
+            error!("Error saving the daily geocoding usage: {}", e);
+        }
+        report.finish();
+        stopped(format!("{}. Run again with --resume to continue", summary))
+    }
+
+    /// Stops once the budget ran out, with `left` files still to label.
+    pub fn stop_over_budget(&mut self, checkpoint: &mut Checkpoint, report: &mut Report, left: usize, carried_out: bool) -> RunError {
+        let renamed = if carried_out { ", the files of the earlier chunks were renamed" } else { "" };
+        let summary = format!("{}, {} files weren't labeled yet{}", self.budget.used_up(), left, renamed);
+        self.stop(checkpoint, report, &summary, RunError::OverBudget)
+    }
+}
@@ -0,0 +1,92 @@
+use crate::output::FileRecord;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What an archive holds, worked out from the records of a run that didn't rename anything.
+#[derive(Debug, Default)]
+pub struct Stats {
+    files: usize,
+    located: usize,
+    countries: BTreeMap<String, usize>,
+    cities: BTreeMap<String, usize>,
+    /// Keyed by yyyyMM so months sort in order
+    months: BTreeMap<String, usize>,
+    first_date: Option<String>,
+    last_date: Option<String>,
+}
+
+const UNKNOWN: &str = "Unknown";
+
+impl Stats {
+    pub fn new(records: &[FileRecord]) -> Stats {
+        let mut stats = Stats { files: records.len(), ..Stats::default() };
+
+        for record in records {
+            if record.lat.is_some() && record.lon.is_some() {
+                stats.located += 1;
+                let address = record.address.as_ref();
+                let country = address.and_then(|address| address.country.clone()).unwrap_or_else(|| UNKNOWN.to_string());
+                let city = address
+                    .and_then(|address| address.city.as_ref().or(address.town.as_ref()).or(address.village.as_ref()))
+                    .map(|city| format!("{}, {}", city, country))
+                    .unwrap_or_else(|| UNKNOWN.to_string());
+                *stats.countries.entry(country).or_default() += 1;
+                *stats.cities.entry(city).or_default() += 1;
+            }
+
+            if let Some(date) = record.date.as_ref().filter(|date| date.len() == 8) {
+                *stats.months.entry(date[..6].to_string()).or_default() += 1;
+                if stats.first_date.as_ref().is_none_or(|first| date < first) {
+                    stats.first_date = Some(date.clone());
+                }
+                if stats.last_date.as_ref().is_none_or(|last| date > last) {
+                    stats.last_date = Some(date.clone());
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let coverage = if self.files == 0 { 0.0 } else { self.located as f64 * 100.0 / self.files as f64 };
+        writeln!(f, "Files:        {}", self.files)?;
+        writeln!(f, "GPS coverage: {:.1}% ({} of {})", coverage, self.located, self.files)?;
+        match (&self.first_date, &self.last_date) {
+            (Some(first), Some(last)) => writeln!(f, "Date range:   {} to {}", iso_date(first), iso_date(last))?,
+            _ => writeln!(f, "Date range:   unknown")?,
+        }
+
+        write_breakdown(f, "Per country", by_count(&self.countries))?;
+        write_breakdown(f, "Per city", by_count(&self.cities))?;
+        let months = self.months.iter().map(|(month, count)| (format!("{}-{}", &month[..4], &month[4..]), *count));
+        write_breakdown(f, "Per month", months.collect())
+    }
+}
+
+// Most photos first, then alphabetically
+fn by_count(counts: &BTreeMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts = counts.iter().map(|(name, count)| (name.clone(), *count)).collect::<Vec<_>>();
+    counts.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+    counts
+}
+
+fn write_breakdown(f: &mut fmt::Formatter<'_>, title: &str, counts: Vec<(String, usize)>) -> fmt::Result {
+    if counts.is_empty() {
+        return Ok(());
+    }
+    writeln!(f)?;
+    writeln!(f, "{}:", title)?;
+    let width = counts.iter().map(|(_, count)| count.to_string().len()).max().unwrap_or(0);
+    for (name, count) in counts {
+        writeln!(f, "  {:>width$}  {}", count, name, width = width)?;
+    }
+    Ok(())
+}
+
+// "20231024" as "2023-10-24"
+fn iso_date(date: &str) -> String {
+    format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..])
+}
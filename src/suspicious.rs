@@ -0,0 +1,65 @@
+use crate::geo::haversine_km;
+use crate::metadata::{PhotoMetadata, PositionSource};
+
+// A little faster than an airliner, so only positions nobody could have travelled to between two
+// photos are suspected
+const MAX_SPEED_KMH: f64 = 1200.0;
+
+// Jumps shorter than this are left alone however little time there is between them, since phones
+// commonly take a while to settle on a fix after being switched on
+const MIN_JUMP_KM: f64 = 1000.0;
+
+/// Why coordinates can't be a real position, such as the 0, 0 ("Null Island") cameras write when
+/// they had no fix, or values out of range.
+pub fn implausible_position(lat: f64, lon: f64) -> Option<String> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Some(format!("GPS position {}, {} is out of range", lat, lon));
+    }
+    if lat.abs() < 1e-6 && lon.abs() < 1e-6 {
+        return Some("GPS position 0, 0 is what cameras record without a fix".to_string());
+    }
+    None
+}
+
+/// Finds positions that are likely wrong, among photos sorted by capture time: those that can't be
+/// a position at all, and stale fixes that put a photo thousands of kilometers from the photos taken
+/// right before and after it, further than anyone could have travelled in between. Returns why for
+/// each suspected photo. Positions from an overrides file are trusted.
+pub fn suspicious_positions(metadata: &[Option<PhotoMetadata>]) -> Vec<Option<String>> {
+    let mut reasons = metadata.iter()
+        .map(|metadata| {
+            let metadata = metadata.as_ref().filter(|metadata| metadata.position_source != PositionSource::Override)?;
+            implausible_position(metadata.lat, metadata.lon)
+        })
+        .collect::<Vec<_>>();
+
+    // Only positions the files recorded themselves, since those on a track or between neighbors
+    // follow from other positions
+    let timed = metadata.iter()
+        .enumerate()
+        .filter(|(index, _)| reasons[*index].is_none())
+        .filter_map(|(index, metadata)| {
+            let metadata = metadata.as_ref().filter(|metadata| metadata.position_source == PositionSource::Embedded)?;
+            Some((index, metadata.timestamp?, metadata.lat, metadata.lon))
+        })
+        .collect::<Vec<_>>();
+
+    for window in timed.windows(3) {
+        let [before, current, after] = [window[0], window[1], window[2]];
+        let jump = |from: (usize, f64, f64, f64), to: (usize, f64, f64, f64)| {
+            let distance = haversine_km(from.2, from.3, to.2, to.3);
+            let hours = (to.1 - from.1).abs() / 3600.0;
+            (distance, distance > MIN_JUMP_KM && distance > MAX_SPEED_KMH * hours)
+        };
+        let (distance_before, impossible_before) = jump(before, current);
+        let (distance_after, impossible_after) = jump(current, after);
+        let (_, neighbors_disagree) = jump(before, after);
+        if impossible_before && impossible_after && !neighbors_disagree {
+            reasons[current.0] = Some(format!(
+                "GPS position is {:.0} km from the photos taken before and after it",
+                distance_before.min(distance_after)
+            ));
+        }
+    }
+    reasons
+}
@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// How many tags a file gets at most, and how sure the classifier has to be of each.
+#[derive(Debug, Clone, Copy)]
+pub struct TagOptions {
+    pub max_tags: usize,
+    /// Lowest probability from 0 to 1 a tag needs
+    pub min_confidence: f32,
+}
+
+/// Reads the class names of a model, one per line in the order of its outputs. Lines such as
+/// "tench, Tinca tinca" from the ImageNet lists are shortened to the first name.
+pub fn load_labels(path: &Path) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let labels = fs::read_to_string(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .lines()
+        .map(|line| line.split(',').next().unwrap_or_default().trim().to_string())
+        .collect::<Vec<_>>();
+    if labels.iter().all(String::is_empty) {
+        return Err(format!("{}: no labels found", path.display()).into());
+    }
+    Ok(labels)
+}
+
+// Picks the most likely classes from the scores a model gave, which may be logits or probabilities
+#[cfg(feature = "tags")]
+fn top_tags(scores: &[f32], labels: &[String], options: TagOptions) -> Vec<String> {
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp = scores.iter().map(|score| (score - max).exp()).collect::<Vec<_>>();
+    let sum = exp.iter().sum::<f32>();
+
+    let mut ranked = exp.iter().map(|value| value / sum).enumerate().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked.into_iter()
+        .take_while(|&(_, probability)| probability >= options.min_confidence)
+        .filter_map(|(index, _)| labels.get(index).filter(|label| !label.is_empty()).cloned())
+        .take(options.max_tags)
+        .collect()
+}
+
+#[cfg(feature = "tags")]
+mod onnx {
+    use super::{load_labels, top_tags, TagOptions};
+    use image::imageops::FilterType;
+    use std::error::Error;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tract_onnx::prelude::*;
+
+    // The input size and normalization of the ImageNet classifiers in the ONNX model zoo, such as
+    // MobileNet, SqueezeNet and ResNet
+    const SIZE: u32 = 224;
+    const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+    const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+    /// An image classifier that runs locally, without sending the photos anywhere.
+    pub struct Classifier {
+        model: Arc<TypedRunnableModel>,
+        labels: Vec<String>,
+        options: TagOptions,
+    }
+
+    impl Classifier {
+        /// Loads an ONNX model that takes a 1x3x224x224 RGB image and gives a score per class,
+        /// along with the names of its classes.
+        pub fn load(model: &Path, labels: &Path, options: TagOptions) -> Result<Classifier, Box<dyn Error + Send + Sync>> {
+            let labels = load_labels(labels)?;
+            let model = tract_onnx::onnx()
+                .model_for_path(model)
+                .and_then(|model| model.with_input_fact(0, f32::fact([1, 3, SIZE as usize, SIZE as usize]).into()))
+                .and_then(|model| model.into_optimized())
+                .and_then(|model| model.into_runnable())
+                .map_err(|e| format!("{}: {}", model.display(), e))?;
+            Ok(Classifier { model, labels, options })
+        }
+
+        /// The tags for a photo, most likely first. Formats the image decoder doesn't know, such
+        /// as RAW and HEIC files, are an error.
+        pub fn classify(&self, path: &Path) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+            let image = image::open(path)?.resize_exact(SIZE, SIZE, FilterType::Triangle).to_rgb8();
+            let input = tract_ndarray::Array4::from_shape_fn((1, 3, SIZE as usize, SIZE as usize), |(_, channel, y, x)| {
+                let value = image.get_pixel(x as u32, y as u32)[channel] as f32 / 255.0;
+                (value - MEAN[channel]) / STD[channel]
+            });
+
+            let outputs = self.model.run(tvec!(Tensor::from(input).into()))?;
+            let scores = outputs[0].to_plain_array_view::<f32>()?.iter().copied().collect::<Vec<_>>();
+            Ok(top_tags(&scores, &self.labels, self.options))
+        }
+    }
+}
+
+#[cfg(feature = "tags")]
+pub use onnx::Classifier;
+
+/// Stands in for the classifier in builds without the `tags` feature, and refuses to load.
+#[cfg(not(feature = "tags"))]
+pub struct Classifier;
+
+#[cfg(not(feature = "tags"))]
+impl Classifier {
+    pub fn load(_model: &Path, _labels: &Path, _options: TagOptions) -> Result<Classifier, Box<dyn Error + Send + Sync>> {
+        Err("image-labeler was built without content tags, rebuild it with `--features tags`".into())
+    }
+
+    pub fn classify(&self, _path: &Path) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(Vec::new())
+    }
+}
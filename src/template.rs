@@ -0,0 +1,392 @@
+use crate::filename::{TargetFs, MAX_NAME_LEN};
+use crate::plan::SHORT_HASH_LEN;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+pub const DEFAULT_TEMPLATE: &str = "{date}_{seq}_{country_code}, {location}";
+
+pub const DEFAULT_FOLDER_TEMPLATE: &str = "{year}/{month} - {month_name}/{city}";
+
+/// Where {variant} goes in a rendered name, until each file of a group puts its own suffix there.
+pub const VARIANT_SLOT: &str = "\u{E000}";
+
+pub const PLACEHOLDERS: &[&str] = &[
+    "date",
+    "year",
+    "month",
+    "month_name",
+    "day",
+    "weekday",
+    "time",
+    "seq",
+    "location",
+    "city",
+    "road",
+    "neighbourhood",
+    "suburb",
+    "county",
+    "state",
+    "postcode",
+    "country",
+    "country_code",
+    "camera",
+    "make",
+    "lens",
+    "orig_name",
+    "event",
+    "pluscode",
+    "w3w",
+    "altitude",
+    "poi",
+    "tags",
+    "variant",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A parsed filename template such as `{date}_{seq}_{country_code}, {location}`. The file
+/// extension is always appended and is not part of the template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug)]
+pub struct TemplateError(String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid template: {}", self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let template = Template::parse_segments(template)?;
+
+        if !template.segments.iter().any(|s| matches!(s, Segment::Placeholder(_))) {
+            return Err(TemplateError("template must contain at least one placeholder".to_string()));
+        }
+
+        Ok(template)
+    }
+
+    fn parse_segments(template: &str) -> Result<Template, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError(format!("unclosed placeholder \"{{{}\"", name))),
+                        }
+                    }
+
+                    if !PLACEHOLDERS.contains(&name.as_str()) {
+                        return Err(TemplateError(format!(
+                            "unknown placeholder \"{{{}}}\", expected one of: {}",
+                            name,
+                            PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+                        )));
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(name));
+                }
+                '}' => return Err(TemplateError("unmatched \"}\", use \"}}\" for a literal brace".to_string())),
+                '/' | '\\' => return Err(TemplateError("path separators are not allowed".to_string())),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Renders the template, leaving missing values empty and trimming any separators left dangling
+    /// at either end as a result.
+    pub fn render(&self, values: &HashMap<&str, String>) -> String {
+        let rendered = self.segments.iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.as_str(),
+                Segment::Placeholder(name) if name == "variant" => VARIANT_SLOT,
+                Segment::Placeholder(name) => values.get(name.as_str()).map(String::as_str).unwrap_or(""),
+            })
+            .collect::<String>();
+
+        rendered
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_matches(|c: char| c == ' ' || c == ',' || c == '_' || c == '-')
+            .to_string()
+    }
+
+    /// Renders a name that is valid on `target` and leaves room for `reserved` more characters,
+    /// such as the extension. A name that's too long loses the trailing parts of its location
+    /// first, e.g. "Centrum, Amsterdam, Noord-Holland" becomes "Centrum, Amsterdam", and is only
+    /// cut short after that.
+    pub fn render_for(&self, values: &HashMap<&str, String>, target: TargetFs, reserved: usize) -> String {
+        let max_len = MAX_NAME_LEN.saturating_sub(reserved);
+        let render = |values: &HashMap<&str, String>| target.sanitize(&self.render(values));
+        let fits = |name: &str| target.name_len(name) <= max_len;
+
+        let mut name = render(values);
+        if fits(&name) {
+            return name;
+        }
+
+        if let Some(location) = values.get("location") {
+            let mut values = values.clone();
+            let mut parts = location.split(", ").collect::<Vec<_>>();
+            while parts.len() > 1 {
+                parts.pop();
+                values.insert("location", parts.join(", "));
+                name = render(&values);
+                if fits(&name) {
+                    return name;
+                }
+            }
+
+            let excess = target.name_len(&name) - max_len;
+            let location = parts.join(", ");
+            values.insert("location", target.truncate(&location, target.name_len(&location).saturating_sub(excess)));
+            name = render(&values);
+            if fits(&name) {
+                return name;
+            }
+        }
+
+        let truncated = target.truncate(&name, max_len);
+        target.sanitize(truncated.trim_end_matches([' ', ',', '_', '-']))
+    }
+
+    /// Whether the template has a `{name}` placeholder.
+    pub fn uses(&self, name: &str) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, Segment::Placeholder(placeholder) if placeholder == name))
+    }
+
+    /// Whether `stem` looks like something this template produced. Only templates with at least one
+    /// placeholder of a recognizable shape ({date}, {time}, {seq} or {country_code}) can match, since
+    /// free-form placeholders alone would match any name.
+    pub fn matches(&self, stem: &str) -> bool {
+        let recognizable = self.segments.iter().any(|segment| {
+            matches!(segment, Segment::Placeholder(name) if matches!(name.as_str(), "date" | "time" | "seq" | "country_code"))
+        });
+        recognizable && matches_segments(&self.segments, stem).is_some()
+    }
+
+    /// The values the placeholders had in a name this template produced, such as "seq" => "3"
+    /// for "20231024_3_NL, Amsterdam". None when `stem` doesn't look like one of its names.
+    pub fn values_in<'a>(&self, stem: &'a str) -> Option<HashMap<&str, &'a str>> {
+        if !self.matches(stem) {
+            return None;
+        }
+        let values = matches_segments(&self.segments, stem)?;
+        Some(values.into_iter().collect())
+    }
+}
+
+// The value of each placeholder when `text` fits the segments
+fn matches_segments<'s, 't>(segments: &'s [Segment], text: &'t str) -> Option<Vec<(&'s str, &'t str)>> {
+    match segments.split_first() {
+        None => text.is_empty().then(Vec::new),
+        Some((Segment::Literal(literal), rest)) => matches_segments(rest, text.strip_prefix(literal.as_str())?),
+        // Placeholders without a value, like {variant} for the original, render empty
+        Some((Segment::Placeholder(name), rest)) => std::iter::once(0)
+            .chain(text.char_indices().map(|(i, c)| i + c.len_utf8()))
+            .filter(|&end| accepts(name, &text[..end]))
+            .find_map(|end| {
+                let mut values = matches_segments(rest, &text[end..])?;
+                values.push((name.as_str(), &text[..end]));
+                Some(values)
+            }),
+    }
+}
+
+// Whether a rendered placeholder could have produced `value`
+fn accepts(name: &str, value: &str) -> bool {
+    let digits = |count: usize| value.len() == count && value.chars().all(|c| c.is_ascii_digit());
+    match name {
+        "date" => digits(8),
+        "time" => digits(6),
+        // A counter, or the content hash of --seq-format hash
+        "seq" => {
+            let hash = value.len() == SHORT_HASH_LEN && value.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+            hash || (!value.is_empty() && value.chars().all(|c| c.is_ascii_digit()))
+        }
+        "country_code" => value == "UNKNOWN" || (value.len() == 2 && value.chars().all(|c| c.is_ascii_uppercase())),
+        _ => true,
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::parse(DEFAULT_TEMPLATE).expect("default template is valid")
+    }
+}
+
+/// A destination folder layout such as `{year}/{month} - {month_name}/{city}`, where every
+/// `/`-separated component becomes one directory level.
+#[derive(Debug, Clone)]
+pub struct FolderTemplate {
+    components: Vec<Template>,
+}
+
+impl FolderTemplate {
+    pub fn parse(template: &str) -> Result<FolderTemplate, TemplateError> {
+        let components = template.split('/')
+            .map(|component| match component.trim() {
+                "" => Err(TemplateError("folder template contains an empty directory name".to_string())),
+                "." | ".." => Err(TemplateError("folder template must not contain \".\" or \"..\"".to_string())),
+                component => Template::parse_segments(component),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if components.iter().any(|component| component.uses("variant")) {
+            return Err(TemplateError("{variant} can only be used in the file name template".to_string()));
+        }
+
+        Ok(FolderTemplate { components })
+    }
+
+    /// Adds the level for `group_by` at the top of the layout.
+    pub fn grouped_by(mut self, group_by: Option<GroupBy>) -> FolderTemplate {
+        if let Some(GroupBy::Camera) = group_by {
+            let camera = Template { segments: vec![Segment::Placeholder("camera".to_string())] };
+            self.components.insert(0, camera);
+        }
+        self
+    }
+
+    /// Whether any level of the layout has a `{name}` placeholder.
+    pub fn uses(&self, name: &str) -> bool {
+        self.components.iter().any(|component| component.uses(name))
+    }
+
+    /// Renders the relative directory path. Levels that end up empty are named "unknown" so files
+    /// without e.g. a city still land at the same depth as the rest.
+    pub fn render(&self, values: &HashMap<&str, String>, target: TargetFs) -> PathBuf {
+        self.components.iter()
+            .map(|component| match component.render_for(values, target, 0) {
+                name if name.is_empty() || name == "." || name == ".." => "unknown".to_string(),
+                name => name,
+            })
+            .collect()
+    }
+}
+
+/// A directory level to sort organized files into above the folder template.
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupBy {
+    /// The camera model, as in {camera}
+    Camera,
+}
+
+impl Default for FolderTemplate {
+    fn default() -> Self {
+        FolderTemplate::parse(DEFAULT_FOLDER_TEMPLATE).expect("default folder template is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&'static str, &str)]) -> HashMap<&'static str, String> {
+        pairs.iter().map(|&(name, value)| (name, value.to_string())).collect()
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let values = values(&[("date", "20231024"), ("seq", "3"), ("country_code", "NL"), ("location", "Dam, Amsterdam")]);
+        assert_eq!(Template::default().render(&values), "20231024_3_NL, Dam, Amsterdam");
+        assert_eq!(Template::parse("{{{city}}}").unwrap().render(&HashMap::from([("city", "Amsterdam".to_string())])), "{Amsterdam}");
+    }
+
+    #[test]
+    fn missing_values_leave_no_dangling_separators() {
+        let values = values(&[("date", "20231024"), ("seq", "3")]);
+        assert_eq!(Template::default().render(&values), "20231024_3");
+        assert_eq!(Template::parse("{city} - {date}").unwrap().render(&values), "20231024");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!(Template::parse("photo").is_err());
+        assert!(Template::parse("{date").is_err());
+        assert!(Template::parse("{date}}").is_err());
+        assert!(Template::parse("{weather}").is_err());
+        assert!(Template::parse("{year}/{date}").is_err());
+        assert!(FolderTemplate::parse("{year}//{city}").is_err());
+        assert!(FolderTemplate::parse("{year}/../{city}").is_err());
+        assert!(FolderTemplate::parse("{year}/{variant}").is_err());
+    }
+
+    #[test]
+    fn long_names_drop_trailing_location_parts_first() {
+        let location = format!("{}, Amsterdam, Noord-Holland", "a".repeat(200));
+        let values = values(&[("date", "20231024"), ("seq", "1"), ("country_code", "NL"), ("location", &location)]);
+        let name = Template::default().render_for(&values, TargetFs::Posix, 20);
+        assert_eq!(name, format!("20231024_1_NL, {}, Amsterdam", "a".repeat(200)));
+
+        let name = Template::default().render_for(&values, TargetFs::Posix, 100);
+        assert_eq!(name.len(), MAX_NAME_LEN - 100);
+        assert!(name.starts_with("20231024_1_NL, aaa"));
+    }
+
+    #[test]
+    fn names_are_made_valid_for_the_target() {
+        let values = values(&[("city", "Amsterdam?"), ("road", "CON")]);
+        assert_eq!(Template::parse("{city}").unwrap().render_for(&values, TargetFs::Windows, 0), "Amsterdam_");
+        assert_eq!(Template::parse("{road}").unwrap().render_for(&values, TargetFs::Windows, 0), "CON_");
+        assert_eq!(Template::parse("{road}").unwrap().render_for(&values, TargetFs::Posix, 0), "CON");
+    }
+
+    #[test]
+    fn rendered_names_are_recognized() {
+        let template = Template::default();
+        let values = template.values_in("20231024_3_NL, Dam, Amsterdam").unwrap();
+        assert_eq!(values["date"], "20231024");
+        assert_eq!(values["seq"], "3");
+        assert_eq!(values["location"], "Dam, Amsterdam");
+        assert!(!template.matches("IMG_1234"));
+        assert!(!Template::parse("{city}").unwrap().matches("Amsterdam"));
+    }
+
+    #[test]
+    fn empty_folder_levels_are_named_unknown() {
+        let values = values(&[("year", "2023"), ("month", "10"), ("month_name", "October")]);
+        assert_eq!(FolderTemplate::default().render(&values, TargetFs::Posix), PathBuf::from("2023/10 - October/unknown"));
+        let grouped = FolderTemplate::parse("{year}").unwrap().grouped_by(Some(GroupBy::Camera));
+        assert_eq!(grouped.render(&values, TargetFs::Posix), PathBuf::from("unknown/2023"));
+    }
+}
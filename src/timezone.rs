@@ -0,0 +1,75 @@
+use crate::datetime::format_unix_time;
+use crate::metadata::PhotoMetadata;
+use chrono::{NaiveDate, Offset, TimeZone};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use tzf_rs::DefaultFinder;
+
+/// Which clock the dates and times in filenames are read from.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateTimezone {
+    /// Whatever the camera's clock said
+    #[default]
+    Camera,
+    /// Local time where the photo was taken, looked up from its coordinates
+    Local,
+    /// UTC
+    Utc,
+}
+
+/// Rewrites capture dates and times to the configured clock. Files whose UTC capture time isn't
+/// known (no offset or GPS time recorded, and no `--camera-timezone`) are left as they are.
+pub struct TimeNormalizer {
+    mode: DateTimezone,
+    // Loading the timezone polygons takes a moment, so only do it when they're needed
+    finder: Option<DefaultFinder>,
+}
+
+impl TimeNormalizer {
+    pub fn new(mode: DateTimezone) -> TimeNormalizer {
+        let finder = (mode == DateTimezone::Local).then(DefaultFinder::new);
+        TimeNormalizer { mode, finder }
+    }
+
+    /// Returns false when the file's time couldn't be normalized.
+    pub fn normalize(&self, metadata: &mut PhotoMetadata) -> bool {
+        if self.mode == DateTimezone::Camera {
+            return true;
+        }
+
+        let Some(utc) = metadata.timestamp.filter(|_| metadata.timestamp_is_utc) else {
+            return false;
+        };
+        let seconds = utc.floor() as i64;
+
+        let offset = match &self.finder {
+            Some(finder) => {
+                let Ok(timezone) = finder.get_tz_name(metadata.lon, metadata.lat).parse::<Tz>() else {
+                    return false;
+                };
+                let Some(instant) = chrono::DateTime::from_timestamp(seconds, 0) else {
+                    return false;
+                };
+                timezone.offset_from_utc_datetime(&instant.naive_utc()).fix().local_minus_utc() as i64
+            }
+            None => 0,
+        };
+
+        let Ok(local) = u64::try_from(seconds + offset) else {
+            return false;
+        };
+        let (date, time) = format_unix_time(local);
+        metadata.date = date;
+        metadata.time = Some(time);
+        true
+    }
+}
+
+/// Seconds since the Unix epoch for a wall clock date (yyyyMMdd) and time (HHmmss) in `timezone`.
+/// Ambiguous times, at the end of daylight saving time, resolve to the earlier instant.
+pub fn local_to_utc(date: &str, time: &str, timezone: Tz) -> Option<i64> {
+    let field = |value: &str, range: std::ops::Range<usize>| value.get(range)?.parse::<u32>().ok();
+    let naive = NaiveDate::from_ymd_opt(date.get(0..4)?.parse().ok()?, field(date, 4..6)?, field(date, 6..8)?)?
+        .and_hms_opt(field(time, 0..2)?, field(time, 2..4)?, field(time, 4..6)?)?;
+    Some(timezone.from_local_datetime(&naive).earliest()?.timestamp())
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Sends files that are about to be overwritten or deleted to the trash first, for --use-trash.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether displaced files go to the trash rather than being lost.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Moves a file to the trash of the desktop, where it can be restored from until the trash is
+/// emptied: the freedesktop.org trash on Linux and BSD, the Finder's on macOS and the recycle bin
+/// on Windows.
+pub fn put(path: &Path) -> io::Result<()> {
+    let path = std::path::absolute(path)?;
+    platform::put(&path)
+}
+
+// The first name in `dir` that isn't taken, counting up like "IMG_1234_2.jpg"
+#[cfg(unix)]
+fn free_name(dir: &Path, path: &Path, taken: impl Fn(&str) -> bool) -> String {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    std::iter::once(format!("{}{}", stem, extension))
+        .chain((2..).map(|n| format!("{}_{}{}", stem, n, extension)))
+        .find(|name| !dir.join(name).exists() && !taken(name))
+        .expect("there is always a free name")
+}
+
+// The trash may be on another filesystem, where a plain rename isn't possible
+#[cfg(unix)]
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::{free_name, move_file};
+    use std::fs;
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    // See https://specifications.freedesktop.org/trash-spec/latest/
+    pub fn put(path: &Path) -> io::Result<()> {
+        let trash = dirs::data_dir().ok_or_else(|| io::Error::other("no home directory to find the trash in"))?.join("Trash");
+        let (files, info) = (trash.join("files"), trash.join("info"));
+        fs::create_dir_all(&files)?;
+        fs::create_dir_all(&info)?;
+
+        // The info file claims the name, so it's written first
+        let name = free_name(&files, path, |name| info.join(format!("{}.trashinfo", name)).exists());
+        let info_path = info.join(format!("{}.trashinfo", name));
+        let mut info_file = fs::OpenOptions::new().write(true).create_new(true).open(&info_path)?;
+        write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode(&path.to_string_lossy()),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        )?;
+
+        move_file(path, &files.join(&name)).inspect_err(|_| {
+            let _ = fs::remove_file(&info_path);
+        })
+    }
+
+    fn encode(path: &str) -> String {
+        path.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+                b => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{free_name, move_file};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    pub fn put(path: &Path) -> io::Result<()> {
+        let trash = dirs::home_dir().ok_or_else(|| io::Error::other("no home directory to find the trash in"))?.join(".Trash");
+        fs::create_dir_all(&trash)?;
+        let name = free_name(&trash, path, |_| false);
+        move_file(path, &trash.join(name))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use std::path::Path;
+    use std::process::Command;
+
+    // The recycle bin is only reachable through the shell, which Visual Basic's file API wraps
+    pub fn put(path: &Path) -> io::Result<()> {
+        let path = path.to_string_lossy().replace('\'', "''");
+        let script = format!(
+            "Add-Type -AssemblyName Microsoft.VisualBasic; \
+             [Microsoft.VisualBasic.FileIO.FileSystem]::DeleteFile('{}', 'OnlyErrorDialogs', 'SendToRecycleBin')",
+            path
+        );
+        let output = Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", &script]).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(())
+    }
+}
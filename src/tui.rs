@@ -0,0 +1,301 @@
+use crate::interrupt;
+use crate::logging;
+use crate::scan::FileGroup;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::Show;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Gauge, Paragraph, Row, Table, TableState};
+use ratatui::Terminal;
+use std::io::{self, IsTerminal, Stderr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Whether the terminal is in raw mode on the alternate screen and has to be put back
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// A planned rename as shown for review, one for each group of files.
+#[derive(Debug, Clone)]
+pub struct ReviewRow {
+    pub group: FileGroup,
+    /// Where the primary file goes
+    pub target: PathBuf,
+    /// Capture day, as yyyyMMdd
+    pub day: String,
+    pub location: String,
+    pub skipped: bool,
+    /// Name typed during review, without the extension
+    pub edited: Option<String>,
+}
+
+impl ReviewRow {
+    pub fn new(group: FileGroup, target: PathBuf, day: &str, location: &str) -> ReviewRow {
+        ReviewRow { group, target, day: day.to_string(), location: location.to_string(), skipped: false, edited: None }
+    }
+
+    fn old_name(&self) -> String {
+        self.group.primary().file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    fn proposed_stem(&self) -> String {
+        self.target.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    fn new_name(&self) -> String {
+        match (&self.edited, self.target.extension()) {
+            (Some(stem), Some(extension)) => format!("{}.{}", stem, extension.to_string_lossy()),
+            (Some(stem), None) => stem.clone(),
+            (None, _) => self.target.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// What to do with the reviewed renames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Carry out the accepted renames
+    Apply,
+    /// Stop without renaming the files under review
+    Cancel,
+}
+
+// A line of the review table: the heading of a day, or a rename
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    Day(usize),
+    Rename(usize),
+}
+
+enum Mode {
+    Browse,
+    Edit(String),
+    Confirm(Outcome),
+}
+
+/// A full-screen view on stderr for following a run and reviewing its renames, for imports too
+/// large to follow in the scrolling log. Log lines are held back while it's up and written out
+/// once it closes.
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stderr>>,
+}
+
+impl Tui {
+    pub fn start() -> io::Result<Tui> {
+        if !io::stderr().is_terminal() {
+            return Err(io::Error::other("--tui needs a terminal"));
+        }
+        enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        logging::hold();
+        let terminal = execute!(io::stderr(), EnterAlternateScreen).and_then(|_| Terminal::new(CrosstermBackend::new(io::stderr())));
+        match terminal {
+            Ok(terminal) => Ok(Tui { terminal }),
+            Err(e) => {
+                restore();
+                Err(e)
+            }
+        }
+    }
+
+    /// Shows how far along a step is, such as geocoding a chunk. Ctrl-C and q ask the run to
+    /// stop, since the terminal doesn't turn Ctrl-C into a signal while the view is up.
+    pub fn progress(&mut self, title: &str, label: &str, done: usize, total: usize) -> io::Result<()> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? && key.kind == KeyEventKind::Press && (is_ctrl_c(key) || key.code == KeyCode::Char('q')) {
+                interrupt::request();
+            }
+        }
+
+        let ratio = if total == 0 { 1.0 } else { (done as f64 / total as f64).min(1.0) };
+        let hint = if interrupt::requested() {
+            "Stopping, the run picks up again with --resume"
+        } else {
+            "Ctrl-C or q to stop"
+        };
+        self.terminal.draw(|frame| {
+            let [_, gauge, hint_area, _] = Layout::vertical([Constraint::Fill(1), Constraint::Length(3), Constraint::Length(1), Constraint::Fill(1)]).areas(frame.area());
+            let gauge_widget = Gauge::default()
+                .block(Block::bordered().title(format!(" {} ", title)))
+                .gauge_style(Style::new().fg(Color::Green))
+                .ratio(ratio)
+                .label(format!("{}/{} {}", done, total, label));
+            frame.render_widget(gauge_widget, gauge);
+            frame.render_widget(Paragraph::new(hint).centered().style(Style::new().fg(Color::DarkGray)), hint_area);
+        })?;
+        Ok(())
+    }
+
+    /// Shows the renames in a table grouped by capture day, where each rename or whole day can be
+    /// accepted, skipped or given another name, until they're applied or the review is cancelled.
+    pub fn review(&mut self, title: &str, rows: &mut [ReviewRow]) -> io::Result<Outcome> {
+        // Days in order, each with its renames in the order the files were taken
+        let mut order = (0..rows.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| rows[a].day.cmp(&rows[b].day));
+        let mut days: Vec<Vec<usize>> = Vec::new();
+        let mut entries = Vec::new();
+        for index in order {
+            if days.last().is_none_or(|day| rows[day[0]].day != rows[index].day) {
+                entries.push(Entry::Day(days.len()));
+                days.push(Vec::new());
+            }
+            days.last_mut().expect("a day was just added").push(index);
+            entries.push(Entry::Rename(index));
+        }
+
+        let mut state = TableState::default().with_selected(Some(entries.len().min(1)));
+        let mut mode = Mode::Browse;
+        let mut page = 10;
+        loop {
+            self.terminal.draw(|frame| {
+                let [header_area, table_area, footer_area] = Layout::vertical([Constraint::Length(1), Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+                page = table_area.height.saturating_sub(2).max(1) as usize;
+
+                let skipped = rows.iter().filter(|row| row.skipped).count();
+                let edited = rows.iter().filter(|row| !row.skipped && row.edited.is_some()).count();
+                let header = format!(" {}: {} to rename, {} skipped, {} named by hand", title, rows.len() - skipped, skipped, edited);
+                frame.render_widget(Paragraph::new(header).style(Style::new().add_modifier(Modifier::BOLD)), header_area);
+
+                let table_rows = entries.iter().map(|entry| match *entry {
+                    Entry::Day(day) => {
+                        let skipped = days[day].iter().filter(|&&index| rows[index].skipped).count();
+                        let counts = format!("{} files, {} skipped", days[day].len(), skipped);
+                        Row::new([Cell::from(""), Cell::from(format_day(&rows[days[day][0]].day)), Cell::from(counts)]).style(Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                    }
+                    Entry::Rename(index) => {
+                        let row = &rows[index];
+                        let (mark, style) = match (row.skipped, &row.edited) {
+                            (true, _) => ("✗", Style::new().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT)),
+                            (false, Some(_)) => ("✎", Style::new().fg(Color::Yellow)),
+                            (false, None) => ("✓", Style::new()),
+                        };
+                        Row::new([Cell::from(mark), Cell::from(row.old_name()), Cell::from(row.new_name()), Cell::from(row.location.clone())]).style(style)
+                    }
+                });
+                let table = Table::new(table_rows, [Constraint::Length(1), Constraint::Fill(2), Constraint::Fill(3), Constraint::Fill(2)])
+                    .header(Row::new(["", "File", "New name", "Location"]).style(Style::new().add_modifier(Modifier::UNDERLINED)))
+                    .block(Block::bordered())
+                    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+                frame.render_stateful_widget(table, table_area, &mut state);
+
+                let footer = match &mode {
+                    Mode::Browse => Line::from(" ↑↓ move  [ ] day  space toggle  a accept  s skip  e edit name  enter apply  q quit"),
+                    Mode::Edit(text) => Line::from(format!(" New name: {}_   enter keep, empty for the proposed name, esc cancel", text)),
+                    Mode::Confirm(Outcome::Apply) => {
+                        let skipped = rows.iter().filter(|row| row.skipped).count();
+                        Line::from(format!(" Rename {} files and skip {}? y/n", rows.len() - skipped, skipped))
+                    }
+                    Mode::Confirm(Outcome::Cancel) => Line::from(" Stop without renaming these files? y/n"),
+                };
+                frame.render_widget(Paragraph::new(footer).style(Style::new().fg(Color::Black).bg(Color::Gray)), footer_area);
+            })?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let selected = state.selected().and_then(|selected| entries.get(selected).copied());
+            // The renames the selection stands for, all of a day's on its heading
+            let selection = match selected {
+                Some(Entry::Day(day)) => days[day].clone(),
+                Some(Entry::Rename(index)) => vec![index],
+                None => Vec::new(),
+            };
+
+            match &mut mode {
+                Mode::Edit(text) => match key.code {
+                    KeyCode::Enter => {
+                        if let Some(Entry::Rename(index)) = selected {
+                            let text = text.trim();
+                            rows[index].edited = Some(text.to_string()).filter(|text| !text.is_empty() && *text != rows[index].proposed_stem());
+                        }
+                        mode = Mode::Browse;
+                    }
+                    KeyCode::Esc => mode = Mode::Browse,
+                    KeyCode::Backspace => {
+                        text.pop();
+                    }
+                    _ if is_ctrl_c(key) => mode = Mode::Browse,
+                    KeyCode::Char(c) => text.push(c),
+                    _ => {}
+                },
+                Mode::Confirm(outcome) => match key.code {
+                    KeyCode::Char('y') | KeyCode::Enter => return Ok(*outcome),
+                    _ => mode = Mode::Browse,
+                },
+                Mode::Browse => match key.code {
+                    _ if is_ctrl_c(key) => return Ok(Outcome::Cancel),
+                    KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                    KeyCode::Down | KeyCode::Char('j') => state.select_next(),
+                    KeyCode::PageUp => state.scroll_up_by(page as u16),
+                    KeyCode::PageDown => state.scroll_down_by(page as u16),
+                    KeyCode::Home => state.select_first(),
+                    KeyCode::End => state.select_last(),
+                    KeyCode::Char(']') => {
+                        let current = state.selected().unwrap_or(0);
+                        if let Some(next) = (current + 1..entries.len()).find(|&index| matches!(entries[index], Entry::Day(_))) {
+                            state.select(Some(next));
+                        }
+                    }
+                    KeyCode::Char('[') => {
+                        let current = state.selected().unwrap_or(0);
+                        if let Some(previous) = (0..current).rev().find(|&index| matches!(entries[index], Entry::Day(_))) {
+                            state.select(Some(previous));
+                        }
+                    }
+                    KeyCode::Char(' ') => {
+                        // A day with anything still accepted is skipped as a whole
+                        let skip = selection.iter().any(|&index| !rows[index].skipped);
+                        selection.iter().for_each(|&index| rows[index].skipped = skip);
+                    }
+                    KeyCode::Char('a') => selection.iter().for_each(|&index| rows[index].skipped = false),
+                    KeyCode::Char('s') => selection.iter().for_each(|&index| rows[index].skipped = true),
+                    KeyCode::Char('e') => {
+                        if let Some(Entry::Rename(index)) = selected {
+                            let row = &rows[index];
+                            mode = Mode::Edit(row.edited.clone().unwrap_or_else(|| row.proposed_stem()));
+                        }
+                    }
+                    KeyCode::Enter => mode = Mode::Confirm(Outcome::Apply),
+                    KeyCode::Char('q') | KeyCode::Esc => mode = Mode::Confirm(Outcome::Cancel),
+                    _ => {}
+                },
+            }
+        }
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Puts the terminal back the way it was and writes out the log lines held back meanwhile. Does
+/// nothing without a view up, so it's safe to call right before exiting.
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        // There is nowhere left to report a terminal that can't be put back
+        let _ = execute!(io::stderr(), LeaveAlternateScreen, Show);
+        let _ = disable_raw_mode();
+    }
+    logging::release();
+}
+
+fn is_ctrl_c(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+// 20230601 as 2023-06-01
+fn format_day(day: &str) -> String {
+    match (day.get(0..4), day.get(4..6), day.get(6..8)) {
+        (Some(year), Some(month), Some(day)) => format!("{}-{}-{}", year, month, day),
+        _ => day.to_string(),
+    }
+}
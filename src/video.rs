@@ -0,0 +1,204 @@
+use crate::datetime::{format_unix_time, unix_time};
+use crate::metadata::{capture_timestamp, fallback_date, iso_8601_utc, DateFallback, MetadataOptions, MissingMetadata, PhotoMetadata, PositionSource, RecordedMetadata};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Seconds between the QuickTime epoch (1904-01-01) and the Unix epoch
+const QUICKTIME_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+// The moov atom only holds metadata and sample tables, so anything larger is not worth reading
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The position and creation time a QuickTime/MP4 file records, without a track or fallback date.
+pub fn recorded_metadata(path: &Path) -> RecordedMetadata {
+    let Some(moov) = fs::File::open(path).ok().and_then(|mut file| read_top_level_atom(&mut file, b"moov")) else {
+        return RecordedMetadata::default();
+    };
+    let position = embedded_position(&moov);
+    // Videos only record their creation time in UTC, which is what they're dated by as well
+    let created = unix_creation_time(&moov).and_then(|created| iso_8601_utc(created as i64));
+    RecordedMetadata {
+        lat: position.map(|(lat, _, _)| lat),
+        lon: position.map(|(_, lon, _)| lon),
+        altitude: position.and_then(|(_, _, altitude)| altitude),
+        taken: created.as_deref().map(|created| created.trim_end_matches('Z').to_string()),
+        taken_utc: created,
+        ..RecordedMetadata::default()
+    }
+}
+
+// The creation time from the `mvhd` atom in seconds since the Unix epoch
+fn unix_creation_time(moov: &[u8]) -> Option<u64> {
+    find_atom(moov, b"mvhd")
+        .and_then(creation_time)
+        .and_then(|created| created.checked_sub(QUICKTIME_EPOCH_OFFSET))
+}
+
+/// Reads the GPS position from the `©xyz` atom and the capture date from the `mvhd` atom of a
+/// QuickTime/MP4 file. Videos without a position are placed on the track, if one is given.
+pub fn extract_metadata(path: &Path, options: &MetadataOptions) -> Result<PhotoMetadata, MissingMetadata> {
+    // A file that isn't a readable QuickTime file has no capture date either
+    let mut file = fs::File::open(path).map_err(|_| MissingMetadata::Date)?;
+    let moov = read_top_level_atom(&mut file, b"moov").ok_or(MissingMetadata::Date)?;
+
+    // The creation time is in UTC, so it can be matched against a track as is
+    let (date, time, recorded_utc) = match unix_creation_time(&moov) {
+        Some(created) => {
+            // Videos don't name the camera, so only the correction for every file applies
+            let created = created.saturating_add_signed(options.clock_offsets.map_or(0, |offsets| offsets.all));
+            let (date, time) = format_unix_time(created);
+            (date, Some(time), Some(created as i64))
+        }
+        None => {
+            let (date, time) = fallback_date(path, options.date_fallback).ok_or(MissingMetadata::Date)?;
+            let recorded_utc = match (options.date_fallback, time.as_deref()) {
+                (DateFallback::Mtime, Some(time)) => unix_time(&date, time),
+                _ => None,
+            };
+            (date, time, recorded_utc)
+        }
+    };
+    let (timestamp, timestamp_is_utc) = capture_timestamp(&date, time.as_deref(), recorded_utc, options);
+    let timestamp = timestamp.map(|seconds| seconds as f64);
+
+    let (lat, lon, position_source, altitude) = match (options.position, embedded_position(&moov)) {
+        (Some((lat, lon)), _) => (lat, lon, PositionSource::Override, None),
+        (None, Some((lat, lon, altitude))) => (lat, lon, PositionSource::Embedded, altitude),
+        (None, None) => {
+            let (track, timestamp) = options.track.zip(timestamp).ok_or(MissingMetadata::Position)?;
+            let (lat, lon) = track.position_at(timestamp).ok_or(MissingMetadata::Position)?;
+            (lat, lon, track.source(), None)
+        }
+    };
+
+    Ok(PhotoMetadata {
+        lat,
+        lon,
+        position_source,
+        altitude,
+        timestamp,
+        timestamp_is_utc,
+        date,
+        time,
+        subsec: None,
+        camera: None,
+        make: None,
+        lens: None,
+    })
+}
+
+fn embedded_position(moov: &[u8]) -> Option<(f64, f64, Option<f64>)> {
+    let udta = find_atom(moov, b"udta")?;
+    let xyz = find_atom(udta, b"\xa9xyz")?;
+    // 16-bit string length and 16-bit language code precede the ISO 6709 string
+    let length = u16::from_be_bytes([*xyz.first()?, *xyz.get(1)?]) as usize;
+    let location = std::str::from_utf8(xyz.get(4..4 + length)?).ok()?;
+    parse_iso6709(location)
+}
+
+fn read_top_level_atom(file: &mut fs::File, kind: &[u8; 4]) -> Option<Vec<u8>> {
+    let file_len = file.metadata().ok()?.len();
+    let mut offset = 0;
+
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).ok()?;
+
+        let mut size = u32::from_be_bytes(header[0..4].try_into().ok()?) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            file.read_exact(&mut large).ok()?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = file_len - offset;
+        }
+
+        if size < header_len {
+            return None;
+        }
+
+        if &header[4..8] == kind {
+            let body_len = size - header_len;
+            if body_len > MAX_MOOV_SIZE {
+                return None;
+            }
+            let mut body = vec![0u8; body_len as usize];
+            file.read_exact(&mut body).ok()?;
+            return Some(body);
+        }
+
+        offset += size;
+    }
+
+    None
+}
+
+// Returns the body of the first child atom of the given type
+fn find_atom<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 0;
+
+    while offset + 8 <= data.len() {
+        let mut size = u32::from_be_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+        let mut header_len = 8;
+        if size == 1 {
+            size = u64::from_be_bytes(data.get(offset + 8..offset + 16)?.try_into().ok()?) as usize;
+            header_len = 16;
+        } else if size == 0 {
+            size = data.len() - offset;
+        }
+
+        if size < header_len || offset + size > data.len() {
+            return None;
+        }
+
+        if &data[offset + 4..offset + 8] == kind {
+            return Some(&data[offset + header_len..offset + size]);
+        }
+
+        offset += size;
+    }
+
+    None
+}
+
+fn creation_time(mvhd: &[u8]) -> Option<u64> {
+    let seconds = match mvhd.first()? {
+        0 => u32::from_be_bytes(mvhd.get(4..8)?.try_into().ok()?) as u64,
+        1 => u64::from_be_bytes(mvhd.get(4..12)?.try_into().ok()?),
+        _ => return None,
+    };
+
+    // Many cameras leave the field zeroed when their clock isn't set
+    if seconds == 0 { None } else { Some(seconds) }
+}
+
+// Parses the latitude, longitude and altitude, if given, out of an ISO 6709 string such as
+// "+52.3702+004.8952+002.1/"
+fn parse_iso6709(value: &str) -> Option<(f64, f64, Option<f64>)> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+
+    for c in value.trim().trim_end_matches('/').chars() {
+        if (c == '+' || c == '-') && !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    let lat = parts.first()?.parse::<f64>().ok()?;
+    let lon = parts.get(1)?.parse::<f64>().ok()?;
+    let altitude = parts.get(2).and_then(|altitude| altitude.parse::<f64>().ok());
+
+    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+        Some((lat, lon, altitude))
+    } else {
+        None
+    }
+}
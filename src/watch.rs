@@ -0,0 +1,79 @@
+use crate::interrupt;
+use crate::paths;
+use crate::scan::is_media;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+// How often to check for Ctrl-C while no events come in
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Picks up the photos and videos that appear in a directory and hands them out once they've
+/// stopped changing for `quiet_period`, so files that are still being synced or copied in aren't
+/// read halfway.
+pub struct DirectoryWatcher {
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    quiet_period: Duration,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl DirectoryWatcher {
+    pub fn new(dir: &Path, recursive: bool, quiet_period: Duration) -> notify::Result<DirectoryWatcher> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(dir, mode)?;
+        Ok(DirectoryWatcher { _watcher: watcher, events, quiet_period, pending: HashMap::new() })
+    }
+
+    /// Waits until at least one new file has settled and returns every file that has, as
+    /// canonical paths. Returns None once the user presses Ctrl-C.
+    pub fn next_batch(&mut self) -> notify::Result<Option<HashSet<PathBuf>>> {
+        loop {
+            if interrupt::requested() {
+                return Ok(None);
+            }
+
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(event) => self.add(event?),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+
+            let now = Instant::now();
+            let settled = self.pending.iter()
+                .filter(|(_, changed)| now.duration_since(**changed) >= self.quiet_period)
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>();
+            let batch = settled.into_iter()
+                .filter_map(|path| {
+                    self.pending.remove(&path);
+                    paths::canonicalize(&path).ok()
+                })
+                .collect::<HashSet<_>>();
+
+            if !batch.is_empty() {
+                return Ok(Some(batch));
+            }
+        }
+    }
+
+    fn add(&mut self, event: Event) {
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+
+        // Hidden files include the partial copies made by --organize copy
+        let is_media = |path: &Path| {
+            let hidden = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.'));
+            !hidden && is_media(path)
+        };
+        for path in event.paths.into_iter().filter(|path| is_media(path)) {
+            self.pending.insert(path, Instant::now());
+        }
+    }
+}
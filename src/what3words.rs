@@ -0,0 +1,40 @@
+use crate::geocoder::{request_error, send, GeocodeError, NetworkOptions};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const API_URL: &str = "https://api.what3words.com/v3/convert-to-3wa";
+
+/// Looks up the what3words address of a position, e.g. "filled.count.soap", for {w3w}.
+pub struct What3Words {
+    client: reqwest::Client,
+    api_key: String,
+    language: String,
+    /// Addresses already looked up this run, by position rounded to the 3 meter squares
+    known: HashMap<(i64, i64), String>,
+}
+
+#[derive(Deserialize)]
+struct ConvertResponse {
+    words: String,
+}
+
+impl What3Words {
+    pub fn new(api_key: String, language: String, network: &NetworkOptions) -> Result<What3Words, GeocodeError> {
+        Ok(What3Words { client: network.client()?, api_key, language, known: HashMap::new() })
+    }
+
+    pub async fn words(&mut self, lat: f64, lon: f64) -> Result<String, GeocodeError> {
+        let key = ((lat * 1e5).round() as i64, (lon * 1e5).round() as i64);
+        if let Some(words) = self.known.get(&key) {
+            return Ok(words.clone());
+        }
+
+        // The key goes in a header so it doesn't end up in error messages with the URL
+        let request = self.client.get(API_URL)
+            .query(&[("coordinates", format!("{},{}", lat, lon)), ("language", self.language.clone())])
+            .header("X-Api-Key", &self.api_key);
+        let response = send(request).await?.json::<ConvertResponse>().await.map_err(request_error)?;
+        self.known.insert(key, response.words.clone());
+        Ok(response.words)
+    }
+}
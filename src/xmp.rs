@@ -0,0 +1,217 @@
+use crate::format::is_jpeg;
+use crate::geocoder::GeocodeResponse;
+use crate::jpeg::{self, replace_file, segments};
+use crate::label::town_or_city;
+use clap::ValueEnum;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+// Marks the rdf:Description written by this tool, so a later run replaces it instead of adding
+// a second copy, and everything else in the packet is left alone
+const MARKER_NAMESPACE: &str = "https://github.com/Vannevelj/image-labeler/ns/1.0/";
+
+/// Where `--write-metadata` puts the resolved location.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataTarget {
+    /// Inside JPEG files; other formats get a sidecar
+    Embedded,
+    /// In an XMP sidecar next to every file
+    Sidecar,
+}
+
+/// Properties written into XMP, as understood by Lightroom, Photos and Darktable.
+#[derive(Debug, Clone, Default)]
+pub struct XmpProperties {
+    pub city: Option<String>,
+    pub road: Option<String>,
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+    /// Capture time in ISO 8601, e.g. "2023-10-24T12:00:00"
+    pub date_created: Option<String>,
+    pub title: Option<String>,
+    /// Name the file had before it was first labeled
+    pub preserved_file_name: Option<String>,
+    /// What the photo shows, e.g. "beach", from the content classifier
+    pub tags: Vec<String>,
+}
+
+impl From<&GeocodeResponse> for XmpProperties {
+    fn from(response: &GeocodeResponse) -> Self {
+        let address = &response.address;
+        XmpProperties {
+            city: town_or_city(response).map(str::to_string),
+            road: address.road.clone(),
+            country: address.country.clone(),
+            country_code: address.country_code.as_ref().map(|code| code.to_uppercase()),
+            date_created: None,
+            title: None,
+            preserved_file_name: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl XmpProperties {
+    // City and country double as keywords, since that's what most catalogs let you search on
+    fn keywords(&self) -> Vec<&str> {
+        [self.city.as_deref(), self.country.as_deref()].into_iter().flatten().chain(self.tags.iter().map(String::as_str)).collect()
+    }
+
+    fn description(&self) -> String {
+        let mut xml = format!(
+            "  <rdf:Description rdf:about=\"\"\n    xmlns:image-labeler=\"{}\"\n    xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"\n    xmlns:Iptc4xmpCore=\"http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/\"\n    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n    xmlns:xmpMM=\"http://ns.adobe.com/xap/1.0/mm/\">\n",
+            MARKER_NAMESPACE
+        );
+
+        let mut property = |name: &str, value: Option<&str>| {
+            if let Some(value) = value {
+                xml.push_str(&format!("   <{}>{}</{}>\n", name, escape(value), name));
+            }
+        };
+        property("photoshop:City", self.city.as_deref());
+        property("photoshop:Country", self.country.as_deref());
+        property("Iptc4xmpCore:CountryCode", self.country_code.as_deref());
+        property("Iptc4xmpCore:Location", self.road.as_deref());
+        property("photoshop:DateCreated", self.date_created.as_deref());
+        property("xmpMM:PreservedFileName", self.preserved_file_name.as_deref());
+
+        if let Some(title) = &self.title {
+            xml.push_str(&format!(
+                "   <dc:title>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n   </dc:title>\n",
+                escape(title)
+            ));
+        }
+
+        let keywords = self.keywords();
+        if !keywords.is_empty() {
+            xml.push_str("   <dc:subject>\n    <rdf:Bag>\n");
+            for keyword in keywords {
+                xml.push_str(&format!("     <rdf:li>{}</rdf:li>\n", escape(keyword)));
+            }
+            xml.push_str("    </rdf:Bag>\n   </dc:subject>\n");
+        }
+
+        xml.push_str("  </rdf:Description>\n");
+        xml
+    }
+}
+
+/// Writes the properties into the file itself or into its sidecar, depending on `target` and the
+/// file's format. Returns where it was written.
+pub fn write_properties(path: &Path, properties: &XmpProperties, target: MetadataTarget) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    if target == MetadataTarget::Embedded && is_jpeg(path) {
+        write_embedded(path, properties)?;
+        Ok(path.to_path_buf())
+    } else {
+        write_sidecar(path, properties)
+    }
+}
+
+/// Writes the properties into `<name>.xmp` next to the file, merging them into an existing sidecar.
+pub fn write_sidecar(path: &Path, properties: &XmpProperties) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let sidecar = sidecar_path(path);
+    let existing = match fs::read_to_string(&sidecar) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    replace_file(&sidecar, merge_packet(existing.as_deref(), &properties.description())?.as_bytes())?;
+    Ok(sidecar)
+}
+
+/// The `xmpMM:PreservedFileName` recorded in the file's embedded XMP or in its sidecar.
+pub fn preserved_file_name(path: &Path) -> Option<String> {
+    let embedded = is_jpeg(path)
+        .then(|| fs::read(path).ok())
+        .flatten()
+        .and_then(|data| {
+            let segments = segments(&data).ok()?;
+            let segment = segments.iter().find(|s| s.marker == 0xE1 && data[s.payload.clone()].starts_with(XMP_HEADER))?;
+            let packet = std::str::from_utf8(&data[segment.payload.start + XMP_HEADER.len()..segment.payload.end]).ok()?;
+            find_property(packet, "xmpMM:PreservedFileName")
+        });
+    embedded.or_else(|| {
+        let packet = fs::read_to_string(sidecar_path(path)).ok()?;
+        find_property(&packet, "xmpMM:PreservedFileName")
+    })
+}
+
+// Finds a simple property written either as an element or as an attribute of rdf:Description
+fn find_property(packet: &str, name: &str) -> Option<String> {
+    let element = format!("<{}>", name);
+    let attribute = format!("{}=\"", name);
+    let value = if let Some(start) = packet.find(&element).map(|i| i + element.len()) {
+        &packet[start..start + packet[start..].find('<')?]
+    } else {
+        let start = packet.find(&attribute)? + attribute.len();
+        &packet[start..start + packet[start..].find('"')?]
+    };
+    Some(unescape(value.trim()))
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension("xmp")
+}
+
+/// Writes the properties into the XMP packet embedded in a JPEG file.
+pub fn write_embedded(path: &Path, properties: &XmpProperties) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let data = fs::read(path)?;
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("only JPEG files are supported".into());
+    }
+
+    let segments = segments(&data)?;
+    let existing = segments.iter().find(|s| s.marker == 0xE1 && data[s.payload.clone()].starts_with(XMP_HEADER));
+    let existing_packet = existing
+        .map(|s| std::str::from_utf8(&data[s.payload.start + XMP_HEADER.len()..s.payload.end]))
+        .transpose()
+        .map_err(|_| "existing XMP packet is not valid UTF-8")?;
+
+    let packet = merge_packet(existing_packet, &properties.description())?;
+    if XMP_HEADER.len() + packet.len() > jpeg::MAX_SEGMENT_LEN {
+        return Err("XMP packet is too large to fit in a JPEG segment".into());
+    }
+
+    let segment = jpeg::app1_segment(XMP_HEADER, packet.as_bytes());
+    replace_file(path, &jpeg::splice(&data, &segments, existing, &segment))?;
+    Ok(())
+}
+
+// Adds our description to an existing packet, replacing the one from a previous run, or wraps it
+// in a new packet
+fn merge_packet(existing: Option<&str>, description: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let Some(existing) = existing else {
+        return Ok(format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n{} </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n",
+            description
+        ));
+    };
+
+    let mut packet = existing.to_string();
+    if let Some(marker) = packet.find(MARKER_NAMESPACE) {
+        let start = packet[..marker].rfind("<rdf:Description").ok_or("malformed XMP packet")?;
+        let end = packet[marker..].find("</rdf:Description>").ok_or("malformed XMP packet")? + marker + "</rdf:Description>".len();
+        // Take the line break that followed the old description with it
+        let end = if packet[end..].starts_with('\n') { end + 1 } else { end };
+        let start = packet[..start].rfind('\n').map(|i| i + 1).filter(|&i| packet[i..start].trim().is_empty()).unwrap_or(start);
+        packet.replace_range(start..end, "");
+    }
+
+    let close = packet.rfind("</rdf:RDF>").ok_or("existing XMP packet has no rdf:RDF element")?;
+    let close = packet[..close].rfind('\n').map(|i| i + 1).filter(|&i| packet[i..close].trim().is_empty()).unwrap_or(close);
+    packet.insert_str(close, description);
+    Ok(packet)
+}
+
+/// Escapes text for use in XML content and attribute values.
+pub fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("&quot;", "\"").replace("&gt;", ">").replace("&lt;", "<").replace("&apos;", "'").replace("&amp;", "&")
+}